@@ -1,6 +1,31 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use crate::render::aov::Aov;
+use crate::render::crop::CropWindow;
+use crate::render::tonemap::ToneMap;
+
+/// Renders a scene by default, other tasks are subcommands
+#[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub render: Option<CliArgs>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Write the triangle mesh of a scene object to an OBJ, SMF or PLY file
+    ExportMesh(ExportMeshArgs),
+    /// Write a scene with all defaults filled in and its materials inlined
+    Normalize(NormalizeArgs),
+    /// Print the render settings stored in a PNG or EXR image
+    Info(InfoArgs),
+}
 
 #[derive(Default, Debug, Parser)]
 pub struct CliArgs {
@@ -8,6 +33,153 @@ pub struct CliArgs {
     pub scene: PathBuf,
     #[arg(short, long)]
     pub image: Option<PathBuf>,
-    #[arg(short = 'S', long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..3))]
+    /// Image format, chosen by the image extension if not given [default: png]
+    #[arg(short, long, value_enum)]
+    pub format: Option<ImageFormat>,
+    /// JPEG quality [default: scene output or 90]
+    #[arg(short, long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: Option<u8>,
+    /// Depth of adaptive anti-aliasing, which a progressive render doesn't use
+    #[arg(short = 'S', long, default_value_t = 2, conflicts_with = "progressive", value_parser = clap::value_parser!(u8).range(0..3))]
     pub sampling_depth: u8,
+    /// Image width, replacing the scene's. Keeps the aspect ratio if the height isn't given
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: Option<u32>,
+    /// Image height, replacing the scene's. Keeps the aspect ratio if the width isn't given
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: Option<u32>,
+    /// Scale the resolution, e.g. 0.25 for a quick preview
+    #[arg(long, value_parser = parse_positive)]
+    pub scale: Option<f32>,
+    /// Render in this many passes of one sample per pixel instead of adaptively, writing
+    /// the image as it refines
+    #[arg(long, value_name = "PASSES", value_parser = clap::value_parser!(u32).range(1..))]
+    pub progressive: Option<u32>,
+    /// Seconds between writes of a progressive render [default: 10 without --write-passes]
+    #[arg(long, value_name = "SECONDS", requires = "progressive", value_parser = parse_positive)]
+    pub write_interval: Option<f32>,
+    /// Write a progressive render after every this many passes
+    #[arg(long, value_name = "PASSES", requires = "progressive", value_parser = clap::value_parser!(u32).range(1..))]
+    pub write_passes: Option<u32>,
+    /// Exposure adjustment in stops [default: scene output or 0]
+    #[arg(long, allow_negative_numbers = true)]
+    pub exposure: Option<f32>,
+    /// Tone mapping operator for 8-bit output [default: scene output or clamp]
+    #[arg(long, value_enum)]
+    pub tone_map: Option<ToneMap>,
+    /// Linear value mapped to white by extended-reinhard and hable
+    #[arg(long)]
+    pub white_point: Option<f32>,
+    /// Sample type of the channels in .exr output [default: scene output or half]
+    #[arg(long, value_enum)]
+    pub exr_sample: Option<ExrSample>,
+    /// Auxiliary images to render, replacing those of the scene output
+    #[arg(long = "aov", value_enum, value_delimiter = ',')]
+    pub aovs: Vec<Aov>,
+    /// Alpha channel for PNG and EXR output [default: scene output or off]
+    #[arg(long, value_enum)]
+    pub alpha: Option<AlphaMode>,
+    /// Keep the background colour behind transparent pixels instead of black
+    #[arg(long)]
+    pub keep_background: bool,
+    /// Render only the pixels x0,y0 up to x1,y1, replacing the scene's crop
+    #[arg(long, value_name = "X0,Y0,X1,Y1")]
+    pub crop: Option<CropWindow>,
+    /// Write a cropped render at full size with the rest of the image empty
+    #[arg(long)]
+    pub crop_full_size: bool,
+    /// Write a cropped render into the existing image instead of replacing it
+    #[arg(long, requires = "crop")]
+    pub patch: bool,
+}
+
+fn parse_positive(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(scale) if scale.is_finite() && scale > 0.0 => Ok(scale),
+        Ok(_) => Err(format!("{s} is not greater than 0")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Image formats the framebuffer can be written as
+#[derive(Default, Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+pub enum ImageFormat {
+    /// 8-bit PNG
+    #[default]
+    Png,
+    /// 16-bit PNG
+    Png16,
+    Jpeg,
+    /// Binary 8-bit PPM
+    Ppm,
+    /// Portable float map, linear
+    Pfm,
+    /// 32-bit float TIFF, linear
+    Tiff,
+    /// Lossless WebP
+    Webp,
+    /// OpenEXR, linear
+    Exr,
+    /// Radiance RGBE, linear
+    Hdr,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+pub enum ExrSample {
+    #[default]
+    Half,
+    Float,
+}
+
+/// What makes output pixels transparent
+#[derive(Default, Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+pub enum AlphaMode {
+    /// No alpha channel, misses show the background colour
+    #[default]
+    Off,
+    /// Camera rays that miss every object are transparent
+    Coverage,
+    /// Misses seen through refractive materials are partly transparent too
+    Transmission,
+}
+
+#[derive(Default, Debug, Args)]
+pub struct ExportMeshArgs {
+    #[arg(short, long)]
+    pub scene: PathBuf,
+    /// Index of the object in the scene's objects list
+    #[arg(short, long, default_value_t = 0)]
+    pub object: usize,
+    /// Output file, the format is chosen by extension (.obj, .smf or .ply)
+    #[arg(short = 'O', long)]
+    pub output: PathBuf,
+    /// Bake the object's transform into the vertices and normals
+    #[arg(short = 't', long)]
+    pub apply_transform: bool,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SceneFormat {
+    #[default]
+    Yaml,
+    Json,
+    Toml,
+}
+
+#[derive(Default, Debug, Args)]
+pub struct NormalizeArgs {
+    #[arg(short, long)]
+    pub scene: PathBuf,
+    /// Output file, stdout if not given
+    #[arg(short = 'O', long)]
+    pub output: Option<PathBuf>,
+    /// Output format, chosen by the output extension if not given (YAML for stdout)
+    #[arg(short, long, value_enum)]
+    pub format: Option<SceneFormat>,
+}
+
+#[derive(Default, Debug, Args)]
+pub struct InfoArgs {
+    /// Image written by a render
+    pub image: PathBuf,
 }
@@ -0,0 +1,75 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::args::{ExportMeshArgs, NormalizeArgs, SceneFormat};
+use crate::objects::{obj, ply, smf, Mesh};
+use crate::scene::{write_normalized_scene, Scene};
+
+/// Write a mesh to a file, choosing the format by extension
+fn save_mesh(mesh: &Mesh, path: &Path) -> io::Result<()> {
+    type Writer = fn(&Mesh, &mut BufWriter<File>) -> io::Result<()>;
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    let write: Writer = match extension.as_deref() {
+        Some("obj") => obj::write_obj,
+        Some("smf") => smf::write_smf,
+        Some("ply") => ply::write_ply,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: unknown mesh format, expected .obj, .smf or .ply",
+                    path.display()
+                ),
+            ))
+        }
+    };
+
+    let file = File::create(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("failed to create {}: {e}", path.display()),
+        )
+    })?;
+    let mut out = BufWriter::new(file);
+    write(mesh, &mut out)?;
+    out.flush()
+}
+
+/// Export the mesh of one scene object, for inspecting tessellation in other tools
+pub fn export_mesh_with_args(args: &ExportMeshArgs) -> io::Result<()> {
+    let mut scene = Scene::new(&args.scene)?;
+    let mesh = scene.object_mesh(args.object, args.apply_transform)?;
+    log::info!(
+        "writing {} vertices and {} triangles to {}",
+        mesh.vertices.len(),
+        mesh.triangles.len(),
+        args.output.display()
+    );
+    save_mesh(&mesh, &args.output)
+}
+
+/// Write the normalized form of a scene to a file or stdout
+pub fn normalize_with_args(args: &NormalizeArgs) -> io::Result<()> {
+    let format = args
+        .format
+        .or_else(|| args.output.as_deref().and_then(SceneFormat::from_path))
+        .unwrap_or_default();
+
+    match &args.output {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("failed to create {}: {e}", path.display()),
+                )
+            })?;
+            let mut out = BufWriter::new(file);
+            write_normalized_scene(&args.scene, format, &mut out)?;
+            out.flush()
+        }
+        None => write_normalized_scene(&args.scene, format, io::stdout().lock()),
+    }
+}
@@ -4,4 +4,5 @@ mod objects;
 mod scene;
 
 pub mod args;
+pub mod export;
 pub mod render;
@@ -1,17 +1,42 @@
-use std::path::PathBuf;
+use std::process;
 
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 
-use arrt::args::CliArgs;
-use arrt::render::render_with_args;
+use arrt::args::{Cli, Command};
+use arrt::export::{export_mesh_with_args, normalize_with_args};
+use arrt::render::metadata::info_with_args;
+use arrt::render::render_image_with_args;
 
 fn main() {
     env_logger::init();
-    let args = CliArgs::parse();
-    log::debug!("cli args= {args:?}");
-    let framebuf = render_with_args(&args);
-    let image = args
-        .image
-        .unwrap_or_else(|| PathBuf::from(args.scene.file_name().unwrap()).with_extension("png"));
-    framebuf.save_image(&image);
+    let cli = Cli::parse();
+    log::debug!("cli args= {cli:?}");
+
+    match cli.command {
+        Some(command) => {
+            let result = match command {
+                Command::ExportMesh(args) => export_mesh_with_args(&args),
+                Command::Normalize(args) => normalize_with_args(&args),
+                Command::Info(args) => info_with_args(&args),
+            };
+            if let Err(e) = result {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        }
+        None => {
+            let Some(args) = cli.render else {
+                Cli::command()
+                    .error(
+                        clap::error::ErrorKind::MissingRequiredArgument,
+                        "--scene <SCENE> is required to render",
+                    )
+                    .exit();
+            };
+            if let Err(e) = render_image_with_args(&args) {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        }
+    }
 }
@@ -1,10 +1,13 @@
 use std::ops::{Index, IndexMut, Mul};
 
+use serde::{Deserialize, Serialize};
+
 use super::vec3::Vec3;
 use super::vec4::Vec4;
 use super::Degree;
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Mat4 {
     dat: [f32; 16],
 }
@@ -43,6 +46,17 @@ impl Mat4 {
         Mat4 { dat: [0.0; 16] }
     }
 
+    /// Create from column major data, as used by glTF and OpenGL
+    pub fn from_cols(cols: [[f32; 4]; 4]) -> Mat4 {
+        let mut m = Mat4::zeros();
+        for (j, col) in cols.iter().enumerate() {
+            for (i, val) in col.iter().enumerate() {
+                m[i][j] = *val;
+            }
+        }
+        m
+    }
+
     pub fn translate(v: &Vec3) -> Mat4 {
         let mut m = Mat4::identity();
         m.dat[3] = v.x();
@@ -174,6 +188,59 @@ impl Mat4 {
             ],
         }
     }
+
+    /// Transform a point, including the translation
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        (self * Vec4::from_vec3(p, 1.0)).to_vec3()
+    }
+
+    /// Transform a direction or offset, ignoring the translation
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        (self * Vec4::from_vec3(v, 0.0)).to_vec3()
+    }
+}
+
+impl Mat4 {
+    /// General inverse by Gauss-Jordan elimination with partial pivoting.
+    /// Returns None if the matrix is singular.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = *self;
+        let mut inv = Mat4::identity();
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&x, &y| a[x][col].abs().total_cmp(&a[y][col].abs()))
+                .unwrap_or(col);
+
+            if a[pivot][col].abs() < f32::EPSILON {
+                return None;
+            }
+
+            for j in 0..4 {
+                a.dat.swap(col * 4 + j, pivot * 4 + j);
+                inv.dat.swap(col * 4 + j, pivot * 4 + j);
+            }
+
+            let p = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= p;
+                inv[col][j] /= p;
+            }
+
+            for row in 0..4 {
+                if row == col {
+                    continue;
+                }
+                let f = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= f * a[col][j];
+                    inv[row][j] -= f * inv[col][j];
+                }
+            }
+        }
+
+        Some(inv)
+    }
 }
 
 impl Mul<Vec4> for &Mat4 {
@@ -245,6 +312,15 @@ mod tests {
         assert!(approx(r[0], 2.0) && approx(r[1], 3.0) && approx(r[2], 4.0));
     }
 
+    #[test]
+    fn transform_vector_ignores_translation() {
+        let m = Mat4::translate(&Vec3::new(1.0, 2.0, 3.0));
+        let p = m.transform_point(Vec3::new(1.0, 0.0, 0.0));
+        let v = m.transform_vector(Vec3::new(1.0, 0.0, 0.0));
+        assert!(approx(p.x(), 2.0) && approx(p.y(), 2.0) && approx(p.z(), 3.0));
+        assert!(approx(v.x(), 1.0) && approx(v.y(), 0.0) && approx(v.z(), 0.0));
+    }
+
     #[test]
     fn scale_inverse_cancels() {
         let s = Vec3::new(2.0, 3.0, 4.0);
@@ -271,6 +347,34 @@ mod tests {
         assert!(approx(r[0], 0.0) && approx(r[1], 1.0) && approx(r[2], 0.0));
     }
 
+    #[test]
+    fn inverse_of_trs_cancels() {
+        let t = Mat4::translate(&Vec3::new(1.0, -2.0, 3.0));
+        let r = Mat4::rotate_y(Degree(30.0));
+        let s = Mat4::scale(&Vec3::new(2.0, 0.5, 4.0));
+        let m = &(&t * &r) * &s;
+        let inv = m.inverse().unwrap();
+        let p = &(&inv * &m) * Vec4::new(1.0, 2.0, 3.0, 1.0);
+        assert!(approx(p[0], 1.0) && approx(p[1], 2.0) && approx(p[2], 3.0));
+    }
+
+    #[test]
+    fn singular_matrix_has_no_inverse() {
+        assert!(Mat4::scale(&Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn from_cols_places_translation_in_last_column() {
+        let m = Mat4::from_cols([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [4.0, 5.0, 6.0, 1.0],
+        ]);
+        let r = &m * Vec4::new(0.0, 0.0, 0.0, 1.0);
+        assert!(approx(r[0], 4.0) && approx(r[1], 5.0) && approx(r[2], 6.0));
+    }
+
     #[test]
     fn transpose_of_rotation_is_inverse() {
         let rot = Mat4::rotate_z(Degree(45.0));
@@ -9,7 +9,7 @@ pub use mat3::{determinant, Mat3};
 pub use mat4::Mat4;
 pub use range::{in_range, Range};
 pub use ray::Ray;
-pub use vec3::{cross, dot, normalize, reflect, refract, Vec3};
+pub use vec3::{cross, dot, length, normalize, reflect, refract, Vec3};
 pub use vec4::Vec4;
 
 use serde::{Deserialize, Serialize};
@@ -88,6 +88,7 @@ fn interpolate(u: f32, v: f32, patch: &Patch) -> Vec3 {
 #[allow(clippy::cast_precision_loss)]
 pub fn tessellate_bpatch(dpath: &String, config: &BPatchConfig) -> Mesh {
     let mut vertices = Vec::new();
+    let mut uvs = Vec::new();
     let mut triangles = Vec::new();
     let path = Path::new(dpath).join(&config.fpath);
 
@@ -104,6 +105,7 @@ pub fn tessellate_bpatch(dpath: &String, config: &BPatchConfig) -> Mesh {
                 let v = j as f32 / config.slices as f32;
                 let point = interpolate(u, v, patch);
                 vertices.push(point);
+                uvs.push((u, v));
 
                 box_min.set_x(point.x().min(box_min.x()));
                 box_min.set_y(point.y().min(box_min.y()));
@@ -146,6 +148,8 @@ pub fn tessellate_bpatch(dpath: &String, config: &BPatchConfig) -> Mesh {
         vertices,
         triangles,
         normals,
+        uvs: Some(uvs),
+        colors: None,
         bbox,
     }
 }
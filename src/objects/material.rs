@@ -1,10 +1,9 @@
 use std::collections::HashMap;
-use std::fs::File;
-use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
 use crate::math::Vec3;
+use crate::render::color::WorkingSpace;
 use crate::render::ColorRGB;
 use crate::render::texture::{Texture, TextureConfig};
 
@@ -25,6 +24,17 @@ pub struct Material {
     pub shininess: f32,
     pub highlight: f32,
     pub texture: Option<TextureConfig>,
+    pub vertex_colors: VertexColorMode,
+    /// Shade the side facing the viewer rather than only the front of the surface
+    pub two_sided: bool,
+}
+
+/// How a mesh's per-vertex colours combine with the material's diffuse colour
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum VertexColorMode {
+    #[default]
+    Multiply,
+    Replace,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -34,6 +44,9 @@ pub struct MaterialMap {
     materials: Vec<Material>,
     name_to_id: HashMap<String, MaterialID>,
     textures: Vec<Option<Box<dyn Texture>>>,
+    working_space: WorkingSpace,
+    /// The materials before this many come from the scene, the rest were registered later
+    scene_materials: usize,
 }
 
 pub struct Surfel {
@@ -43,6 +56,9 @@ pub struct Surfel {
     pub material_id: MaterialID,
     pub n_offset: f32,
     pub uv: Option<(f32, f32)>,
+    pub color: Option<ColorRGB>,
+    /// Position in the scene's object list counting from 1, 0 when not known
+    pub object_id: u32,
 }
 
 impl Default for Material {
@@ -62,23 +78,21 @@ impl Default for Material {
             shininess: 1.0_f32,
             highlight: 0.0_f32,
             texture: None,
+            vertex_colors: VertexColorMode::Multiply,
+            two_sided: false,
         }
     }
 }
 
 impl MaterialMap {
-    pub fn new(fpath: &PathBuf) -> MaterialMap {
-        log::info!("loading materials from: {}", fpath.display());
-        let inf = File::open(fpath).unwrap();
-        let materials: Vec<Material> = serde_yml::from_reader(&inf).unwrap();
-
+    pub fn from_materials(materials: Vec<Material>, working_space: WorkingSpace) -> MaterialMap {
         for mat in &materials {
             log::debug!("{mat:?}");
         }
 
         let textures = materials
             .iter()
-            .map(|m| m.texture.as_ref().map(TextureConfig::build))
+            .map(|m| m.texture.as_ref().map(|t| t.build(working_space)))
             .collect();
 
         let mut name_to_id = HashMap::new();
@@ -86,14 +100,87 @@ impl MaterialMap {
             name_to_id.insert(mat.name.clone(), MaterialID(i));
         }
         MaterialMap {
+            scene_materials: materials.len(),
             materials,
             name_to_id,
             textures,
+            working_space,
+        }
+    }
+
+    /// The working space textures are built for
+    pub fn working_space(&self) -> WorkingSpace {
+        self.working_space
+    }
+
+    /// Add a material loaded at runtime (e.g. from a model's MTL library) and return its id.
+    /// If a material with the same name is already registered that one is kept, so scene
+    /// material libraries can override materials that ship with a model. Loaders prefix names
+    /// with their file, so any other name defined twice keeps the first with a warning.
+    pub fn register_material(&mut self, material: Material) -> MaterialID {
+        if let Some(id) = self.registered_id(&material.name) {
+            return id;
+        }
+
+        let texture = material
+            .texture
+            .as_ref()
+            .map(|t| t.build(self.working_space));
+        self.insert_material(material, texture)
+    }
+
+    /// Like `register_material`, for textures that aren't described by a `TextureConfig`
+    /// such as images embedded in a model file.
+    pub fn register_material_with_texture(
+        &mut self,
+        material: Material,
+        texture: Box<dyn Texture>,
+    ) -> MaterialID {
+        if let Some(id) = self.registered_id(&material.name) {
+            return id;
+        }
+
+        self.insert_material(material, Some(texture))
+    }
+
+    /// The id a material being registered is already known by, warning when it isn't a scene
+    /// material that is meant to override it
+    fn registered_id(&self, name: &str) -> Option<MaterialID> {
+        let id = self.find_material_id(name)?;
+        if id.0 < self.scene_materials {
+            log::debug!("scene material {name} overrides a registered one");
+        } else {
+            log::warn!("material {name} is defined twice, keeping the first definition");
         }
+        Some(id)
+    }
+
+    fn insert_material(
+        &mut self,
+        material: Material,
+        texture: Option<Box<dyn Texture>>,
+    ) -> MaterialID {
+        let id = MaterialID(self.materials.len());
+        log::debug!("{material:?}");
+        self.textures.push(texture);
+        self.name_to_id.insert(material.name.clone(), id);
+        self.materials.push(material);
+        id
     }
 
     pub fn get_material_id(&self, name: &str) -> MaterialID {
-        self.name_to_id[name]
+        self.find_material_id(name)
+            .unwrap_or_else(|| panic!("unknown material {name}"))
+    }
+
+    pub fn find_material_id(&self, name: &str) -> Option<MaterialID> {
+        self.name_to_id.get(name).copied()
+    }
+
+    /// Like `find_material_id`, only for materials given in the scene
+    pub fn scene_material_id(&self, name: &str) -> Option<MaterialID> {
+        self.find_material_id(name)
+            .filter(|id| id.0 < self.scene_materials)
     }
 
     pub fn get_material(&self, id: MaterialID) -> &Material {
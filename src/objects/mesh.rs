@@ -1,8 +1,8 @@
 #![allow(non_snake_case)]
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use std::fs::File;
-use std::io::{self, BufRead};
+use std::io;
 use std::path::Path;
 
 use serde::{Deserialize, Serialize};
@@ -11,7 +11,11 @@ use super::aabb::Aabb;
 use super::material::{MaterialID, Surfel};
 use super::object::Object;
 use super::transform::Transform;
-use crate::math::{cross, determinant, in_range, normalize, Mat3, Mat4, Range, Ray, Vec3, Vec4};
+use crate::math::{
+    cross, determinant, dot, in_range, length, normalize, Degree, Mat3, Mat4, Range, Ray, Vec3,
+    Vec4,
+};
+use crate::render::ColorRGB;
 
 pub struct Triangle {
     pub i: usize,
@@ -23,6 +27,8 @@ pub struct Mesh {
     pub vertices: Vec<Vec3>,
     pub triangles: Vec<Triangle>,
     pub normals: Vec<Vec3>,
+    pub uvs: Option<Vec<(f32, f32)>>,
+    pub colors: Option<Vec<ColorRGB>>,
     pub bbox: Aabb,
 }
 
@@ -40,6 +46,23 @@ pub struct MeshConfig {
     pub material: String,
     #[serde(default)]
     pub transform: Transform,
+    /// Faces meeting at a sharper angle than this get split normals (STL only)
+    #[serde(default)]
+    pub crease_angle: Option<Degree>,
+}
+
+/// A triangle mesh given in the scene file itself rather than loaded from a mesh file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TriangleMeshConfig {
+    pub vertices: Vec<Vec3>,
+    pub triangles: Vec<[usize; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normals: Option<Vec<Vec3>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uvs: Option<Vec<(f32, f32)>>,
+    pub material: String,
+    #[serde(default)]
+    pub transform: Transform,
 }
 
 impl Triangle {
@@ -50,6 +73,8 @@ impl Triangle {
         range: Range,
         vertices: &[Vec3],
         normals: &[Vec3],
+        uvs: Option<&[(f32, f32)]>,
+        colors: Option<&[ColorRGB]>,
     ) -> Option<Surfel> {
         let v0 = vertices[self.i];
         let v1 = vertices[self.j];
@@ -108,6 +133,17 @@ impl Triangle {
         let normal = normalize(
             (alpha * normals[self.i]) + (beta * normals[self.j]) + (gamma * normals[self.k]),
         );
+        let uv = uvs.map(|uvs| {
+            let (u0, v0) = uvs[self.i];
+            let (u1, v1) = uvs[self.j];
+            let (u2, v2) = uvs[self.k];
+            (
+                alpha * u0 + beta * u1 + gamma * u2,
+                alpha * v0 + beta * v1 + gamma * v2,
+            )
+        });
+        let color = colors
+            .map(|colors| alpha * colors[self.i] + beta * colors[self.j] + gamma * colors[self.k]);
         let material_id = MaterialID(0);
 
         Some(Surfel {
@@ -116,7 +152,9 @@ impl Triangle {
             normal,
             material_id,
             n_offset: 0.0_f32,
-            uv: None,
+            uv,
+            color,
+            object_id: 0,
         })
     }
 }
@@ -158,75 +196,190 @@ pub fn compute_normals(vertices: &[Vec3], triangles: &[Triangle], flip: bool) ->
     normals
 }
 
-impl Mesh {
-    pub fn fromSMF(fpath: &String, dpath: &String) -> Mesh {
-        let mut vertices = Vec::new();
-        let mut triangles = Vec::new();
-        let mut normals = Vec::new();
-        let path = Path::new(dpath).join(fpath);
-        log::info!("loading model mesh from: {}", path.display());
-
-        let file = match File::open(&path) {
-            Err(why) => panic!("failed to open {}: {}", path.display(), why),
-            Ok(file) => file,
+/// Compute vertex normals that are only smoothed across faces whose normals differ by less than
+/// `crease`. Vertices on a crease are duplicated, so new vertex and triangle lists are returned.
+pub fn compute_crease_normals(
+    vertices: &[Vec3],
+    triangles: &[Triangle],
+    crease: Degree,
+) -> (Vec<Vec3>, Vec<Triangle>, Vec<Vec3>) {
+    let cos_crease = crease.cos();
+    let mut incident = vec![Vec::new(); vertices.len()];
+    let face_normals: Vec<Vec3> = triangles
+        .iter()
+        .enumerate()
+        .map(|(f, tri)| {
+            incident[tri.i].push(f);
+            incident[tri.j].push(f);
+            incident[tri.k].push(f);
+            let c = cross(
+                vertices[tri.j] - vertices[tri.i],
+                vertices[tri.k] - vertices[tri.i],
+            );
+            // degenerate faces don't contribute to any normal
+            if length(c) > 0.0_f32 {
+                normalize(c)
+            } else {
+                Vec3::zeros()
+            }
+        })
+        .collect();
+
+    let mut out_vertices = Vec::new();
+    let mut out_normals = Vec::new();
+    let mut out_triangles = Vec::with_capacity(triangles.len());
+    let mut split: HashMap<(usize, [u32; 3]), usize> = HashMap::new();
+
+    for (f, tri) in triangles.iter().enumerate() {
+        let mut corner = |v: usize| {
+            let mut sum = Vec3::zeros();
+            for &g in &incident[v] {
+                if dot(face_normals[g], face_normals[f]) >= cos_crease {
+                    sum = sum + face_normals[g];
+                }
+            }
+            let n = if length(sum) > 0.0_f32 {
+                normalize(sum)
+            } else {
+                face_normals[f]
+            };
+            let key = (v, [n.x().to_bits(), n.y().to_bits(), n.z().to_bits()]);
+            *split.entry(key).or_insert_with(|| {
+                out_vertices.push(vertices[v]);
+                out_normals.push(n);
+                out_vertices.len() - 1
+            })
         };
+        let (i, j, k) = (corner(tri.i), corner(tri.j), corner(tri.k));
+        out_triangles.push(Triangle { i, j, k });
+    }
 
-        let mut box_min = Vec3::fill(f32::MAX);
-        let mut box_max = Vec3::fill(f32::MIN);
-        let lines = io::BufReader::new(file).lines();
+    (out_vertices, out_triangles, out_normals)
+}
 
-        for line in lines.map_while(Result::ok) {
-            if line.is_empty() || line.starts_with('#') {
-                continue;
-            }
+/// Build an error for a malformed line in a mesh file
+pub fn parse_error(path: &Path, line: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}:{}: {}", path.display(), line, msg),
+    )
+}
 
-            let split = line.split_whitespace();
-            let vec: Vec<&str> = split.collect();
+/// Parse the first `N` tokens of a mesh file line as floats
+pub fn parse_floats<const N: usize>(
+    tokens: &[&str],
+    path: &Path,
+    line: usize,
+) -> io::Result<[f32; N]> {
+    if tokens.len() < N {
+        return Err(parse_error(path, line, "too few values"));
+    }
 
-            if vec.len() < 4 {
-                continue; // dragon.smf has a single line with 'f\n'
-            }
+    let mut values = [0.0_f32; N];
+    for (value, token) in values.iter_mut().zip(tokens) {
+        *value = token
+            .parse::<f32>()
+            .map_err(|e| parse_error(path, line, &format!("invalid number '{token}': {e}")))?;
+    }
+    Ok(values)
+}
 
-            if vec[0] == "v" {
-                let x = vec[1].parse::<f32>().unwrap();
-                let y = vec[2].parse::<f32>().unwrap();
-                let z = vec[3].parse::<f32>().unwrap();
-                let v = Vec3::new(x, y, z);
-
-                box_min.set_x(v.x().min(box_min.x()));
-                box_min.set_y(v.y().min(box_min.y()));
-                box_min.set_z(v.z().min(box_min.z()));
-
-                box_max.set_x(v.x().max(box_max.x()));
-                box_max.set_y(v.y().max(box_max.y()));
-                box_max.set_z(v.z().max(box_max.z()));
-
-                vertices.push(v);
-            } else if vec[0] == "f" {
-                let i = vec[1].parse::<usize>().unwrap() - 1;
-                let j = vec[2].parse::<usize>().unwrap() - 1;
-                let k = vec[3].parse::<usize>().unwrap() - 1;
-                triangles.push(Triangle { i, j, k });
-            } else if vec[0] == "n" {
-                let a = vec[1].parse::<f32>().unwrap();
-                let b = vec[2].parse::<f32>().unwrap();
-                let c = vec[3].parse::<f32>().unwrap();
-                normals.push(Vec3::new(a, b, c));
-            }
-        }
+pub fn compute_bbox(vertices: &[Vec3]) -> Aabb {
+    let mut box_min = Vec3::fill(f32::MAX);
+    let mut box_max = Vec3::fill(f32::MIN);
+
+    for v in vertices {
+        box_min.set_x(v.x().min(box_min.x()));
+        box_min.set_y(v.y().min(box_min.y()));
+        box_min.set_z(v.z().min(box_min.z()));
+
+        box_max.set_x(v.x().max(box_max.x()));
+        box_max.set_y(v.y().max(box_max.y()));
+        box_max.set_z(v.z().max(box_max.z()));
+    }
+
+    Aabb::new(box_min, box_max)
+}
 
-        if normals.is_empty() {
-            normals = compute_normals(&vertices, &triangles, false);
+impl Mesh {
+    /// Build the mesh of an inline triangle mesh config. Normals are computed if not given.
+    pub fn from_config(config: &TriangleMeshConfig) -> io::Result<Mesh> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let count = config.vertices.len();
+        if let Some(idx) = config.triangles.iter().flatten().find(|&&idx| idx >= count) {
+            return Err(invalid(format!(
+                "triangle index {idx} out of range for {count} vertices"
+            )));
+        }
+        if config.normals.as_ref().is_some_and(|n| n.len() != count) {
+            return Err(invalid(format!("mesh needs {count} normals")));
+        }
+        if config.uvs.as_ref().is_some_and(|uvs| uvs.len() != count) {
+            return Err(invalid(format!("mesh needs {count} uvs")));
         }
 
-        let bbox = Aabb::new(box_min, box_max);
-        log::debug!("mesh bbox: {bbox:?}");
-        Mesh {
+        let vertices = config.vertices.clone();
+        let triangles: Vec<Triangle> = config
+            .triangles
+            .iter()
+            .map(|&[i, j, k]| Triangle { i, j, k })
+            .collect();
+        let normals = match &config.normals {
+            Some(normals) => normals.iter().map(|&n| normalize(n)).collect(),
+            None => compute_normals(&vertices, &triangles, false),
+        };
+        let bbox = compute_bbox(&vertices);
+
+        Ok(Mesh {
             vertices,
             triangles,
             normals,
+            uvs: config.uvs.clone(),
+            colors: None,
             bbox,
+        })
+    }
+
+    /// Append the triangles of another mesh. UVs and colours are only kept if both meshes have
+    /// them.
+    pub fn append(&mut self, other: &Mesh) {
+        let offset = self.vertices.len();
+        self.vertices.extend_from_slice(&other.vertices);
+        self.normals.extend_from_slice(&other.normals);
+        self.triangles
+            .extend(other.triangles.iter().map(|tri| Triangle {
+                i: tri.i + offset,
+                j: tri.j + offset,
+                k: tri.k + offset,
+            }));
+        self.uvs = match (self.uvs.take(), &other.uvs) {
+            (Some(mut uvs), Some(more)) => {
+                uvs.extend_from_slice(more);
+                Some(uvs)
+            }
+            _ => None,
+        };
+        self.colors = match (self.colors.take(), &other.colors) {
+            (Some(mut colors), Some(more)) => {
+                colors.extend_from_slice(more);
+                Some(colors)
+            }
+            _ => None,
+        };
+        self.bbox = compute_bbox(&self.vertices);
+    }
+
+    /// Apply a transform to the vertices, and its inverse transpose to the normals
+    pub fn transform(&mut self, transform: &Transform) {
+        let m = transform.mat4();
+        let n = transform.inverse().transpose();
+        for v in &mut self.vertices {
+            *v = (&m * Vec4::from_vec3(*v, 1.0)).to_vec3();
         }
+        for normal in &mut self.normals {
+            *normal = normalize((&n * Vec4::from_vec3(*normal, 0.0)).to_vec3());
+        }
+        self.bbox = compute_bbox(&self.vertices);
     }
 }
 
@@ -244,7 +397,14 @@ impl Object for Mesh {
         let mut surfel = None;
 
         for tri in &self.triangles {
-            if let Some(surf) = tri.intersect(ray, t_range, &self.vertices, &self.normals) {
+            if let Some(surf) = tri.intersect(
+                ray,
+                t_range,
+                &self.vertices,
+                &self.normals,
+                self.uvs.as_deref(),
+                self.colors.as_deref(),
+            ) {
                 t_range.max = surf.t;
                 surfel = Some(Surfel { ..surf });
             }
@@ -256,8 +416,21 @@ impl Object for Mesh {
 
 impl Instance {
     pub fn new(model: Arc<Mesh>, material_id: MaterialID, transformations: &Transform) -> Self {
-        let transform = transformations.mat4();
-        let inverse = transformations.inverse();
+        Instance::from_matrices(
+            model,
+            material_id,
+            transformations.mat4(),
+            transformations.inverse(),
+        )
+    }
+
+    /// Create an instance from an arbitrary transformation matrix and its inverse
+    pub fn from_matrices(
+        model: Arc<Mesh>,
+        material_id: MaterialID,
+        transform: Mat4,
+        inverse: Mat4,
+    ) -> Self {
         let bbox = model.bbox.transform(&transform);
         log::debug!("instance bbox: {:?} center: {:?}", bbox, bbox.center());
         Instance {
@@ -308,8 +481,43 @@ impl Object for Instance {
                 material_id,
                 n_offset: 0.000_000_000_1,
                 uv: surf.uv,
+                color: surf.color,
+                object_id: surf.object_id,
             });
         }
         surfel
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uv_is_interpolated_at_hit() {
+        let vertices = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let normals = [Vec3::new(0.0, 0.0, 1.0); 3];
+        let uvs = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)];
+        let ray = Ray {
+            origin: Vec3::new(0.25, 0.5, 1.0),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+            depth: 0,
+        };
+        let range = Range {
+            min: 0.001,
+            max: f32::MAX,
+        };
+
+        let tri = Triangle { i: 0, j: 1, k: 2 };
+        let surfel = tri
+            .intersect(&ray, range, &vertices, &normals, Some(&uvs), None)
+            .unwrap();
+        let (u, v) = surfel.uv.unwrap();
+        assert!((u - 0.25).abs() < 1e-5);
+        assert!((v - 0.5).abs() < 1e-5);
+    }
+}
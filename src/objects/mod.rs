@@ -3,9 +3,13 @@ pub mod bpatch;
 pub mod bvh;
 pub mod material;
 pub mod mesh;
+pub mod obj;
 pub mod object;
 pub mod plane;
+pub mod ply;
+pub mod smf;
 pub mod sphere;
+pub mod stl;
 pub mod superquadric;
 
 pub mod transform;
@@ -13,6 +17,6 @@ pub mod transform;
 pub use bvh::Bvh;
 pub use material::{Material, MaterialMap, Surfel};
 pub use mesh::{Instance, Mesh};
-pub use object::Object;
+pub use object::{Labeled, Object};
 pub use plane::Plane;
 pub use sphere::Sphere;
@@ -0,0 +1,571 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use super::material::{Material, MaterialID, MaterialMap};
+use super::mesh::{compute_bbox, compute_normals, parse_error, parse_floats, Mesh, Triangle};
+use crate::math::Vec3;
+use crate::render::texture::TextureConfig;
+use crate::render::ColorRGB;
+
+/// Indices into the OBJ position, texture coordinate and normal lists for one face corner
+type Corner = (usize, Option<usize>, Option<usize>);
+
+/// A triangulated OBJ group/object and the material name it was assigned with `usemtl`
+struct ObjGroup {
+    material: Option<String>,
+    mesh: Mesh,
+}
+
+struct ObjData {
+    groups: Vec<ObjGroup>,
+    mtllibs: Vec<String>,
+}
+
+/// Collects the faces of one group. OBJ indexes positions, uvs and normals separately,
+/// so every distinct corner becomes its own mesh vertex.
+struct GroupBuilder {
+    material: Option<String>,
+    corners: HashMap<Corner, usize>,
+    vertices: Vec<Vec3>,
+    uvs: Vec<(f32, f32)>,
+    normals: Vec<Vec3>,
+    colors: Vec<ColorRGB>,
+    triangles: Vec<Triangle>,
+    all_uvs: bool,
+    all_normals: bool,
+    any_colors: bool,
+}
+
+/// Resolve a 1-based (or negative, relative) OBJ index against a list of `count` elements
+fn resolve_index(token: &str, count: usize, path: &Path, line: usize) -> io::Result<usize> {
+    let idx = token
+        .parse::<i64>()
+        .map_err(|e| parse_error(path, line, &format!("invalid index '{token}': {e}")))?;
+    let count = i64::try_from(count).unwrap_or(i64::MAX);
+    let resolved = if idx < 0 { count + idx } else { idx - 1 };
+
+    if resolved < 0 || resolved >= count {
+        return Err(parse_error(
+            path,
+            line,
+            &format!("index {idx} out of range"),
+        ));
+    }
+    // resolved is in [0, count) so it fits in usize
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    Ok(resolved as usize)
+}
+
+impl GroupBuilder {
+    fn new(material: Option<String>) -> Self {
+        GroupBuilder {
+            material,
+            corners: HashMap::new(),
+            vertices: Vec::new(),
+            uvs: Vec::new(),
+            normals: Vec::new(),
+            colors: Vec::new(),
+            triangles: Vec::new(),
+            all_uvs: true,
+            all_normals: true,
+            any_colors: false,
+        }
+    }
+
+    fn add_corner(
+        &mut self,
+        corner: Corner,
+        positions: &[(Vec3, Option<ColorRGB>)],
+        texcoords: &[(f32, f32)],
+        normals: &[Vec3],
+    ) -> usize {
+        *self.corners.entry(corner).or_insert_with(|| {
+            let (v, vt, vn) = corner;
+            let (position, color) = positions[v];
+            self.all_uvs &= vt.is_some();
+            self.all_normals &= vn.is_some();
+            self.any_colors |= color.is_some();
+            self.vertices.push(position);
+            self.uvs.push(vt.map_or((0.0, 0.0), |i| texcoords[i]));
+            self.normals.push(vn.map_or(Vec3::zeros(), |i| normals[i]));
+            self.colors.push(color.unwrap_or_else(ColorRGB::white));
+            self.vertices.len() - 1
+        })
+    }
+
+    fn finish(self) -> Option<ObjGroup> {
+        if self.triangles.is_empty() {
+            return None;
+        }
+
+        let normals = if self.all_normals {
+            self.normals
+        } else {
+            compute_normals(&self.vertices, &self.triangles, false)
+        };
+        let uvs = if self.all_uvs { Some(self.uvs) } else { None };
+        let colors = if self.any_colors {
+            Some(self.colors)
+        } else {
+            None
+        };
+        let bbox = compute_bbox(&self.vertices);
+        log::debug!("obj group bbox: {bbox:?}");
+
+        Some(ObjGroup {
+            material: self.material,
+            mesh: Mesh {
+                vertices: self.vertices,
+                triangles: self.triangles,
+                normals,
+                uvs,
+                colors,
+                bbox,
+            },
+        })
+    }
+}
+
+fn read_obj<R: BufRead>(reader: R, path: &Path) -> io::Result<ObjData> {
+    let mut positions = Vec::new();
+    let mut texcoords = Vec::new();
+    let mut normals = Vec::new();
+    let mut groups = Vec::new();
+    let mut mtllibs = Vec::new();
+    let mut material: Option<String> = None;
+    let mut group = GroupBuilder::new(None);
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = idx + 1;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        let Some((&keyword, args)) = tokens.split_first() else {
+            continue;
+        };
+
+        match keyword {
+            "v" => {
+                // the common `v x y z r g b` extension carries a vertex colour
+                if args.len() >= 6 {
+                    let [x, y, z, r, g, b] = parse_floats(args, path, line_no)?;
+                    positions.push((Vec3::new(x, y, z), Some(ColorRGB::new(r, g, b))));
+                } else {
+                    let [x, y, z] = parse_floats(args, path, line_no)?;
+                    positions.push((Vec3::new(x, y, z), None));
+                }
+            }
+            "vt" => {
+                // v is optional and defaults to 0, and any w is ignored
+                if args.len() >= 2 {
+                    let [u, v] = parse_floats(args, path, line_no)?;
+                    texcoords.push((u, v));
+                } else {
+                    let [u] = parse_floats(args, path, line_no)?;
+                    texcoords.push((u, 0.0));
+                }
+            }
+            "vn" => {
+                let [x, y, z] = parse_floats(args, path, line_no)?;
+                normals.push(Vec3::new(x, y, z));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(parse_error(path, line_no, "face needs at least 3 vertices"));
+                }
+
+                let mut face = Vec::with_capacity(args.len());
+                for arg in args {
+                    let mut parts = arg.split('/');
+                    let v =
+                        resolve_index(parts.next().unwrap_or(""), positions.len(), path, line_no)?;
+                    let vt = match parts.next() {
+                        Some(s) if !s.is_empty() => {
+                            Some(resolve_index(s, texcoords.len(), path, line_no)?)
+                        }
+                        _ => None,
+                    };
+                    let vn = match parts.next() {
+                        Some(s) if !s.is_empty() => {
+                            Some(resolve_index(s, normals.len(), path, line_no)?)
+                        }
+                        _ => None,
+                    };
+                    face.push(group.add_corner((v, vt, vn), &positions, &texcoords, &normals));
+                }
+
+                // fan triangulation, assumes convex polygons
+                for w in 1..face.len() - 1 {
+                    group.triangles.push(Triangle {
+                        i: face[0],
+                        j: face[w],
+                        k: face[w + 1],
+                    });
+                }
+            }
+            "g" | "o" => {
+                let prev = std::mem::replace(&mut group, GroupBuilder::new(material.clone()));
+                groups.extend(prev.finish());
+            }
+            "usemtl" => {
+                material = args.first().map(|s| (*s).to_string());
+                let prev = std::mem::replace(&mut group, GroupBuilder::new(material.clone()));
+                groups.extend(prev.finish());
+            }
+            "mtllib" => {
+                mtllibs.extend(args.iter().map(|s| (*s).to_string()));
+            }
+            _ => {} // comments, smoothing groups, lines, etc.
+        }
+    }
+
+    groups.extend(group.finish());
+    Ok(ObjData { groups, mtllibs })
+}
+
+/// Parse an MTL library. Texture paths are resolved relative to `dir`.
+fn read_mtl<R: BufRead>(reader: R, path: &Path, dir: &Path) -> io::Result<Vec<Material>> {
+    let mut materials: Vec<Material> = Vec::new();
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = idx + 1;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        let Some((&keyword, args)) = tokens.split_first() else {
+            continue;
+        };
+
+        if keyword == "newmtl" {
+            let name = args
+                .first()
+                .ok_or_else(|| parse_error(path, line_no, "newmtl without a name"))?;
+            materials.push(Material {
+                name: (*name).to_string(),
+                ..Material::default()
+            });
+            continue;
+        }
+
+        if keyword.starts_with('#') {
+            continue;
+        }
+
+        let mat = materials
+            .last_mut()
+            .ok_or_else(|| parse_error(path, line_no, "statement before newmtl"))?;
+
+        match keyword {
+            "Ka" => {
+                let [r, g, b] = parse_floats(args, path, line_no)?;
+                mat.ambient = ColorRGB::new(r, g, b);
+            }
+            "Kd" => {
+                let [r, g, b] = parse_floats(args, path, line_no)?;
+                mat.diffuse = ColorRGB::new(r, g, b);
+            }
+            "Ks" => {
+                let [r, g, b] = parse_floats(args, path, line_no)?;
+                mat.specular = ColorRGB::new(r, g, b);
+            }
+            "Tf" => {
+                let [r, g, b] = parse_floats(args, path, line_no)?;
+                mat.transmissive = ColorRGB::new(r, g, b);
+            }
+            "Ns" => {
+                let [ns] = parse_floats(args, path, line_no)?;
+                mat.shininess = ns;
+            }
+            "Ni" => {
+                let [ni] = parse_floats(args, path, line_no)?;
+                mat.ior = ni;
+            }
+            "d" | "Tr" => {
+                let [f] = parse_floats(args, path, line_no)?;
+                // d is opacity, Tr is its inverse
+                let d = if keyword == "d" { f } else { 1.0 - f };
+                mat.kt = (1.0 - d).clamp(0.0, 1.0);
+                if mat.kt > 0.0
+                    && mat.transmissive.r + mat.transmissive.g + mat.transmissive.b == 0.0
+                {
+                    mat.transmissive = ColorRGB::white();
+                }
+            }
+            "map_Kd" => {
+                // options such as -s or -o may precede the file name
+                let file = args
+                    .last()
+                    .ok_or_else(|| parse_error(path, line_no, "map_Kd without a file"))?;
+                mat.texture = Some(TextureConfig::Image {
+                    file: dir.join(file).to_string_lossy().into_owned(),
+                    linear: false,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+/// Load a Wavefront OBJ file. Each group, object or `usemtl` section becomes its own mesh.
+/// Materials from referenced MTL libraries are registered in `materials` as
+/// `<path>#<name>`, unless the scene has a material of that name; groups without a
+/// material are returned with `None` so the caller can apply a default.
+pub fn load_obj(
+    path: &Path,
+    materials: &mut MaterialMap,
+) -> io::Result<Vec<(Mesh, Option<MaterialID>)>> {
+    log::info!("loading obj mesh from: {}", path.display());
+    let file = File::open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to open {}: {e}", path.display())))?;
+    let data = read_obj(BufReader::new(file), path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    // MTL names are only unique within a model, Blender names every default `Material`
+    let mut mtl_ids: HashMap<String, MaterialID> = HashMap::new();
+    for lib in &data.mtllibs {
+        let mtl_path = dir.join(lib);
+        log::info!("loading obj materials from: {}", mtl_path.display());
+        let file = File::open(&mtl_path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("failed to open {}: {e}", mtl_path.display()),
+            )
+        })?;
+        let mtl_dir = mtl_path.parent().unwrap_or(Path::new(""));
+        for mut material in read_mtl(BufReader::new(file), &mtl_path, mtl_dir)? {
+            if mtl_ids.contains_key(&material.name) {
+                log::warn!(
+                    "{}: material {} is defined twice, keeping the first definition",
+                    path.display(),
+                    material.name
+                );
+                continue;
+            }
+            let name = material.name.clone();
+            let qualified = format!("{}#{name}", path.display());
+            let id = match materials.scene_material_id(&name) {
+                Some(id) => id,
+                // the model was already loaded for another object
+                None => materials.find_material_id(&qualified).unwrap_or_else(|| {
+                    material.name = qualified;
+                    materials.register_material(material)
+                }),
+            };
+            mtl_ids.insert(name, id);
+        }
+    }
+
+    log::info!("obj groups: {}", data.groups.len());
+    data.groups
+        .into_iter()
+        .map(|group| {
+            let material_id = match group.material {
+                Some(name) => Some(
+                    mtl_ids
+                        .get(&name)
+                        .copied()
+                        .or_else(|| materials.scene_material_id(&name))
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::NotFound,
+                                format!("{}: unknown material {name}", path.display()),
+                            )
+                        })?,
+                ),
+                None => None,
+            };
+            Ok((group.mesh, material_id))
+        })
+        .collect()
+}
+
+/// The material names an OBJ file's faces use with `usemtl`
+pub fn usemtl_names(path: &Path) -> io::Result<Vec<String>> {
+    let file = File::open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to open {}: {e}", path.display())))?;
+    let data = read_obj(BufReader::new(file), path)?;
+    Ok(data
+        .groups
+        .into_iter()
+        .filter_map(|group| group.material)
+        .collect())
+}
+
+/// Write a mesh as OBJ. Every attribute is per vertex so faces use the same index for the
+/// position, texture coordinate and normal.
+pub fn write_obj<W: Write>(mesh: &Mesh, out: &mut W) -> io::Result<()> {
+    for (idx, v) in mesh.vertices.iter().enumerate() {
+        write!(out, "v {} {} {}", v.x(), v.y(), v.z())?;
+        if let Some(colors) = &mesh.colors {
+            let c = colors[idx];
+            write!(out, " {} {} {}", c.r, c.g, c.b)?;
+        }
+        writeln!(out)?;
+    }
+    for (u, v) in mesh.uvs.iter().flatten() {
+        writeln!(out, "vt {u} {v}")?;
+    }
+    for n in &mesh.normals {
+        writeln!(out, "vn {} {} {}", n.x(), n.y(), n.z())?;
+    }
+
+    let has_uvs = mesh.uvs.is_some();
+    for tri in &mesh.triangles {
+        write!(out, "f")?;
+        for idx in [tri.i + 1, tri.j + 1, tri.k + 1] {
+            if has_uvs {
+                write!(out, " {idx}/{idx}/{idx}")?;
+            } else {
+                write!(out, " {idx}//{idx}")?;
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::color::WorkingSpace;
+    use std::io::Cursor;
+
+    fn parse(src: &str) -> io::Result<ObjData> {
+        read_obj(Cursor::new(src), Path::new("test.obj"))
+    }
+
+    #[test]
+    fn quad_is_triangulated() {
+        let data = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+        assert_eq!(data.groups.len(), 1);
+        let mesh = &data.groups[0].mesh;
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangles.len(), 2);
+        assert!(mesh.uvs.is_none());
+        // normals are computed when the file has none
+        assert!((mesh.normals[0].z() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn corners_with_distinct_uvs_are_split() {
+        let src = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvt 0.5 0.5\n\
+                   vn 0 0 1\nf 1/1/1 2/2/1 3/3/1\nf 1/4/1 2/2/1 3/3/1\n";
+        let data = parse(src).unwrap();
+        let mesh = &data.groups[0].mesh;
+        assert_eq!(mesh.vertices.len(), 4);
+        let uvs = mesh.uvs.as_ref().unwrap();
+        assert!((uvs[3].0 - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn one_component_uv_defaults_v() {
+        let src = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.25\nvt 0.5 0.75 0\nf 1/1 2/2 3/1\n";
+        let mesh = &parse(src).unwrap().groups[0].mesh;
+        let uvs = mesh.uvs.as_ref().unwrap();
+        assert!((uvs[0].0 - 0.25).abs() < 1e-5);
+        assert!(uvs[0].1.abs() < 1e-5);
+        assert!((uvs[1].1 - 0.75).abs() < 1e-5);
+    }
+
+    #[test]
+    fn negative_indices_are_relative() {
+        let data = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\n").unwrap();
+        let tri = &data.groups[0].mesh.triangles[0];
+        assert_eq!((tri.i, tri.j, tri.k), (0, 1, 2));
+    }
+
+    #[test]
+    fn vertex_colors_are_read() {
+        let data = parse("v 0 0 0 1 0 0\nv 1 0 0\nv 0 1 0 0 0 1\nf 1 2 3\n").unwrap();
+        let colors = data.groups[0].mesh.colors.as_ref().unwrap();
+        assert!((colors[0].r - 1.0).abs() < 1e-5);
+        // vertices without a colour are white
+        assert!((colors[1].g - 1.0).abs() < 1e-5);
+        assert!((colors[2].b - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn usemtl_starts_new_group() {
+        let src = "mtllib a.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n\
+                   g second\nusemtl blue\nf 1 2 3\n";
+        let data = parse(src).unwrap();
+        assert_eq!(data.mtllibs, vec!["a.mtl".to_string()]);
+        assert_eq!(data.groups.len(), 2);
+        assert_eq!(data.groups[0].material.as_deref(), Some("red"));
+        assert_eq!(data.groups[1].material.as_deref(), Some("blue"));
+    }
+
+    #[test]
+    fn bad_index_reports_line() {
+        let err = parse("v 0 0 0\nf 1 2 3\n").err().unwrap();
+        assert!(err.to_string().contains("test.obj:2"), "{err}");
+    }
+
+    #[test]
+    fn mtl_maps_onto_material() {
+        let src = "newmtl glass\nKd 0.5 0.5 0.5\nKs 1 1 1\nNs 200\nNi 1.5\nd 0.25\n\
+                   map_Kd -s 1 1 1 tex.png\n";
+        let mats = read_mtl(Cursor::new(src), Path::new("a.mtl"), Path::new("models")).unwrap();
+        assert_eq!(mats.len(), 1);
+        let mat = &mats[0];
+        assert_eq!(mat.name, "glass");
+        assert!((mat.diffuse.r - 0.5).abs() < 1e-5);
+        assert!((mat.shininess - 200.0).abs() < 1e-5);
+        assert!((mat.ior - 1.5).abs() < 1e-5);
+        assert!((mat.kt - 0.75).abs() < 1e-5);
+        match &mat.texture {
+            Some(TextureConfig::Image { file, .. }) => {
+                assert_eq!(Path::new(file), Path::new("models").join("tex.png"));
+            }
+            _ => panic!("expected image texture"),
+        }
+    }
+
+    #[test]
+    fn models_keep_their_own_mtl_materials() {
+        let dir = std::env::temp_dir().join("arrt_obj_mtl_names");
+        std::fs::create_dir_all(&dir).unwrap();
+        for (model, kd) in [("a", "1 0 0"), ("b", "0 0 1")] {
+            std::fs::write(
+                dir.join(format!("{model}.obj")),
+                format!(
+                    "mtllib {model}.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl Material\nf 1 2 3\n"
+                ),
+            )
+            .unwrap();
+            std::fs::write(
+                dir.join(format!("{model}.mtl")),
+                format!("newmtl Material\nKd {kd}\n"),
+            )
+            .unwrap();
+        }
+
+        let mut materials = MaterialMap::from_materials(Vec::new(), WorkingSpace::default());
+        let a = load_obj(&dir.join("a.obj"), &mut materials).unwrap();
+        let b = load_obj(&dir.join("b.obj"), &mut materials).unwrap();
+        let a = materials.get_material(a[0].1.unwrap());
+        let b = materials.get_material(b[0].1.unwrap());
+        assert!((a.diffuse.r - 1.0).abs() < 1e-5);
+        assert!((b.diffuse.b - 1.0).abs() < 1e-5);
+        assert!(b.diffuse.r.abs() < 1e-5);
+    }
+
+    #[test]
+    fn written_obj_reads_back() {
+        let src = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\n\
+                   f 1/1/1 2/2/1 3/3/1\n";
+        let mesh = &parse(src).unwrap().groups[0].mesh;
+        let mut out = Vec::new();
+        write_obj(mesh, &mut out).unwrap();
+
+        let copy = &parse(std::str::from_utf8(&out).unwrap()).unwrap().groups[0].mesh;
+        assert_eq!(copy.vertices.len(), 3);
+        assert_eq!(copy.triangles.len(), 1);
+        assert!((copy.uvs.as_ref().unwrap()[2].1 - 1.0).abs() < 1e-5);
+        assert!((copy.normals[1].z() - 1.0).abs() < 1e-5);
+    }
+}
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use crate::math::{Range, Ray, Vec3};
 
 use super::aabb::Aabb;
@@ -8,3 +10,32 @@ pub trait Object: Send + Sync {
     fn centroid(&self) -> Vec3;
     fn intersect(&self, ray: &Ray, range: Range) -> Option<Surfel>;
 }
+
+/// Tags the surfels of an object with the id of the scene object it belongs to
+pub struct Labeled {
+    object: Arc<dyn Object>,
+    id: u32,
+}
+
+impl Labeled {
+    pub fn new(object: Arc<dyn Object>, id: u32) -> Self {
+        Labeled { object, id }
+    }
+}
+
+impl Object for Labeled {
+    fn bbox(&self) -> Option<Aabb> {
+        self.object.bbox()
+    }
+
+    fn centroid(&self) -> Vec3 {
+        self.object.centroid()
+    }
+
+    fn intersect(&self, ray: &Ray, range: Range) -> Option<Surfel> {
+        self.object.intersect(ray, range).map(|surfel| Surfel {
+            object_id: self.id,
+            ..surfel
+        })
+    }
+}
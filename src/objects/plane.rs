@@ -79,6 +79,8 @@ impl Object for Plane {
                     material_id: self.material_id,
                     n_offset: 0.0_f32,
                     uv: Some((u, v)),
+                    color: None,
+                    object_id: 0,
                 });
             }
         }
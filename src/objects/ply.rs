@@ -0,0 +1,651 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use super::mesh::{compute_bbox, compute_normals, parse_error, Mesh, Triangle};
+use crate::math::Vec3;
+use crate::render::ColorRGB;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar {
+        name: String,
+        ty: Scalar,
+    },
+    List {
+        name: String,
+        count: Scalar,
+        item: Scalar,
+    },
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    /// Number of lines the header occupies, used for ASCII line numbers
+    lines: usize,
+}
+
+/// Positions of the vertex attributes within a vertex element row
+struct VertexLayout {
+    position: [usize; 3],
+    normal: Option<[usize; 3]>,
+    color: Option<([usize; 3], f32)>,
+    uv: Option<[usize; 2]>,
+}
+
+/// Source of property values for the element data that follows the header
+trait ValueReader {
+    fn read(&mut self, ty: Scalar) -> io::Result<f64>;
+}
+
+struct AsciiReader<'a, R> {
+    reader: R,
+    path: &'a Path,
+    line_no: usize,
+    tokens: Vec<String>,
+}
+
+struct BinaryReader<'a, R> {
+    reader: R,
+    path: &'a Path,
+    big_endian: bool,
+}
+
+impl Scalar {
+    fn parse(s: &str) -> Option<Scalar> {
+        match s {
+            "char" | "int8" => Some(Scalar::I8),
+            "uchar" | "uint8" => Some(Scalar::U8),
+            "short" | "int16" => Some(Scalar::I16),
+            "ushort" | "uint16" => Some(Scalar::U16),
+            "int" | "int32" => Some(Scalar::I32),
+            "uint" | "uint32" => Some(Scalar::U32),
+            "float" | "float32" => Some(Scalar::F32),
+            "double" | "float64" => Some(Scalar::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar { name, .. } | Property::List { name, .. } => name,
+        }
+    }
+}
+
+impl Element {
+    fn scalar(&self, names: &[&str]) -> Option<(usize, Scalar)> {
+        names.iter().find_map(|n| {
+            self.properties
+                .iter()
+                .enumerate()
+                .find_map(|(i, p)| match p {
+                    Property::Scalar { name, ty } if name == n => Some((i, *ty)),
+                    _ => None,
+                })
+        })
+    }
+
+    fn scalars<const N: usize>(&self, names: [&[&str]; N]) -> Option<[usize; N]> {
+        let mut idx = [0; N];
+        for (slot, alternatives) in idx.iter_mut().zip(names) {
+            *slot = self.scalar(alternatives)?.0;
+        }
+        Some(idx)
+    }
+}
+
+impl<R: BufRead> ValueReader for AsciiReader<'_, R> {
+    fn read(&mut self, _ty: Scalar) -> io::Result<f64> {
+        while self.tokens.is_empty() {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(parse_error(
+                    self.path,
+                    self.line_no,
+                    "unexpected end of file",
+                ));
+            }
+            self.line_no += 1;
+            // pop from the back, so store the tokens reversed
+            self.tokens = line.split_whitespace().rev().map(String::from).collect();
+        }
+
+        let token = self.tokens.pop().unwrap_or_default();
+        token.parse::<f64>().map_err(|e| {
+            parse_error(
+                self.path,
+                self.line_no,
+                &format!("invalid number '{token}': {e}"),
+            )
+        })
+    }
+}
+
+impl<R: Read> ValueReader for BinaryReader<'_, R> {
+    fn read(&mut self, ty: Scalar) -> io::Result<f64> {
+        let mut buf = [0_u8; 8];
+        let bytes = &mut buf[..ty.size()];
+        self.reader.read_exact(bytes).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("{}: truncated element data: {e}", self.path.display()),
+            )
+        })?;
+
+        macro_rules! decode {
+            ($t:ty) => {{
+                let arr = (&*bytes).try_into().unwrap();
+                if self.big_endian {
+                    <$t>::from_be_bytes(arr)
+                } else {
+                    <$t>::from_le_bytes(arr)
+                }
+            }};
+        }
+
+        Ok(match ty {
+            Scalar::I8 => f64::from(decode!(i8)),
+            Scalar::U8 => f64::from(decode!(u8)),
+            Scalar::I16 => f64::from(decode!(i16)),
+            Scalar::U16 => f64::from(decode!(u16)),
+            Scalar::I32 => f64::from(decode!(i32)),
+            Scalar::U32 => f64::from(decode!(u32)),
+            Scalar::F32 => f64::from(decode!(f32)),
+            Scalar::F64 => decode!(f64),
+        })
+    }
+}
+
+/// Convert a list length or vertex index read as a number, which may be of a signed type
+#[allow(clippy::cast_possible_truncation)]
+fn to_index(value: f64, what: &str, path: &Path) -> io::Result<usize> {
+    usize::try_from(value as i64).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: invalid {what} {value}", path.display()),
+        )
+    })
+}
+
+fn read_header<R: BufRead>(reader: &mut R, path: &Path) -> io::Result<Header> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut line_no = 0;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(parse_error(path, line_no, "missing end_header"));
+        }
+        line_no += 1;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        if line_no == 1 {
+            if tokens.first() != Some(&"ply") {
+                return Err(parse_error(path, line_no, "not a PLY file"));
+            }
+            continue;
+        }
+
+        match tokens.as_slice() {
+            ["format", fmt, _version] => {
+                format = Some(match *fmt {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(parse_error(path, line_no, &format!("unknown format {fmt}"))),
+                });
+            }
+            ["element", name, count] => {
+                let count = count.parse::<usize>().map_err(|e| {
+                    parse_error(path, line_no, &format!("invalid element count: {e}"))
+                })?;
+                elements.push(Element {
+                    name: (*name).to_string(),
+                    count,
+                    properties: Vec::new(),
+                });
+            }
+            ["property", "list", count, item, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| parse_error(path, line_no, "property before element"))?;
+                let (Some(count), Some(item)) = (Scalar::parse(count), Scalar::parse(item)) else {
+                    return Err(parse_error(path, line_no, "unknown list property type"));
+                };
+                element.properties.push(Property::List {
+                    name: (*name).to_string(),
+                    count,
+                    item,
+                });
+            }
+            ["property", ty, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| parse_error(path, line_no, "property before element"))?;
+                let ty = Scalar::parse(ty).ok_or_else(|| {
+                    parse_error(path, line_no, &format!("unknown property type {ty}"))
+                })?;
+                element.properties.push(Property::Scalar {
+                    name: (*name).to_string(),
+                    ty,
+                });
+            }
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(parse_error(path, line_no, "unrecognized header line")),
+        }
+    }
+
+    let format = format.ok_or_else(|| parse_error(path, line_no, "missing format line"))?;
+    Ok(Header {
+        format,
+        elements,
+        lines: line_no,
+    })
+}
+
+fn vertex_layout(element: &Element, path: &Path) -> io::Result<VertexLayout> {
+    let position = element.scalars([&["x"], &["y"], &["z"]]).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: vertex element has no x/y/z", path.display()),
+        )
+    })?;
+    let normal = element.scalars([&["nx"], &["ny"], &["nz"]]);
+    let color = element
+        .scalars([
+            &["red", "diffuse_red"],
+            &["green", "diffuse_green"],
+            &["blue", "diffuse_blue"],
+        ])
+        .map(|idx| {
+            let scale = match element.scalar(&["red", "diffuse_red"]).map(|(_, ty)| ty) {
+                Some(Scalar::U8) => 1.0 / 255.0,
+                Some(Scalar::U16) => 1.0 / 65535.0,
+                _ => 1.0,
+            };
+            (idx, scale)
+        });
+    let uv = element.scalars([
+        &["u", "s", "texture_u", "texture_s"],
+        &["v", "t", "texture_v", "texture_t"],
+    ]);
+
+    Ok(VertexLayout {
+        position,
+        normal,
+        color,
+        uv,
+    })
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn read_body<V: ValueReader>(values: &mut V, header: &Header, path: &Path) -> io::Result<Mesh> {
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut colors = Vec::new();
+    let mut uvs = Vec::new();
+    let mut triangles = Vec::new();
+    let mut layout = None;
+
+    let mut row = Vec::new();
+    let mut face = Vec::new();
+
+    for element in &header.elements {
+        let face_list = if element.name == "face" {
+            element
+                .properties
+                .iter()
+                .position(|p| p.name() == "vertex_indices" || p.name() == "vertex_index")
+        } else {
+            None
+        };
+        if element.name == "vertex" {
+            layout = Some(vertex_layout(element, path)?);
+        }
+
+        for _ in 0..element.count {
+            row.clear();
+            face.clear();
+
+            for (idx, property) in element.properties.iter().enumerate() {
+                match property {
+                    Property::Scalar { ty, .. } => row.push(values.read(*ty)?),
+                    Property::List { count, item, .. } => {
+                        row.push(0.0);
+                        let n = to_index(values.read(*count)?, "list length", path)?;
+                        for _ in 0..n {
+                            let v = values.read(*item)?;
+                            if face_list == Some(idx) {
+                                face.push(to_index(v, "face index", path)?);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if element.name == "vertex" {
+                let Some(layout) = &layout else { continue };
+                let vec3 =
+                    |[a, b, c]: [usize; 3]| Vec3::new(row[a] as f32, row[b] as f32, row[c] as f32);
+                vertices.push(vec3(layout.position));
+                if let Some(n) = layout.normal {
+                    normals.push(vec3(n));
+                }
+                if let Some(([r, g, b], scale)) = layout.color {
+                    colors.push(ColorRGB::new(row[r] as f32, row[g] as f32, row[b] as f32) * scale);
+                }
+                if let Some([u, v]) = layout.uv {
+                    uvs.push((row[u] as f32, row[v] as f32));
+                }
+            } else if face_list.is_some() {
+                if face.len() < 3 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}: face with fewer than 3 vertices", path.display()),
+                    ));
+                }
+                // fan triangulation, assumes convex polygons
+                for w in 1..face.len() - 1 {
+                    triangles.push(Triangle {
+                        i: face[0],
+                        j: face[w],
+                        k: face[w + 1],
+                    });
+                }
+            }
+        }
+    }
+
+    if let Some(tri) = triangles
+        .iter()
+        .find(|t| t.i.max(t.j).max(t.k) >= vertices.len())
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: face index {} out of range",
+                path.display(),
+                tri.i.max(tri.j).max(tri.k)
+            ),
+        ));
+    }
+
+    if normals.is_empty() {
+        normals = compute_normals(&vertices, &triangles, false);
+    }
+
+    let bbox = compute_bbox(&vertices);
+    log::debug!("ply bbox: {bbox:?}");
+    Ok(Mesh {
+        vertices,
+        triangles,
+        normals,
+        uvs: if uvs.is_empty() { None } else { Some(uvs) },
+        colors: if colors.is_empty() {
+            None
+        } else {
+            Some(colors)
+        },
+        bbox,
+    })
+}
+
+fn read_ply<R: BufRead>(mut reader: R, path: &Path) -> io::Result<Mesh> {
+    let header = read_header(&mut reader, path)?;
+    log::debug!(
+        "ply format: {:?} elements: {:?}",
+        header.format,
+        header.elements
+    );
+
+    match header.format {
+        Format::Ascii => {
+            let mut values = AsciiReader {
+                reader,
+                path,
+                line_no: header.lines,
+                tokens: Vec::new(),
+            };
+            read_body(&mut values, &header, path)
+        }
+        Format::BinaryLittleEndian | Format::BinaryBigEndian => {
+            let mut values = BinaryReader {
+                reader,
+                path,
+                big_endian: header.format == Format::BinaryBigEndian,
+            };
+            read_body(&mut values, &header, path)
+        }
+    }
+}
+
+/// Load a Stanford PLY mesh in ASCII or binary (little or big endian) format.
+/// Vertex normals, colours and texture coordinates are used when present.
+pub fn load_ply(path: &Path) -> io::Result<Mesh> {
+    log::info!("loading ply mesh from: {}", path.display());
+    let file = File::open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to open {}: {e}", path.display())))?;
+    read_ply(BufReader::new(file), path)
+}
+
+/// Write a mesh as ASCII PLY with its normals and, when present, texture coordinates and
+/// vertex colours.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn write_ply<W: Write>(mesh: &Mesh, out: &mut W) -> io::Result<()> {
+    writeln!(out, "ply\nformat ascii 1.0\ncomment written by arrt")?;
+    writeln!(out, "element vertex {}", mesh.vertices.len())?;
+    for name in ["x", "y", "z", "nx", "ny", "nz"] {
+        writeln!(out, "property float {name}")?;
+    }
+    if mesh.uvs.is_some() {
+        writeln!(out, "property float u\nproperty float v")?;
+    }
+    if mesh.colors.is_some() {
+        writeln!(
+            out,
+            "property uchar red\nproperty uchar green\nproperty uchar blue"
+        )?;
+    }
+    writeln!(out, "element face {}", mesh.triangles.len())?;
+    writeln!(out, "property list uchar int vertex_indices\nend_header")?;
+
+    for (idx, (v, n)) in mesh.vertices.iter().zip(&mesh.normals).enumerate() {
+        write!(
+            out,
+            "{} {} {} {} {} {}",
+            v.x(),
+            v.y(),
+            v.z(),
+            n.x(),
+            n.y(),
+            n.z()
+        )?;
+        if let Some(uvs) = &mesh.uvs {
+            let (u, v) = uvs[idx];
+            write!(out, " {u} {v}")?;
+        }
+        if let Some(colors) = &mesh.colors {
+            let c = colors[idx].clamp(0.0, 1.0);
+            let byte = |f: f32| (f * 255.0).round() as u8;
+            write!(out, " {} {} {}", byte(c.r), byte(c.g), byte(c.b))?;
+        }
+        writeln!(out)?;
+    }
+    for tri in &mesh.triangles {
+        writeln!(out, "3 {} {} {}", tri.i, tri.j, tri.k)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const ASCII_QUAD: &str = "ply
+format ascii 1.0
+comment a unit quad
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+property float u
+property float v
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0 0 0
+1 0 0 0 255 0 1 0
+1 1 0 0 0 255 1 1
+0 1 0 255 255 255 0 1
+4 0 1 2 3
+";
+
+    fn parse(src: &[u8]) -> io::Result<Mesh> {
+        read_ply(Cursor::new(src), Path::new("test.ply"))
+    }
+
+    #[test]
+    fn ascii_quad() {
+        let mesh = parse(ASCII_QUAD.as_bytes()).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangles.len(), 2);
+        let colors = mesh.colors.as_ref().unwrap();
+        assert!((colors[0].r - 1.0).abs() < 1e-5);
+        assert!((colors[1].g - 1.0).abs() < 1e-5);
+        let uvs = mesh.uvs.as_ref().unwrap();
+        assert!((uvs[2].0 - 1.0).abs() < 1e-5 && (uvs[2].1 - 1.0).abs() < 1e-5);
+        assert!((mesh.normals[0].z() - 1.0).abs() < 1e-5);
+    }
+
+    fn binary_triangle(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian {
+            "binary_big_endian"
+        } else {
+            "binary_little_endian"
+        };
+        let mut data = format!(
+            "ply\nformat {format} 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+             property float z\nproperty float nx\nproperty float ny\nproperty float nz\n\
+             element face 1\nproperty list uchar uint vertex_indices\nend_header\n"
+        )
+        .into_bytes();
+        let verts = [[0.0_f32, 0.0, 0.0], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0]];
+        for v in verts {
+            for f in v.iter().chain(&[0.0, 0.0, -1.0]) {
+                data.extend(if big_endian {
+                    f.to_be_bytes()
+                } else {
+                    f.to_le_bytes()
+                });
+            }
+        }
+        data.push(3);
+        for i in 0_u32..3 {
+            data.extend(if big_endian {
+                i.to_be_bytes()
+            } else {
+                i.to_le_bytes()
+            });
+        }
+        data
+    }
+
+    #[test]
+    fn binary_little_endian() {
+        let mesh = parse(&binary_triangle(false)).unwrap();
+        assert_eq!(mesh.triangles.len(), 1);
+        assert!((mesh.vertices[1].x() - 2.0).abs() < 1e-5);
+        assert!((mesh.normals[2].z() + 1.0).abs() < 1e-5);
+        assert!(mesh.colors.is_none());
+    }
+
+    #[test]
+    fn binary_big_endian() {
+        let mesh = parse(&binary_triangle(true)).unwrap();
+        assert!((mesh.vertices[2].y() - 2.0).abs() < 1e-5);
+        assert_eq!(mesh.triangles[0].k, 2);
+    }
+
+    #[test]
+    fn truncated_ascii_reports_line() {
+        let src = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\n\
+                   property float y\nproperty float z\nend_header\n0 0 0\n1 x 0\n";
+        let err = parse(src.as_bytes()).err().unwrap();
+        assert!(err.to_string().contains("test.ply:9"), "{err}");
+    }
+
+    #[test]
+    fn rejects_negative_face_index() {
+        let src = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\n\
+                   property float y\nproperty float z\nelement face 1\n\
+                   property list uchar int vertex_indices\nend_header\n\
+                   0 0 0\n1 0 0\n0 1 0\n3 0 1 -1\n";
+        let err = parse(src.as_bytes()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("invalid face index -1"), "{err}");
+    }
+
+    #[test]
+    fn rejects_non_ply() {
+        assert!(parse(b"solid cube\n").is_err());
+    }
+
+    #[test]
+    fn written_ply_reads_back() {
+        let mesh = parse(ASCII_QUAD.as_bytes()).unwrap();
+        let mut out = Vec::new();
+        write_ply(&mesh, &mut out).unwrap();
+
+        let copy = parse(&out).unwrap();
+        assert_eq!(copy.vertices.len(), 4);
+        assert_eq!(copy.triangles.len(), 2);
+        assert!((copy.colors.as_ref().unwrap()[2].b - 1.0).abs() < 1e-5);
+        assert!((copy.uvs.as_ref().unwrap()[2].0 - 1.0).abs() < 1e-5);
+        assert!((copy.normals[0].z() - mesh.normals[0].z()).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use super::mesh::{compute_bbox, compute_normals, parse_error, parse_floats, Mesh, Triangle};
+use crate::math::Vec3;
+use crate::render::ColorRGB;
+
+/// What an SMF attribute list (`n` or `c` lines) is indexed by, as set with `bind`
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Binding {
+    Vertex,
+    Face,
+    Corner,
+}
+
+/// An attribute list and the `bind` command that applies to it, if any
+struct Attribute<T> {
+    values: Vec<T>,
+    bind: Option<(Binding, usize)>,
+}
+
+impl<T> Attribute<T> {
+    fn new() -> Self {
+        Attribute {
+            values: Vec::new(),
+            bind: None,
+        }
+    }
+
+    /// Work out the binding from the number of values when it isn't given with `bind`
+    fn binding(
+        &self,
+        name: &str,
+        counts: [(Binding, usize); 3],
+        path: &Path,
+    ) -> io::Result<Option<Binding>> {
+        if self.values.is_empty() {
+            return Ok(None);
+        }
+
+        let found = self.values.len();
+        match self.bind {
+            Some((binding, line)) => {
+                let expected = counts
+                    .iter()
+                    .find(|(b, _)| *b == binding)
+                    .map_or(0, |(_, count)| *count);
+                if found == expected {
+                    Ok(Some(binding))
+                } else {
+                    let msg = format!("bind {name} {binding:?} needs {expected} values, found {found}")
+                        .to_lowercase();
+                    Err(parse_error(path, line, &msg))
+                }
+            }
+            None => counts
+                .iter()
+                .find(|(_, count)| *count == found)
+                .map(|(binding, _)| Some(*binding))
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{}: {found} '{name}' values match neither the vertices, faces nor corners",
+                            path.display()
+                        ),
+                    )
+                }),
+        }
+    }
+
+    /// Index into the values for the given vertex, face and running corner number
+    fn index(binding: Option<Binding>, vertex: usize, face: usize, corner: usize) -> usize {
+        match binding {
+            Some(Binding::Vertex) => vertex,
+            Some(Binding::Face) => face,
+            Some(Binding::Corner) => corner,
+            None => 0,
+        }
+    }
+}
+
+fn parse_bind(args: &[&str], path: &Path, line: usize) -> io::Result<(String, Binding)> {
+    let [name, mode] = args else {
+        return Err(parse_error(
+            path,
+            line,
+            "bind needs an attribute and a mode",
+        ));
+    };
+    let binding = match *mode {
+        "vertex" => Binding::Vertex,
+        "face" => Binding::Face,
+        "corner" => Binding::Corner,
+        _ => {
+            return Err(parse_error(
+                path,
+                line,
+                &format!("unknown bind mode '{mode}'"),
+            ))
+        }
+    };
+    Ok(((*name).to_string(), binding))
+}
+
+fn read_smf<R: BufRead>(reader: R, path: &Path) -> io::Result<Mesh> {
+    let mut positions = Vec::new();
+    let mut faces: Vec<Vec<usize>> = Vec::new();
+    let mut normals: Attribute<Vec3> = Attribute::new();
+    let mut colors: Attribute<ColorRGB> = Attribute::new();
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = idx + 1;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        let Some((&keyword, args)) = tokens.split_first() else {
+            continue;
+        };
+
+        match keyword {
+            "v" => {
+                let [x, y, z] = parse_floats(args, path, line_no)?;
+                positions.push(Vec3::new(x, y, z));
+            }
+            "n" => {
+                let [x, y, z] = parse_floats(args, path, line_no)?;
+                normals.values.push(Vec3::new(x, y, z));
+            }
+            "c" => {
+                let [r, g, b] = parse_floats(args, path, line_no)?;
+                colors.values.push(ColorRGB::new(r, g, b));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    // dragon.smf has a lone 'f' line, so don't fail the whole mesh over it
+                    log::warn!(
+                        "{}:{line_no}: skipping face with fewer than 3 vertices",
+                        path.display()
+                    );
+                    continue;
+                }
+
+                let mut face = Vec::with_capacity(args.len());
+                for arg in args {
+                    let v = arg.parse::<usize>().map_err(|e| {
+                        parse_error(path, line_no, &format!("invalid index '{arg}': {e}"))
+                    })?;
+                    if v == 0 || v > positions.len() {
+                        return Err(parse_error(
+                            path,
+                            line_no,
+                            &format!("index {v} out of range"),
+                        ));
+                    }
+                    face.push(v - 1);
+                }
+                faces.push(face);
+            }
+            "bind" => match parse_bind(args, path, line_no)? {
+                (name, binding) if name == "n" => normals.bind = Some((binding, line_no)),
+                (name, binding) if name == "c" => colors.bind = Some((binding, line_no)),
+                (name, _) => {
+                    log::warn!(
+                        "{}:{line_no}: ignoring binding for '{name}'",
+                        path.display()
+                    );
+                }
+            },
+            _ if keyword.starts_with('#') => {}
+            _ => {
+                log::warn!(
+                    "{}:{line_no}: ignoring unsupported command '{keyword}'",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    let corners = faces.iter().map(Vec::len).sum();
+    let counts = [
+        (Binding::Vertex, positions.len()),
+        (Binding::Face, faces.len()),
+        (Binding::Corner, corners),
+    ];
+    let normal_binding = normals.binding("n", counts, path)?;
+    let color_binding = colors.binding("c", counts, path)?;
+
+    Ok(build_mesh(
+        &positions,
+        &faces,
+        (normal_binding, &normals.values),
+        (color_binding, &colors.values),
+    ))
+}
+
+/// Triangulate the faces. Per vertex attributes can use the positions as they are; per face
+/// or per corner ones need a vertex for each distinct combination of position and attributes.
+fn build_mesh(
+    positions: &[Vec3],
+    faces: &[Vec<usize>],
+    (normal_binding, normals): (Option<Binding>, &[Vec3]),
+    (color_binding, colors): (Option<Binding>, &[ColorRGB]),
+) -> Mesh {
+    let per_vertex = |b: Option<Binding>| b.is_none() || b == Some(Binding::Vertex);
+    let shared = per_vertex(normal_binding) && per_vertex(color_binding);
+
+    let mut vertices = Vec::new();
+    let mut vertex_normals = Vec::new();
+    let mut vertex_colors = Vec::new();
+    let mut keys: HashMap<(usize, usize, usize), usize> = HashMap::new();
+    let mut triangles = Vec::new();
+    let mut corner = 0;
+
+    if shared {
+        vertices = positions.to_vec();
+        vertex_normals = normals.to_vec();
+        vertex_colors = colors.to_vec();
+    }
+
+    for (f, face) in faces.iter().enumerate() {
+        let indices: Vec<usize> = face
+            .iter()
+            .map(|&v| {
+                corner += 1;
+                if shared {
+                    return v;
+                }
+                let n = Attribute::<Vec3>::index(normal_binding, v, f, corner - 1);
+                let c = Attribute::<ColorRGB>::index(color_binding, v, f, corner - 1);
+                *keys.entry((v, n, c)).or_insert_with(|| {
+                    vertices.push(positions[v]);
+                    if normal_binding.is_some() {
+                        vertex_normals.push(normals[n]);
+                    }
+                    if color_binding.is_some() {
+                        vertex_colors.push(colors[c]);
+                    }
+                    vertices.len() - 1
+                })
+            })
+            .collect();
+
+        // fan triangulation, assumes convex polygons
+        for w in 1..indices.len() - 1 {
+            triangles.push(Triangle {
+                i: indices[0],
+                j: indices[w],
+                k: indices[w + 1],
+            });
+        }
+    }
+
+    let normals = if normal_binding.is_some() {
+        vertex_normals
+    } else {
+        compute_normals(&vertices, &triangles, false)
+    };
+    let colors = color_binding.map(|_| vertex_colors);
+    let bbox = compute_bbox(&vertices);
+    log::debug!("mesh bbox: {bbox:?}");
+
+    Mesh {
+        vertices,
+        triangles,
+        normals,
+        uvs: None,
+        colors,
+        bbox,
+    }
+}
+
+/// Load an SMF mesh. Polygons are triangulated, and normals (`n`) and colours (`c`) may be
+/// bound per vertex, face or corner with `bind`; normals are computed if there are none.
+pub fn load_smf(path: &Path) -> io::Result<Mesh> {
+    log::info!("loading model mesh from: {}", path.display());
+    let file = File::open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to open {}: {e}", path.display())))?;
+    read_smf(BufReader::new(file), path)
+}
+
+/// Write a mesh as SMF with per vertex normals and colours. SMF has no texture coordinates, so
+/// UVs are dropped.
+pub fn write_smf<W: Write>(mesh: &Mesh, out: &mut W) -> io::Result<()> {
+    for v in &mesh.vertices {
+        writeln!(out, "v {} {} {}", v.x(), v.y(), v.z())?;
+    }
+    writeln!(out, "bind n vertex")?;
+    for n in &mesh.normals {
+        writeln!(out, "n {} {} {}", n.x(), n.y(), n.z())?;
+    }
+    if let Some(colors) = &mesh.colors {
+        writeln!(out, "bind c vertex")?;
+        for c in colors {
+            writeln!(out, "c {} {} {}", c.r, c.g, c.b)?;
+        }
+    }
+    for tri in &mesh.triangles {
+        writeln!(out, "f {} {} {}", tri.i + 1, tri.j + 1, tri.k + 1)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn parse(src: &str) -> io::Result<Mesh> {
+        read_smf(Cursor::new(src), Path::new("test.smf"))
+    }
+
+    const QUAD: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn quad_is_triangulated() {
+        let mesh = parse(&format!("{QUAD}f 1 2 3 4\n")).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangles.len(), 2);
+        assert!((mesh.normals[0].z() - 1.0).abs() < 1e-5);
+        assert!(mesh.colors.is_none());
+    }
+
+    #[test]
+    fn vertex_colors() {
+        let src = format!("{QUAD}c 1 0 0\nc 0 1 0\nc 0 0 1\nc 1 1 1\nf 1 2 3\nf 1 3 4\n");
+        let mesh = parse(&src).unwrap();
+        let colors = mesh.colors.unwrap();
+        assert_eq!(colors.len(), 4);
+        assert!((colors[2].b - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn face_bound_normals_split_vertices() {
+        let src = format!("{QUAD}bind n face\nn 0 0 1\nn 0 0 -1\nf 1 2 3\nf 1 3 4\n");
+        let mesh = parse(&src).unwrap();
+        // vertices 1 and 3 are shared by faces with different normals
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.triangles.len(), 2);
+        assert!((mesh.normals[mesh.triangles[0].i].z() - 1.0).abs() < 1e-5);
+        assert!((mesh.normals[mesh.triangles[1].i].z() + 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn bind_count_mismatch_reports_line() {
+        let src = format!("{QUAD}bind c vertex\nc 1 0 0\nf 1 2 3\n");
+        let err = parse(&src).err().unwrap();
+        assert!(err.to_string().contains("test.smf:5"), "{err}");
+    }
+
+    #[test]
+    fn bad_number_reports_line() {
+        let err = parse("v 0 0 0\nv 1 x 0\n").err().unwrap();
+        assert!(err.to_string().contains("test.smf:2"), "{err}");
+    }
+
+    #[test]
+    fn written_smf_reads_back() {
+        let src = format!("{QUAD}c 1 0 0\nc 0 1 0\nc 0 0 1\nc 1 1 1\nf 1 2 3 4\n");
+        let mesh = parse(&src).unwrap();
+        let mut out = Vec::new();
+        write_smf(&mesh, &mut out).unwrap();
+
+        let copy = parse(std::str::from_utf8(&out).unwrap()).unwrap();
+        assert_eq!(copy.vertices.len(), 4);
+        assert_eq!(copy.triangles.len(), 2);
+        assert!((copy.colors.unwrap()[1].g - 1.0).abs() < 1e-5);
+    }
+}
@@ -93,6 +93,8 @@ impl Object for Sphere {
                 material_id: self.material_id,
                 n_offset: 0.0001,
                 uv: Some((u, v)),
+                color: None,
+                object_id: 0,
             });
         }
 
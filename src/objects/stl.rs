@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::mesh::{
+    compute_bbox, compute_crease_normals, compute_normals, parse_error, Mesh, Triangle,
+};
+use crate::math::{Degree, Vec3};
+
+const HEADER_LEN: usize = 80;
+const FACET_LEN: usize = 50;
+
+/// Binary STL is at least the size its facet count needs, which is a more reliable test than
+/// the `solid` keyword since many binary exporters start their header with it too. Some
+/// exporters pad the file, so a longer one is binary unless it reads as ASCII facets.
+fn is_binary(data: &[u8]) -> bool {
+    if data.len() < HEADER_LEN + 4 {
+        return false;
+    }
+    let size = HEADER_LEN + 4 + facet_count(data) * FACET_LEN;
+    data.len() == size || (data.len() > size && !looks_ascii(data))
+}
+
+fn facet_count(data: &[u8]) -> usize {
+    u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize
+}
+
+/// Whether the file is text starting with `solid` and has facets
+fn looks_ascii(data: &[u8]) -> bool {
+    std::str::from_utf8(data)
+        .is_ok_and(|src| src.trim_start().starts_with("solid") && src.contains("facet"))
+}
+
+fn read_binary(data: &[u8]) -> Vec<[Vec3; 3]> {
+    data[HEADER_LEN + 4..]
+        .chunks_exact(FACET_LEN)
+        .take(facet_count(data))
+        .map(|facet| {
+            let read_f32 = |at: usize| {
+                f32::from_le_bytes([facet[at], facet[at + 1], facet[at + 2], facet[at + 3]])
+            };
+            // skip the 12 byte facet normal, the trailing attribute byte count is ignored
+            let vertex = |v: usize| {
+                let at = 12 + v * 12;
+                Vec3::new(read_f32(at), read_f32(at + 4), read_f32(at + 8))
+            };
+            [vertex(0), vertex(1), vertex(2)]
+        })
+        .collect()
+}
+
+fn read_ascii(src: &str, path: &Path) -> io::Result<Vec<[Vec3; 3]>> {
+    let mut facets = Vec::new();
+    let mut corners = Vec::with_capacity(3);
+
+    for (idx, line) in src.lines().enumerate() {
+        let line_no = idx + 1;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        match tokens.as_slice() {
+            ["vertex", x, y, z] => {
+                let mut v = [0.0_f32; 3];
+                for (value, token) in v.iter_mut().zip([x, y, z]) {
+                    *value = token.parse::<f32>().map_err(|e| {
+                        parse_error(path, line_no, &format!("invalid number '{token}': {e}"))
+                    })?;
+                }
+                corners.push(Vec3::new(v[0], v[1], v[2]));
+            }
+            ["vertex", ..] => return Err(parse_error(path, line_no, "vertex needs 3 values")),
+            ["endloop"] => {
+                if corners.len() != 3 {
+                    return Err(parse_error(path, line_no, "facet must have 3 vertices"));
+                }
+                facets.push([corners[0], corners[1], corners[2]]);
+                corners.clear();
+            }
+            _ => {} // solid, facet normal, outer loop, endfacet, endsolid
+        }
+    }
+
+    Ok(facets)
+}
+
+/// Merge bit-identical corner positions so that neighbouring facets share vertices
+fn weld(facets: &[[Vec3; 3]]) -> (Vec<Vec3>, Vec<Triangle>) {
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::with_capacity(facets.len());
+    let mut index: HashMap<[u32; 3], usize> = HashMap::new();
+
+    for facet in facets {
+        let mut corner = |v: Vec3| {
+            // + 0.0 folds -0.0 into 0.0 so both weld together
+            let key = [
+                (v.x() + 0.0).to_bits(),
+                (v.y() + 0.0).to_bits(),
+                (v.z() + 0.0).to_bits(),
+            ];
+            *index.entry(key).or_insert_with(|| {
+                vertices.push(v);
+                vertices.len() - 1
+            })
+        };
+        let (i, j, k) = (corner(facet[0]), corner(facet[1]), corner(facet[2]));
+        // facets collapsed by welding have no area and would only produce bad normals
+        if i != j && j != k && i != k {
+            triangles.push(Triangle { i, j, k });
+        }
+    }
+
+    (vertices, triangles)
+}
+
+fn build_mesh(facets: &[[Vec3; 3]], crease_angle: Option<Degree>) -> Mesh {
+    let (vertices, triangles) = weld(facets);
+    log::debug!(
+        "stl facets: {} welded vertices: {}",
+        facets.len(),
+        vertices.len()
+    );
+
+    let (vertices, triangles, normals) = match crease_angle {
+        Some(angle) => compute_crease_normals(&vertices, &triangles, angle),
+        None => {
+            let normals = compute_normals(&vertices, &triangles, false);
+            (vertices, triangles, normals)
+        }
+    };
+
+    let bbox = compute_bbox(&vertices);
+    log::debug!("stl bbox: {bbox:?}");
+    Mesh {
+        vertices,
+        triangles,
+        normals,
+        uvs: None,
+        colors: None,
+        bbox,
+    }
+}
+
+fn parse_stl(data: &[u8], path: &Path, crease_angle: Option<Degree>) -> io::Result<Mesh> {
+    let facets = if is_binary(data) {
+        read_binary(data)
+    } else {
+        let src = std::str::from_utf8(data).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: not a valid STL file: {e}", path.display()),
+            )
+        })?;
+        read_ascii(src, path)?
+    };
+    Ok(build_mesh(&facets, crease_angle))
+}
+
+/// Load a binary or ASCII STL file. Duplicated facet corners are welded into shared vertices so
+/// the computed normals are smooth; with a `crease_angle` sharper edges keep faceted normals.
+pub fn load_stl(path: &Path, crease_angle: Option<Degree>) -> io::Result<Mesh> {
+    log::info!("loading stl mesh from: {}", path.display());
+    let data = fs::read(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to open {}: {e}", path.display())))?;
+    parse_stl(&data, path, crease_angle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unit cube as 12 unshared facets
+    fn cube_facets() -> Vec<[Vec3; 3]> {
+        let p = |x: f32, y: f32, z: f32| Vec3::new(x, y, z);
+        let quads = [
+            [p(0., 0., 0.), p(0., 1., 0.), p(1., 1., 0.), p(1., 0., 0.)],
+            [p(0., 0., 1.), p(1., 0., 1.), p(1., 1., 1.), p(0., 1., 1.)],
+            [p(0., 0., 0.), p(1., 0., 0.), p(1., 0., 1.), p(0., 0., 1.)],
+            [p(0., 1., 0.), p(0., 1., 1.), p(1., 1., 1.), p(1., 1., 0.)],
+            [p(0., 0., 0.), p(0., 0., 1.), p(0., 1., 1.), p(0., 1., 0.)],
+            [p(1., 0., 0.), p(1., 1., 0.), p(1., 1., 1.), p(1., 0., 1.)],
+        ];
+        quads
+            .iter()
+            .flat_map(|q| [[q[0], q[1], q[2]], [q[0], q[2], q[3]]])
+            .collect()
+    }
+
+    fn binary_stl(facets: &[[Vec3; 3]]) -> Vec<u8> {
+        let mut data = vec![0_u8; HEADER_LEN];
+        data[..5].copy_from_slice(b"solid"); // binary files often start like this too
+        data.extend(u32::try_from(facets.len()).unwrap().to_le_bytes());
+        for facet in facets {
+            data.extend([0_u8; 12]);
+            for v in facet {
+                for f in [v.x(), v.y(), v.z()] {
+                    data.extend(f.to_le_bytes());
+                }
+            }
+            data.extend([0_u8; 2]);
+        }
+        data
+    }
+
+    #[test]
+    fn binary_cube_is_welded() {
+        let data = binary_stl(&cube_facets());
+        let mesh = parse_stl(&data, Path::new("cube.stl"), None).unwrap();
+        assert_eq!(mesh.vertices.len(), 8);
+        assert_eq!(mesh.triangles.len(), 12);
+        // smooth normals at a cube corner point diagonally out
+        let n = mesh.normals[0];
+        assert!(n.x() < 0.0 && n.y() < 0.0 && n.z() < 0.0);
+    }
+
+    #[test]
+    fn binary_with_trailing_bytes() {
+        let mut data = binary_stl(&cube_facets());
+        data.extend(b"\0\0\0\0padding");
+        let mesh = parse_stl(&data, Path::new("cube.stl"), None).unwrap();
+        assert_eq!(mesh.vertices.len(), 8);
+        assert_eq!(mesh.triangles.len(), 12);
+    }
+
+    #[test]
+    fn crease_angle_keeps_cube_faceted() {
+        let data = binary_stl(&cube_facets());
+        let mesh = parse_stl(&data, Path::new("cube.stl"), Some(Degree(30.0))).unwrap();
+        assert_eq!(mesh.vertices.len(), 24);
+        for n in &mesh.normals {
+            let axis = n.x().abs().max(n.y().abs()).max(n.z().abs());
+            assert!((axis - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn ascii_triangle() {
+        let src = "solid t\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\n\
+                   vertex 0 1 0\nendloop\nendfacet\nendsolid t\n";
+        let mesh = parse_stl(src.as_bytes(), Path::new("t.stl"), None).unwrap();
+        assert_eq!(mesh.vertices.len(), 3);
+        assert_eq!(mesh.triangles.len(), 1);
+        assert!((mesh.normals[0].z() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ascii_error_reports_line() {
+        let src = "solid t\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 zero 0\n";
+        let err = parse_stl(src.as_bytes(), Path::new("t.stl"), None)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("t.stl:5"), "{err}");
+    }
+}
@@ -124,8 +124,12 @@ pub fn tessellate_superquadric(config: &SuperQuadricConfig) -> Mesh {
     // and horizontally from 'left' to 'right'
     // start at y = -1 which is theta = -pi /2.
 
+    // each row gets an extra vertex at phi = pi which duplicates the one at -pi, so that
+    // u runs from 0 to 1 across the row instead of wrapping back to 0 at the seam
+
     let mut vertices = Vec::new();
     let mut normals = Vec::new();
+    let mut uvs = Vec::new();
     let mut triangles = Vec::new();
     let mut box_min = Vec3::fill(f32::MAX);
     let mut box_max = Vec3::fill(f32::MIN);
@@ -133,7 +137,7 @@ pub fn tessellate_superquadric(config: &SuperQuadricConfig) -> Mesh {
     for v in 0..=config.vslices {
         let theta_v = theta(v, config.vslices);
 
-        for h in 0..config.hslices {
+        for h in 0..=config.hslices {
             let phi_h = phi(h, config.hslices);
 
             let w = compute_point(theta_v, phi_h, config.e1, config.e2, config.a);
@@ -150,25 +154,22 @@ pub fn tessellate_superquadric(config: &SuperQuadricConfig) -> Mesh {
 
             let n = compute_normal(theta_v, phi_h, config.e1, config.e2, inverse_a);
             normals.push(n);
+
+            uvs.push((
+                (phi_h - PHI_START) / PHI_RANGE,
+                (theta_v - THETA_START) / THETA_RANGE,
+            ));
         }
     }
 
-    for v in 0..=config.vslices {
+    let row = config.hslices + 1;
+
+    for v in 0..config.vslices {
         for h in 0..config.hslices {
-            let bottom_left = (v * config.hslices + h) as usize;
-            let bottom_right = (v * config.hslices + (h + 1)) as usize % vertices.len();
-            let top_left = ((v + 1) * config.hslices + h) as usize % vertices.len();
-            let top_right = ((v + 1) * config.hslices + (h + 1)) as usize % vertices.len();
-
-            assert!(bottom_left < vertices.len());
-            assert!(bottom_right < vertices.len());
-            assert!(top_left < vertices.len());
-            assert!(
-                top_right < vertices.len(),
-                "{} {}",
-                top_right,
-                vertices.len()
-            );
+            let bottom_left = (v * row + h) as usize;
+            let bottom_right = (v * row + (h + 1)) as usize;
+            let top_left = ((v + 1) * row + h) as usize;
+            let top_right = ((v + 1) * row + (h + 1)) as usize;
 
             triangles.push(Triangle {
                 i: bottom_left,
@@ -189,6 +190,41 @@ pub fn tessellate_superquadric(config: &SuperQuadricConfig) -> Mesh {
         vertices,
         triangles,
         normals,
+        uvs: Some(uvs),
+        colors: None,
         bbox,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::length;
+
+    #[test]
+    fn uvs_span_seam_and_poles() {
+        let config = SuperQuadricConfig {
+            a: Vec3::ones(),
+            e1: 1.0,
+            e2: 1.0,
+            vslices: 4,
+            hslices: 8,
+            material: String::new(),
+            transform: Transform::default(),
+        };
+        let mesh = tessellate_superquadric(&config);
+        let uvs = mesh.uvs.unwrap();
+        assert_eq!(uvs.len(), mesh.vertices.len());
+        assert_eq!(mesh.triangles.len(), 2 * 4 * 8);
+
+        // the first and last vertex of a row share a position but not a u
+        let row = 9;
+        let first = mesh.vertices[row];
+        let last = mesh.vertices[2 * row - 1];
+        assert!(length(first - last) < 1e-5);
+        assert!(uvs[row].0.abs() < 1e-5);
+        assert!((uvs[2 * row - 1].0 - 1.0).abs() < 1e-5);
+        assert!(uvs[0].1.abs() < 1e-5);
+        assert!((uvs[uvs.len() - 1].1 - 1.0).abs() < 1e-5);
+    }
+}
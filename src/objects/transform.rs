@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::math::{Degree, Mat4, Vec3};
+use crate::math::{cross, dot, length, Degree, Mat4, Vec3};
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -8,11 +8,18 @@ pub struct Transform {
     pub translate: Vec3,
     pub rotate: Vec3,
     pub scale: Vec3,
+    /// Matrix (row major) used instead of the translate, rotate and scale above, for combined
+    /// transforms they can't express such as a rotation inside an uneven scale
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<Mat4>,
 }
 
 impl Transform {
     /// Create combined transformation matrix
     pub fn mat4(&self) -> Mat4 {
+        if let Some(m) = self.matrix {
+            return m;
+        }
         let t = Mat4::translate(&self.translate);
         let s = Mat4::scale(&self.scale);
         let rx = Mat4::rotate_x(Degree(self.rotate.x()));
@@ -24,6 +31,12 @@ impl Transform {
 
     /// Create inverse transformation matrix
     pub fn inverse(&self) -> Mat4 {
+        if let Some(m) = &self.matrix {
+            // a singular matrix is inverted like a transform with a zero scale
+            return m
+                .inverse()
+                .unwrap_or_else(|| Transform::split(m).0.inverse());
+        }
         let t = Mat4::itranslate(&self.translate);
         let s = Mat4::iscale(&self.scale);
         let rx = Mat4::irotate_x(Degree(self.rotate.x()));
@@ -32,6 +45,61 @@ impl Transform {
         let r = &(&rz * &ry) * &rx;
         &(&s * &r) * &t
     }
+
+    /// Make a transform from an affine matrix. It is split into translation, rotation and scale
+    /// when it has no shear, with a mirroring matrix getting a negative x scale, and is kept
+    /// whole otherwise.
+    pub fn from_mat4(m: &Mat4) -> Transform {
+        match Transform::split(m) {
+            (_, true) => Transform {
+                matrix: Some(*m),
+                ..Transform::default()
+            },
+            (t, false) => t,
+        }
+    }
+
+    /// Split an affine matrix into translation, rotation and scale, dropping any shear. Also
+    /// returns whether there was shear to drop.
+    fn split(m: &Mat4) -> (Transform, bool) {
+        let column = |j: usize| Vec3::new(m[0][j], m[1][j], m[2][j]);
+        let mut axes = [column(0), column(1), column(2)];
+        let mut scale = axes.map(length);
+        if dot(axes[0], cross(axes[1], axes[2])) < 0.0 {
+            scale[0] = -scale[0];
+        }
+        for (axis, s) in axes.iter_mut().zip(scale) {
+            if s != 0.0 {
+                *axis = (1.0 / s) * *axis;
+            }
+        }
+        let sheared = [(0, 1), (0, 2), (1, 2)]
+            .iter()
+            .any(|&(a, b)| dot(axes[a], axes[b]).abs() > 1e-4);
+
+        // the rotation is rx * ry * rz, whose top right element is sin(y)
+        let r = |i: usize, j: usize| axes[j][i];
+        let y = r(0, 2).clamp(-1.0, 1.0).asin();
+        let (x, z) = if r(0, 2).abs() < 1.0 - 1e-6 {
+            ((-r(1, 2)).atan2(r(2, 2)), (-r(0, 1)).atan2(r(0, 0)))
+        } else {
+            // at y = ±90 degrees x and z turn about the same axis, so z is left at 0
+            (r(2, 1).atan2(r(1, 1)), 0.0)
+        };
+
+        let t = Transform {
+            translate: column(3),
+            rotate: Vec3::new(x.to_degrees(), y.to_degrees(), z.to_degrees()),
+            scale: Vec3::new(scale[0], scale[1], scale[2]),
+            matrix: None,
+        };
+        (t, sheared)
+    }
+
+    /// Place this transform inside another one, which is applied after it
+    pub fn wrap(&mut self, outer: &Mat4) {
+        *self = Transform::from_mat4(&(outer * &self.mat4()));
+    }
 }
 
 impl Default for Transform {
@@ -40,6 +108,7 @@ impl Default for Transform {
             translate: Vec3::zeros(),
             rotate: Vec3::zeros(),
             scale: Vec3::ones(),
+            matrix: None,
         }
     }
 }
@@ -85,4 +154,60 @@ mod tests {
         assert!((result.y() - 3.0).abs() < 1e-4);
         assert!((result.z() - 4.0).abs() < 1e-4);
     }
+
+    #[test]
+    fn matrix_splits_into_translate_rotate_scale() {
+        for rotate in [Vec3::new(30.0, -50.0, 120.0), Vec3::new(10.0, 90.0, 0.0)] {
+            let t = Transform {
+                translate: Vec3::new(1.0, -2.0, 3.0),
+                rotate,
+                scale: Vec3::new(-2.0, 0.5, 3.0),
+                matrix: None,
+            };
+            let m = t.mat4();
+            let copy = Transform::from_mat4(&m).mat4();
+            for i in 0..4 {
+                for j in 0..4 {
+                    assert!((copy[i][j] - m[i][j]).abs() < 1e-4, "{rotate:?}: {i},{j}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wrapped_transform_applies_parent_last() {
+        let mut t = Transform {
+            scale: Vec3::new(2.0, 2.0, 2.0),
+            ..Transform::default()
+        };
+        t.wrap(&Mat4::translate(&Vec3::new(1.0, 0.0, 0.0)));
+        let result = apply(&t, Vec3::new(1.0, 1.0, 1.0));
+        assert!((result.x() - 3.0).abs() < 1e-4);
+        assert!((result.y() - 2.0).abs() < 1e-4);
+
+        let back = (&t.inverse() * Vec4::from_vec3(result, 1.0)).to_vec3();
+        assert!((back.x() - 1.0).abs() < 1e-4);
+        assert!((back.z() - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rotation_inside_uneven_scale_keeps_shear() {
+        let mut t = Transform {
+            rotate: Vec3::new(0.0, 0.0, 45.0),
+            ..Transform::default()
+        };
+        let outer = Mat4::scale(&Vec3::new(2.0, 1.0, 1.0));
+        let expected = &outer * &t.mat4();
+        t.wrap(&outer);
+        assert!(t.matrix.is_some());
+
+        let p = Vec4::from_vec3(Vec3::new(1.0, 2.0, 3.0), 1.0);
+        let result = apply(&t, p.to_vec3());
+        let want = (&expected * p).to_vec3();
+        for i in 0..3 {
+            assert!((result[i] - want[i]).abs() < 1e-4, "{result:?} != {want:?}");
+        }
+        let back = (&t.inverse() * Vec4::from_vec3(result, 1.0)).to_vec3();
+        assert!((back.y() - 2.0).abs() < 1e-4);
+    }
 }
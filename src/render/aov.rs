@@ -0,0 +1,218 @@
+use clap::ValueEnum;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::color::WorkingSpace;
+use super::crop::CropWindow;
+use super::output::ExrLayer;
+use super::tracer::RayTracer;
+use super::{ColorRGB, Framebuffer};
+
+/// Arbitrary output variables: auxiliary images filled from the first surface hit by each
+/// pixel's primary ray. Pixels whose ray misses are 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum Aov {
+    /// Camera space depth along the view direction
+    Depth,
+    /// World space shading normal
+    Normal,
+    /// World space hit position
+    Position,
+    /// Diffuse colour after vertex colours and textures
+    Albedo,
+    /// Material index counting from 1
+    MaterialId,
+    /// Position in the scene's object list counting from 1
+    ObjectId,
+    /// Texture coordinates
+    Uv,
+    /// Fraction of light samples that reach the surface
+    Shadow,
+}
+
+impl Aov {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Albedo => "albedo",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::Uv => "uv",
+            Aov::Shadow => "shadow",
+        }
+    }
+
+    /// EXR channel names, taken from the red, green and blue values in order
+    #[must_use]
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::MaterialId | Aov::ObjectId => &["id"],
+            Aov::Uv => &["U", "V"],
+            Aov::Shadow => &["Y"],
+        }
+    }
+
+    /// The buffer as a named EXR layer with the AOV's channels
+    #[must_use]
+    pub fn exr_layer(self, fb: &Framebuffer) -> ExrLayer {
+        let channels = self
+            .channels()
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let values = fb.data.iter().map(|c| [c.r, c.g, c.b][i]).collect();
+                ((*name).to_string(), values)
+            })
+            .collect();
+        ExrLayer {
+            name: Some(self.name().to_string()),
+            channels,
+        }
+    }
+
+    /// The buffer as an image file: single values are repeated as grey, and for 8-bit
+    /// formats the values are scaled to be viewable. Albedo is a colour and is encoded like
+    /// the main image.
+    #[must_use]
+    pub fn image(
+        self,
+        fb: &Framebuffer,
+        eight_bit: bool,
+        working_space: WorkingSpace,
+    ) -> Framebuffer {
+        let single = self.channels().len() == 1;
+        let mut data: Vec<ColorRGB> = fb
+            .data
+            .iter()
+            .map(|c| if single { ColorRGB::fill(c.r) } else { *c })
+            .collect();
+
+        if eight_bit {
+            match self {
+                Aov::Normal => data
+                    .iter_mut()
+                    .for_each(|c| *c = (*c + ColorRGB::white()) * 0.5),
+                Aov::Depth | Aov::MaterialId | Aov::ObjectId => {
+                    let max = data.iter().map(|c| c.r).fold(0.0_f32, f32::max);
+                    if max > 0.0 {
+                        data.iter_mut().for_each(|c| *c = *c / max);
+                    }
+                }
+                Aov::Position => normalize_bounds(&mut data),
+                Aov::Albedo if working_space == WorkingSpace::LinearSrgb => {
+                    data.iter_mut().for_each(|c| *c = c.to_srgb());
+                }
+                Aov::Albedo | Aov::Uv | Aov::Shadow => {}
+            }
+        }
+
+        Framebuffer {
+            data,
+            ..Framebuffer::new(fb.width, fb.height)
+        }
+    }
+}
+
+/// Scale each channel so the values span [0,1]
+fn normalize_bounds(data: &mut [ColorRGB]) {
+    let lo = data.iter().fold(ColorRGB::fill(f32::MAX), |a, c| {
+        ColorRGB::new(a.r.min(c.r), a.g.min(c.g), a.b.min(c.b))
+    });
+    let hi = data.iter().fold(ColorRGB::fill(f32::MIN), |a, c| {
+        ColorRGB::new(a.r.max(c.r), a.g.max(c.g), a.b.max(c.b))
+    });
+    let scale = |v: f32, lo: f32, hi: f32| if hi > lo { (v - lo) / (hi - lo) } else { 0.0 };
+    for c in data {
+        *c = ColorRGB::new(
+            scale(c.r, lo.r, hi.r),
+            scale(c.g, lo.g, hi.g),
+            scale(c.b, lo.b, hi.b),
+        );
+    }
+}
+
+/// Trace the primary ray of every pixel in `window` again and fill a buffer for each AOV
+pub(super) fn render_aovs(
+    tracer: &RayTracer,
+    width: usize,
+    height: usize,
+    window: CropWindow,
+    aovs: &[Aov],
+) -> Vec<(Aov, Framebuffer)> {
+    let mut buffers: Vec<(Aov, Framebuffer)> = aovs
+        .iter()
+        .map(|&aov| (aov, Framebuffer::new(width, height)))
+        .collect();
+
+    let samples: Vec<Vec<ColorRGB>> = (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let (j, k) = (i % width, i / width);
+            let hit = window
+                .contains(j, k)
+                .then(|| tracer.primary_hit(j, k))
+                .flatten();
+            match hit {
+                Some(surfel) => aovs
+                    .iter()
+                    .map(|&aov| tracer.aov_value(aov, &surfel))
+                    .collect(),
+                None => vec![ColorRGB::black(); aovs.len()],
+            }
+        })
+        .collect();
+
+    for (i, values) in samples.into_iter().enumerate() {
+        for ((_, fb), value) in buffers.iter_mut().zip(values) {
+            fb.data[i] = value;
+        }
+    }
+    buffers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(values: &[ColorRGB]) -> Framebuffer {
+        let mut fb = Framebuffer::new(values.len(), 1);
+        fb.data.copy_from_slice(values);
+        fb
+    }
+
+    #[test]
+    fn exr_layers_are_named_by_aov() {
+        let fb = buffer(&[ColorRGB::new(0.25, 0.5, 0.0)]);
+        let layer = Aov::Uv.exr_layer(&fb);
+        assert_eq!(layer.name.as_deref(), Some("uv"));
+        assert_eq!(layer.channels.len(), 2);
+        assert_eq!(layer.channels[0], ("U".to_string(), vec![0.25]));
+        assert_eq!(layer.channels[1], ("V".to_string(), vec![0.5]));
+    }
+
+    #[test]
+    fn depth_is_scaled_for_eight_bit() {
+        let fb = buffer(&[ColorRGB::fill(2.0), ColorRGB::fill(8.0), ColorRGB::black()]);
+        let image = Aov::Depth.image(&fb, true, WorkingSpace::LinearSrgb);
+        assert!((image.data[0].g - 0.25).abs() < 1e-5);
+        assert!((image.data[1].b - 1.0).abs() < 1e-5);
+
+        let raw = Aov::Depth.image(&fb, false, WorkingSpace::LinearSrgb);
+        assert!((raw.data[1].g - 8.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn normals_are_mapped_to_unit_range() {
+        let fb = buffer(&[ColorRGB::new(-1.0, 0.0, 1.0)]);
+        let image = Aov::Normal.image(&fb, true, WorkingSpace::LinearSrgb);
+        assert!(image.data[0].r.abs() < 1e-5);
+        assert!((image.data[0].g - 0.5).abs() < 1e-5);
+        assert!((image.data[0].b - 1.0).abs() < 1e-5);
+    }
+}
@@ -12,6 +12,37 @@ fn clamp(val: f32, lo: f32, hi: f32) -> f32 {
     }
 }
 
+/// sRGB electro-optical transfer function: decode an encoded value to linear
+#[must_use]
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.040_45 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// sRGB opto-electronic transfer function: encode a linear value for display
+#[must_use]
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The space lighting is computed in
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WorkingSpace {
+    /// Linear values with sRGB primaries: colour textures are decoded when loaded and 8-bit
+    /// output is sRGB encoded
+    #[default]
+    LinearSrgb,
+    /// sRGB encoded values are lit as they are, without decoding or encoding
+    Srgb,
+}
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct ColorRGB {
     pub r: f32,
@@ -55,6 +86,22 @@ impl ColorRGB {
         ColorRGB::new(0.0_f32, 0.0_f32, 1.0_f32)
     }
 
+    /// Apply the sRGB OETF to each channel
+    #[must_use]
+    pub fn to_srgb(&self) -> Self {
+        ColorRGB::new(
+            linear_to_srgb(self.r),
+            linear_to_srgb(self.g),
+            linear_to_srgb(self.b),
+        )
+    }
+
+    /// Relative luminance with Rec. 709 weights
+    #[must_use]
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
     #[must_use]
     pub fn clamp(&self, lo: f32, hi: f32) -> Self {
         ColorRGB {
@@ -64,14 +111,27 @@ impl ColorRGB {
         }
     }
 
-    // Colors are clamped to [0,1] before calling this; truncation and sign loss are expected.
+    // Colors are clamped to [0,1] here; truncation and sign loss are expected.
     #[must_use]
     #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
     pub fn to_irgb(&self) -> [u8; 3] {
+        let c = self.clamp(0.0, 1.0);
+        [
+            (c.r * 255.0).round() as u8,
+            (c.g * 255.0).round() as u8,
+            (c.b * 255.0).round() as u8,
+        ]
+    }
+
+    /// Like `to_irgb` with 16 bits per channel
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn to_irgb16(&self) -> [u16; 3] {
+        let c = self.clamp(0.0, 1.0);
         [
-            (self.r * 255.0).round() as u8,
-            (self.g * 255.0).round() as u8,
-            (self.b * 255.0).round() as u8,
+            (c.r * 65535.0).round() as u16,
+            (c.g * 65535.0).round() as u16,
+            (c.b * 65535.0).round() as u16,
         ]
     }
 }
@@ -173,6 +233,22 @@ mod tests {
         assert_eq!(ColorRGB::red().to_irgb(), [255, 0, 0]);
     }
 
+    #[test]
+    fn bright_to_irgb() {
+        assert_eq!(ColorRGB::new(3.0, 0.5, -1.0).to_irgb(), [255, 128, 0]);
+    }
+
+    #[test]
+    fn srgb_round_trip() {
+        for i in 0..=255_u8 {
+            let v = f32::from(i) / 255.0;
+            assert!((linear_to_srgb(srgb_to_linear(v)) - v).abs() < 1e-5, "{i}");
+        }
+        // mid grey
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+        assert_eq!(ColorRGB::fill(0.2159).to_srgb().to_irgb(), [128, 128, 128]);
+    }
+
     #[test]
     fn clamp_above_one() {
         let c = ColorRGB::new(2.0, 0.5, -0.5).clamp(0.0, 1.0);
@@ -0,0 +1,308 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::color::WorkingSpace;
+use super::output::OutputConfig;
+use super::tonemap::ToneMap;
+use super::{ColorRGB, Framebuffer};
+
+/// A rectangle of pixels from `x0`, `y0` at the top left up to but not including `x1`, `y1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CropWindow {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl CropWindow {
+    #[must_use]
+    pub fn full(width: usize, height: usize) -> Self {
+        CropWindow {
+            x0: 0,
+            y0: 0,
+            x1: width,
+            y1: height,
+        }
+    }
+
+    /// The pixels covering `[x0, y0, x1, y1]` given as fractions of the image size
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn from_normalized(rect: [f32; 4], width: usize, height: usize) -> io::Result<Self> {
+        let [x0, y0, x1, y1] = rect;
+        if !(0.0..=1.0).contains(&x0)
+            || !(0.0..=1.0).contains(&y0)
+            || !(0.0..=1.0).contains(&x1)
+            || !(0.0..=1.0).contains(&y1)
+        {
+            return Err(invalid(format!(
+                "crop window {rect:?} must be fractions between 0 and 1"
+            )));
+        }
+        let (w, h) = (width as f32, height as f32);
+        CropWindow {
+            x0: (x0 * w).floor() as usize,
+            y0: (y0 * h).floor() as usize,
+            x1: (x1 * w).ceil() as usize,
+            y1: (y1 * h).ceil() as usize,
+        }
+        .check(width, height)
+    }
+
+    /// Check the window is inside a `width` by `height` image and not empty
+    pub fn check(self, width: usize, height: usize) -> io::Result<Self> {
+        if self.x0 >= self.x1 || self.y0 >= self.y1 || self.x1 > width || self.y1 > height {
+            return Err(invalid(format!(
+                "crop window {self} is empty or outside the {width}x{height} image"
+            )));
+        }
+        Ok(self)
+    }
+
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+
+    #[must_use]
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+
+    #[must_use]
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x0..self.x1).contains(&x) && (self.y0..self.y1).contains(&y)
+    }
+
+    /// The corners of the window's pixels, in a grid one larger than the image each way
+    #[must_use]
+    pub fn corners(&self) -> Self {
+        CropWindow {
+            x0: self.x0,
+            y0: self.y0,
+            x1: self.x1 + 1,
+            y1: self.y1 + 1,
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+impl fmt::Display for CropWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x0, self.y0, self.x1, self.y1)
+    }
+}
+
+/// Parses `x0,y0,x1,y1` in pixels
+impl FromStr for CropWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{s}: {e}"))?;
+        let [x0, y0, x1, y1] = values[..] else {
+            return Err(format!("{s}: expected x0,y0,x1,y1"));
+        };
+        Ok(CropWindow { x0, y0, x1, y1 })
+    }
+}
+
+impl Framebuffer {
+    /// The pixels inside `window` as a new framebuffer, with the AOVs cropped the same way
+    #[must_use]
+    pub fn cropped(&self, window: CropWindow) -> Framebuffer {
+        let mut fb = Framebuffer::new(window.width(), window.height());
+        for y in 0..fb.height {
+            let src = (window.y0 + y) * self.width + window.x0;
+            let dst = y * fb.width;
+            fb.data[dst..dst + fb.width].copy_from_slice(&self.data[src..src + fb.width]);
+            fb.alpha[dst..dst + fb.width].copy_from_slice(&self.alpha[src..src + fb.width]);
+        }
+        fb.aovs = self
+            .aovs
+            .iter()
+            .map(|(aov, buffer)| (*aov, buffer.cropped(window)))
+            .collect();
+        fb.metadata.clone_from(&self.metadata);
+        fb
+    }
+
+    /// Clear everything outside `window` to black with no coverage
+    pub fn clear_outside(&mut self, window: CropWindow) {
+        for (i, (c, a)) in self.data.iter_mut().zip(&mut self.alpha).enumerate() {
+            if !window.contains(i % self.width, i / self.width) {
+                *c = ColorRGB::black();
+                *a = 0.0;
+            }
+        }
+        for (_, buffer) in &mut self.aovs {
+            buffer.clear_outside(window);
+        }
+    }
+
+    /// Copy `region` into this framebuffer with its top left corner at `x0`, `y0`
+    pub fn paste(&mut self, region: &Framebuffer, x0: usize, y0: usize) {
+        assert!(x0 + region.width <= self.width && y0 + region.height <= self.height);
+        for y in 0..region.height {
+            let src = y * region.width;
+            let dst = (y0 + y) * self.width + x0;
+            self.data[dst..dst + region.width]
+                .copy_from_slice(&region.data[src..src + region.width]);
+            self.alpha[dst..dst + region.width]
+                .copy_from_slice(&region.alpha[src..src + region.width]);
+        }
+    }
+}
+
+/// Read an image's stored values: linear for float formats and display values otherwise
+fn load_image(path: &Path) -> io::Result<Framebuffer> {
+    let image = image::open(path)
+        .map_err(|e| io::Error::other(format!("failed to read {}: {e}", path.display())))?
+        .to_rgba32f();
+    let mut fb = Framebuffer::new(image.width() as usize, image.height() as usize);
+    for (i, p) in image.pixels().enumerate() {
+        fb.data[i] = ColorRGB::new(p[0], p[1], p[2]);
+        fb.alpha[i] = p[3];
+    }
+    Ok(fb)
+}
+
+/// Write the rendered `region` over the pixels of `window` in the existing image at `path`.
+/// 8-bit images are patched with display values so the pixels around the region keep theirs.
+pub fn patch_image(
+    path: &Path,
+    region: &Framebuffer,
+    window: CropWindow,
+    full: (usize, usize),
+    config: &OutputConfig,
+) -> io::Result<()> {
+    let mut image = load_image(path)?;
+    if (image.width, image.height) != full {
+        return Err(invalid(format!(
+            "{} is {}x{}, not the {}x{} render it would be patched into",
+            path.display(),
+            image.width,
+            image.height,
+            full.0,
+            full.1
+        )));
+    }
+    if !region.aovs.is_empty() {
+        log::warn!("AOVs are not patched into {}", path.display());
+    }
+
+    let format = config.image_format(path)?;
+    let write_config = OutputConfig {
+        aovs: Vec::new(),
+        ..config.clone()
+    };
+    if format.is_float() {
+        image.paste(region, window.x0, window.y0);
+        image.metadata.clone_from(&region.metadata);
+        image.save_image(path, &write_config)
+    } else {
+        let display = region.display_encoded(config);
+        image.paste(&display, window.x0, window.y0);
+        image.metadata.clone_from(&region.metadata);
+        // the image now holds display values with straight alpha, written as they are
+        let display_config = OutputConfig {
+            exposure: 0.0,
+            tone_map: ToneMap::Clamp,
+            working_space: WorkingSpace::Srgb,
+            keep_background: true,
+            ..write_config
+        };
+        image.save_image(path, &display_config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pixel_window() {
+        let window: CropWindow = "8, 4,24,20".parse().unwrap();
+        assert_eq!(
+            window,
+            CropWindow {
+                x0: 8,
+                y0: 4,
+                x1: 24,
+                y1: 20
+            }
+        );
+        assert_eq!(window.to_string(), "8,4,24,20");
+        assert!("1,2,3".parse::<CropWindow>().is_err());
+        assert!("a,2,3,4".parse::<CropWindow>().is_err());
+    }
+
+    #[test]
+    fn normalized_window_covers_partial_pixels() {
+        let window = CropWindow::from_normalized([0.25, 0.1, 0.5, 0.55], 64, 10).unwrap();
+        assert_eq!(window.to_string(), "16,1,32,6");
+        assert!(CropWindow::from_normalized([0.5, 0.0, 0.5, 1.0], 64, 64).is_err());
+        assert!(CropWindow::from_normalized([0.0, 0.0, 1.5, 1.0], 64, 64).is_err());
+    }
+
+    #[test]
+    fn window_outside_image_is_an_error() {
+        let window: CropWindow = "0,0,65,10".parse().unwrap();
+        assert!(window.check(64, 64).is_err());
+        assert!(window.check(65, 10).is_ok());
+    }
+
+    #[test]
+    fn crop_and_paste_round_trip() {
+        let mut fb = Framebuffer::new(4, 3);
+        fb.set_color(2, 1, &ColorRGB::red());
+        let window: CropWindow = "1,1,3,3".parse().unwrap();
+        let region = fb.cropped(window);
+        assert_eq!((region.width, region.height), (2, 2));
+        assert!((region.get_color(1, 0).r - 1.0).abs() < 1e-5);
+
+        let mut target = Framebuffer::new(4, 3);
+        target.paste(&region, 1, 1);
+        assert!((target.get_color(2, 1).r - 1.0).abs() < 1e-5);
+        assert!(target.get_color(3, 1).r.abs() < 1e-5);
+    }
+
+    #[test]
+    fn patch_keeps_pixels_outside_the_window() {
+        let path = std::env::temp_dir().join("arrt_crop_patch.png");
+        let mut base = Framebuffer::new(4, 4);
+        base.data.fill(ColorRGB::fill(0.5));
+        let config = OutputConfig {
+            tone_map: ToneMap::Reinhard,
+            ..OutputConfig::default()
+        };
+        base.save_image(&path, &config).unwrap();
+        let before = image::open(&path).unwrap().to_rgb8();
+
+        let mut region = Framebuffer::new(2, 2);
+        region.data.fill(ColorRGB::red());
+        let window: CropWindow = "2,0,4,2".parse().unwrap();
+        patch_image(&path, &region, window, (4, 4), &config).unwrap();
+
+        let after = image::open(&path).unwrap().to_rgb8();
+        assert_eq!(after.get_pixel(0, 3), before.get_pixel(0, 3));
+        assert_eq!(after.get_pixel(1, 1), before.get_pixel(1, 1));
+        let p = after.get_pixel(3, 0);
+        assert!(p[0] > 150 && p[1] == 0, "{p:?}");
+
+        let e = patch_image(&path, &region, window, (8, 8), &config).unwrap_err();
+        assert!(e.to_string().contains("4x4"), "{e}");
+    }
+}
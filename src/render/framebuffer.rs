@@ -1,18 +1,26 @@
 #![allow(clippy::missing_panics_doc)]
 
-use std::fs::File;
-use std::io::BufWriter;
+use std::io;
 use std::path::Path;
 use std::time::Instant;
 
-use rayon::prelude::*;
-
-use super::color::ColorRGB;
+use super::aov::Aov;
+use super::color::{ColorRGB, WorkingSpace};
+use super::metadata::Metadata;
+use super::output::{self, OutputConfig};
+use super::tonemap::ToneMap;
+use crate::args::{AlphaMode, ImageFormat};
 
 pub struct Framebuffer {
     pub width: usize,
     pub height: usize,
     pub data: Vec<ColorRGB>,
+    /// Coverage of each pixel, 1 where opaque
+    pub alpha: Vec<f32>,
+    /// Auxiliary buffers written alongside the image
+    pub aovs: Vec<(Aov, Framebuffer)>,
+    /// How the image was rendered, written into PNG and EXR files
+    pub metadata: Option<Metadata>,
 }
 
 impl Framebuffer {
@@ -35,25 +43,79 @@ impl Framebuffer {
             width,
             height,
             data,
+            alpha: vec![1.0; width * height],
+            aovs: Vec::new(),
+            metadata: None,
         }
     }
 
-    pub fn save_image(&self, path: &Path) {
+    /// Write to `path` in the configured format, or the one given by its extension and
+    /// PNG when it has none. Extensions we can't write are an error.
+    pub fn save_image(&self, path: &Path, config: &OutputConfig) -> io::Result<()> {
         let start = Instant::now();
-        let file = File::create(path).unwrap();
-        let bufwriter = &mut BufWriter::new(file);
-
-        // Framebuffer::new asserts these fit in u32.
-        #[allow(clippy::cast_possible_truncation)]
-        let mut encoder = png::Encoder::new(bufwriter, self.width as u32, self.height as u32);
-        encoder.set_color(png::ColorType::Rgb);
-        encoder.set_depth(png::BitDepth::Eight);
-        let mut writer = encoder.write_header().unwrap();
-
-        let srgb: Vec<u8> = self.data.par_iter().flat_map(ColorRGB::to_irgb).collect();
-        writer.write_image_data(&srgb).unwrap();
+        let format = config.image_format(path)?;
+        match format {
+            ImageFormat::Png => output::write_png(self, path, config),
+            ImageFormat::Png16 => output::write_png16(self, path, config),
+            ImageFormat::Jpeg => output::write_jpeg(self, path, config),
+            ImageFormat::Ppm => output::write_ppm(self, path, config),
+            ImageFormat::Webp => output::write_webp(self, path, config),
+            ImageFormat::Pfm => output::write_pfm(self, path),
+            ImageFormat::Tiff => output::write_tiff(self, path),
+            ImageFormat::Hdr => output::write_hdr(self, path),
+            ImageFormat::Exr => {
+                let alpha = config.alpha != AlphaMode::Off;
+                let layers: Vec<_> = std::iter::once(self.exr_layer(alpha))
+                    .chain(self.aovs.iter().map(|(aov, fb)| aov.exr_layer(fb)))
+                    .collect();
+                output::write_exr(
+                    path,
+                    self.width,
+                    self.height,
+                    &layers,
+                    config.exr_sample,
+                    self.metadata.as_ref(),
+                )
+            }
+        }?;
+        if config.alpha != AlphaMode::Off && !format.has_alpha() {
+            log::warn!("{} has no alpha channel, writing RGB", path.display());
+        }
         let stop = Instant::now();
         log::info!("wrote {} in {:?}", path.display(), stop - start);
+
+        if format != ImageFormat::Exr {
+            self.save_aov_images(path, format, config)?;
+        }
+        Ok(())
+    }
+
+    /// Write each AOV next to the image as `<name>.<aov>.<ext>`, as data without tone
+    /// mapping or encoding
+    fn save_aov_images(
+        &self,
+        path: &Path,
+        format: ImageFormat,
+        config: &OutputConfig,
+    ) -> io::Result<()> {
+        let extension = path
+            .extension()
+            .map_or(format.extension().into(), |ext| ext.to_string_lossy());
+        let aov_config = OutputConfig {
+            format: Some(format),
+            exposure: 0.0,
+            tone_map: ToneMap::Clamp,
+            working_space: WorkingSpace::Srgb,
+            aovs: Vec::new(),
+            alpha: AlphaMode::Off,
+            ..config.clone()
+        };
+        for (aov, fb) in &self.aovs {
+            let image = aov.image(fb, !format.is_float(), config.working_space);
+            let aov_path = path.with_extension(format!("{}.{extension}", aov.name()));
+            image.save_image(&aov_path, &aov_config)?;
+        }
+        Ok(())
     }
 
     pub fn set_color(&mut self, x: usize, y: usize, color: &ColorRGB) {
@@ -70,4 +132,11 @@ impl Framebuffer {
         let idx: usize = (y * self.width) + x;
         self.data[idx]
     }
+
+    #[must_use]
+    pub fn get_alpha(&self, x: usize, y: usize) -> f32 {
+        assert!(x < self.width, "{} {}", x, self.width);
+        assert!(y < self.height, "{} {}", y, self.height);
+        self.alpha[(y * self.width) + x]
+    }
 }
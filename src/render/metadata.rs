@@ -0,0 +1,221 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use exr::meta::attribute::{AttributeValue, Text};
+use exr::meta::MetaData;
+
+use super::crop::CropWindow;
+use crate::args::{ImageFormat, InfoArgs};
+
+/// Written as PNG `Software` and the EXR `software` attribute
+pub const SOFTWARE: &str = concat!("arrt ", env!("CARGO_PKG_VERSION"));
+
+/// How an image was rendered, stored in PNG text chunks and EXR header attributes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    /// Scene file the image was rendered from
+    pub scene: Option<PathBuf>,
+    /// FNV-1a hash of the scene file
+    pub scene_hash: Option<u64>,
+    pub width: usize,
+    pub height: usize,
+    pub sampling_depth: u8,
+    /// Samples per pixel of a progressive render, 0 for an adaptive one
+    pub passes: u32,
+    /// The pixels that were rendered when not the whole image
+    pub crop: Option<CropWindow>,
+    pub setup_time: Duration,
+    pub render_time: Duration,
+    pub ray_count: u64,
+    pub hit_count: u64,
+}
+
+impl Metadata {
+    /// Attribute names and values, other than the software name
+    #[must_use]
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        if let Some(scene) = &self.scene {
+            entries.push(("arrt.scene", scene.display().to_string()));
+        }
+        if let Some(hash) = self.scene_hash {
+            entries.push(("arrt.scene_hash", format!("fnv1a64:{hash:016x}")));
+        }
+        entries.push(("arrt.resolution", format!("{}x{}", self.width, self.height)));
+        if self.passes > 0 {
+            entries.push(("arrt.passes", self.passes.to_string()));
+        } else {
+            entries.push(("arrt.sampling_depth", self.sampling_depth.to_string()));
+        }
+        if let Some(crop) = self.crop {
+            entries.push(("arrt.crop", crop.to_string()));
+        }
+        entries.extend([
+            (
+                "arrt.setup_time",
+                format!("{:.3}s", self.setup_time.as_secs_f64()),
+            ),
+            (
+                "arrt.render_time",
+                format!("{:.3}s", self.render_time.as_secs_f64()),
+            ),
+            ("arrt.ray_count", self.ray_count.to_string()),
+            ("arrt.hit_count", self.hit_count.to_string()),
+        ]);
+        entries
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect()
+    }
+
+    /// Custom attributes for an EXR header, skipping values that aren't Latin-1
+    #[must_use]
+    pub fn exr_attributes(&self) -> Vec<(Text, AttributeValue)> {
+        self.entries()
+            .into_iter()
+            .filter_map(|(k, v)| {
+                Some((
+                    Text::new_or_none(k)?,
+                    AttributeValue::Text(Text::new_or_none(v)?),
+                ))
+            })
+            .collect()
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds unlike the std hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Hash of a scene file's contents, identifying the version that was rendered
+pub fn hash_file(path: &Path) -> io::Result<u64> {
+    std::fs::read(path).map(|bytes| fnv1a(&bytes))
+}
+
+fn read_error(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("failed to read {}: {e}", path.display()),
+    )
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    File::open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to open {}: {e}", path.display())))
+}
+
+/// The text chunks of a PNG file
+fn read_png_text(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let decoder = png::Decoder::new(BufReader::new(open(path)?));
+    let reader = decoder.read_info().map_err(|e| read_error(path, e))?;
+    let info = reader.info();
+    let mut entries: Vec<_> = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+        .collect();
+    for chunk in &info.utf8_text {
+        let text = chunk.get_text().map_err(|e| read_error(path, e))?;
+        entries.push((chunk.keyword.clone(), text));
+    }
+    Ok(entries)
+}
+
+/// The software name and text attributes of an EXR file's first header
+fn read_exr_text(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let meta = MetaData::read_from_file(path, false).map_err(|e| read_error(path, e))?;
+    let Some(header) = meta.headers.first() else {
+        return Ok(Vec::new());
+    };
+    let attributes = &header.own_attributes;
+    let mut entries = Vec::new();
+    if let Some(software) = &attributes.software_name {
+        entries.push(("software".to_string(), software.to_string()));
+    }
+    let mut other: Vec<_> = attributes
+        .other
+        .iter()
+        .filter_map(|(k, v)| match v {
+            AttributeValue::Text(text) => Some((k.to_string(), text.to_string())),
+            _ => None,
+        })
+        .collect();
+    other.sort();
+    entries.extend(other);
+    Ok(entries)
+}
+
+/// The metadata stored in a PNG or EXR image, as attribute names and values
+pub fn read_metadata(path: &Path) -> io::Result<Vec<(String, String)>> {
+    match ImageFormat::from_path(path) {
+        Some(ImageFormat::Png) => read_png_text(path),
+        Some(ImageFormat::Exr) => read_exr_text(path),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}: metadata is only stored in .png and .exr images",
+                path.display()
+            ),
+        )),
+    }
+}
+
+/// Print the metadata of an image, one `name: value` per line
+pub fn info_with_args(args: &InfoArgs) -> io::Result<()> {
+    let entries = read_metadata(&args.image)?;
+    if entries.is_empty() {
+        println!("{}: no metadata", args.image.display());
+    }
+    for (name, value) in entries {
+        println!("{name}: {value}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_matches_reference() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn entries_format_values() {
+        let metadata = Metadata {
+            scene: Some(PathBuf::from("scenes/sphere.yaml")),
+            scene_hash: Some(0xab),
+            width: 64,
+            height: 32,
+            sampling_depth: 2,
+            render_time: Duration::from_millis(1500),
+            ray_count: 10,
+            ..Metadata::default()
+        };
+        let entries = metadata.entries();
+        let get = |key: &str| {
+            entries
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(get("arrt.scene"), Some("scenes/sphere.yaml"));
+        assert_eq!(get("arrt.scene_hash"), Some("fnv1a64:00000000000000ab"));
+        assert_eq!(get("arrt.resolution"), Some("64x32"));
+        assert_eq!(get("arrt.render_time"), Some("1.500s"));
+        assert_eq!(get("arrt.ray_count"), Some("10"));
+    }
+
+    #[test]
+    fn other_formats_have_no_metadata() {
+        let e = read_metadata(Path::new("image.jpg")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,7 +1,13 @@
+pub mod aov;
 pub mod color;
+pub mod crop;
 pub mod framebuffer;
+pub mod metadata;
+pub mod output;
 pub mod perlin;
+pub mod progressive;
 pub mod texture;
+pub mod tonemap;
 
 mod pixel;
 mod shade;
@@ -9,7 +15,10 @@ mod tracer;
 
 pub use color::ColorRGB;
 pub use framebuffer::Framebuffer;
+pub use output::OutputConfig;
 
+use std::io;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use rayon::current_num_threads;
@@ -18,7 +27,10 @@ use rayon::prelude::*;
 use crate::args::CliArgs;
 use crate::scene::Scene;
 
+use crop::CropWindow;
+use metadata::Metadata;
 use pixel::Pixel;
+use progressive::{Progressive, WriteSchedule};
 use tracer::{RayTracer, TraceContext, TraceResult};
 
 /// A 2d view plane coordinate
@@ -28,51 +40,48 @@ pub struct XYCoord {
     pub y: f32,
 }
 
-pub fn render_scene(scene: Scene, anti_aliasing_depth: u8) -> Framebuffer {
+/// Render with the alpha and AOV settings of `config`, inside `crop` when given. A cropped
+/// render is the size of its window unless `crop_full_size` is set.
+pub fn render_scene(
+    scene: Scene,
+    anti_aliasing_depth: u8,
+    crop: Option<CropWindow>,
+    config: &OutputConfig,
+) -> io::Result<Framebuffer> {
     log::info!(
         "bg color {:?} num threads {}",
         scene.bgcolor(),
         current_num_threads()
     );
     let setup_start = Instant::now();
-    let mut fb = Framebuffer::new(scene.width() as usize, scene.height() as usize);
-    let tracer = RayTracer::new(scene);
+    let (width, height) = (scene.width() as usize, scene.height() as usize);
+    let window = crop.unwrap_or(CropWindow::full(width, height));
+    let tracer = RayTracer::new(scene, config)?;
     let setup_end = Instant::now();
     log::info!("setup time: {:?}", setup_end - setup_start);
 
     let begin = Instant::now();
-    let result = fb
-        .data
-        .par_chunks_mut(fb.height)
-        .enumerate()
-        .map(|(k, row)| {
-            let mut ctxt = TraceContext::new(&tracer);
-            for (j, c) in row.iter_mut().enumerate() {
-                let color = ctxt.sample_point(j, k);
-                *c = color;
-            }
-            ctxt.result
-        })
-        .reduce(TraceResult::new, |a, b| a.combine(&b));
-
+    let (corners, result) = trace_corners(&tracer, width, height, window);
     let trace_end = Instant::now();
 
     // anti-aliasing
-    let mut fb2 = Framebuffer::new(fb.width, fb.height);
-    let result2 = fb2
+    let uniform = tracer.sample_uniformly();
+    let mut fb = Framebuffer::new(width, height);
+    let result2 = fb
         .data
-        .par_chunks_mut(fb.height)
-        .skip(1)
+        .par_chunks_mut(width)
+        .zip(fb.alpha.par_chunks_mut(width))
         .enumerate()
-        .map(|(k, row)| {
+        .map(|(k, (row, alpha))| {
             let mut ctxt = TraceContext::new(&tracer);
-            for (j, c) in row.iter_mut().enumerate() {
-                if j == fb.width - 1 {
-                    break;
+            for (j, (c, a)) in row.iter_mut().zip(alpha).enumerate() {
+                if !window.contains(j, k) {
+                    continue;
                 }
-                let mut pixel = Pixel::new(j, k + 1);
-                let color = pixel.sample(&mut ctxt, &fb, anti_aliasing_depth);
-                *c = color;
+                let mut pixel = Pixel::new(j, k, uniform);
+                let sample = pixel.sample(&mut ctxt, &corners, anti_aliasing_depth);
+                *c = sample.color;
+                *a = sample.alpha;
             }
             ctxt.result
         })
@@ -80,15 +89,205 @@ pub fn render_scene(scene: Scene, anti_aliasing_depth: u8) -> Framebuffer {
 
     let render_end = Instant::now();
 
+    if !config.aovs.is_empty() {
+        fb.aovs = aov::render_aovs(&tracer, width, height, window, &config.aovs);
+        log::info!("aov time: {:?}", Instant::now() - render_end);
+    }
+
     result.print_stats();
     result2.print_stats();
     log::info!("total tracing time: {:?}", trace_end - begin);
     log::info!("total render time: {:?}", render_end - begin);
-    fb2
+
+    let total = result.combine(&result2);
+    fb.metadata = Some(Metadata {
+        width: fb.width,
+        height: fb.height,
+        sampling_depth: anti_aliasing_depth,
+        crop,
+        setup_time: setup_end - setup_start,
+        render_time: render_end - begin,
+        ray_count: total.ray_count().into(),
+        hit_count: total.hit_count().into(),
+        ..Metadata::default()
+    });
+
+    Ok(finish(fb, crop, config))
+}
+
+/// Render in passes of one sample per pixel, calling `write` with the image after the
+/// passes its schedule picks. The last pass is returned instead, with the AOVs.
+pub fn render_progressive(
+    scene: Scene,
+    progressive: &Progressive,
+    crop: Option<CropWindow>,
+    config: &OutputConfig,
+    mut write: impl FnMut(Framebuffer),
+) -> io::Result<Framebuffer> {
+    let setup_start = Instant::now();
+    let (width, height) = (scene.width() as usize, scene.height() as usize);
+    let window = crop.unwrap_or(CropWindow::full(width, height));
+    let tracer = RayTracer::new(scene, config)?;
+    let setup_end = Instant::now();
+    log::info!("setup time: {:?}", setup_end - setup_start);
+
+    let begin = Instant::now();
+    let mut schedule = WriteSchedule::new(*progressive, begin);
+    let (corners, mut total) = trace_corners(&tracer, width, height, window);
+    let mut sums = Framebuffer::new(width, height);
+    progressive::first_pass(&corners, &mut sums, window);
+    let snapshot = |sums: &Framebuffer, passes: u32, total: &TraceResult, now: Instant| {
+        let mut fb = progressive::average(sums, passes);
+        fb.metadata = Some(Metadata {
+            width,
+            height,
+            passes,
+            crop,
+            setup_time: setup_end - setup_start,
+            render_time: now - begin,
+            ray_count: total.ray_count().into(),
+            hit_count: total.hit_count().into(),
+            ..Metadata::default()
+        });
+        fb
+    };
+
+    for pass in 1..=progressive.passes {
+        if pass > 1 {
+            total = total.combine(&progressive::refine(&tracer, &mut sums, window));
+        }
+        let now = Instant::now();
+        log::info!("pass {pass}/{}: {:?}", progressive.passes, now - begin);
+        if schedule.due(pass, now) {
+            write(finish(snapshot(&sums, pass, &total, now), crop, config));
+        }
+    }
+
+    let render_end = Instant::now();
+    let mut fb = snapshot(&sums, progressive.passes, &total, render_end);
+    if !config.aovs.is_empty() {
+        fb.aovs = aov::render_aovs(&tracer, width, height, window, &config.aovs);
+        log::info!("aov time: {:?}", Instant::now() - render_end);
+    }
+    total.print_stats();
+    log::info!("total render time: {:?}", render_end - begin);
+    Ok(finish(fb, crop, config))
+}
+
+/// Trace the corners of the pixels in `window`, from the row above the image to the
+/// column after it
+fn trace_corners(
+    tracer: &RayTracer,
+    width: usize,
+    height: usize,
+    window: CropWindow,
+) -> (Framebuffer, TraceResult) {
+    let mut corners = Framebuffer::new(width + 1, height + 1);
+    let traced = window.corners();
+    let result = corners
+        .data
+        .par_chunks_mut(width + 1)
+        .zip(corners.alpha.par_chunks_mut(width + 1))
+        .enumerate()
+        .map(|(y, (row, alpha))| {
+            let mut ctxt = TraceContext::new(tracer);
+            for (x, (c, a)) in row.iter_mut().zip(alpha).enumerate() {
+                if !traced.contains(x, y) {
+                    continue;
+                }
+                #[allow(clippy::cast_precision_loss)]
+                let sample = ctxt.sample_coord(XYCoord {
+                    x: x as f32,
+                    y: y as f32 - 1.0,
+                });
+                *c = sample.color;
+                *a = sample.alpha;
+            }
+            ctxt.result
+        })
+        .reduce(TraceResult::new, |a, b| a.combine(&b));
+    (corners, result)
+}
+
+/// Cut a render down to its crop window, or clear around it for `crop_full_size`
+fn finish(mut fb: Framebuffer, crop: Option<CropWindow>, config: &OutputConfig) -> Framebuffer {
+    match crop {
+        Some(window) if config.crop_full_size => {
+            fb.clear_outside(window);
+            fb
+        }
+        Some(window) => fb.cropped(window),
+        None => fb,
+    }
+}
+
+/// Render a scene read from `path` adaptively, or progressively when the command line asks,
+/// recording the file in the image metadata. A progressive render calls `write` with the
+/// image as it refines.
+fn render_scene_file(
+    path: &Path,
+    scene: Scene,
+    args: &CliArgs,
+    crop: Option<CropWindow>,
+    config: &OutputConfig,
+    mut write: impl FnMut(&Framebuffer),
+) -> io::Result<Framebuffer> {
+    let scene_hash = metadata::hash_file(path).ok();
+    let record = |fb: &mut Framebuffer| {
+        if let Some(metadata) = &mut fb.metadata {
+            metadata.scene = Some(path.to_path_buf());
+            metadata.scene_hash = scene_hash;
+        }
+    };
+    let mut fb = match Progressive::with_args(args) {
+        Some(progressive) => render_progressive(scene, &progressive, crop, config, |mut fb| {
+            record(&mut fb);
+            write(&fb);
+        })?,
+        None => render_scene(scene, args.sampling_depth, crop, config)?,
+    };
+    record(&mut fb);
+    Ok(fb)
+}
+
+/// Load the scene with its resolution overridden by the command line
+fn load_scene(args: &CliArgs) -> io::Result<Scene> {
+    let mut scene = Scene::new(&args.scene)?;
+    scene.set_resolution(args.width, args.height, args.scale.unwrap_or(1.0));
+    Ok(scene)
+}
+
+pub fn render_with_args(args: &CliArgs) -> io::Result<Framebuffer> {
+    let scene = load_scene(args)?;
+    let config = scene.output().with_args(args);
+    let crop = config.crop_window(scene.width() as usize, scene.height() as usize)?;
+    render_scene_file(&args.scene, scene, args, crop, &config, |_| {})
 }
 
-#[must_use]
-pub fn render_with_args(args: &CliArgs) -> Framebuffer {
-    let scene = Scene::new(&args.scene);
-    render_scene(scene, args.sampling_depth)
+/// Render the scene and write the image, with the scene's `output` settings
+/// overridden by the command line
+pub fn render_image_with_args(args: &CliArgs) -> io::Result<()> {
+    let scene = load_scene(args)?;
+    let mut config = scene.output().with_args(args);
+    // a patch is the size of its window
+    config.crop_full_size &= !args.patch;
+    let image = args.image.clone().unwrap_or_else(|| {
+        let format = config.format.unwrap_or_default();
+        PathBuf::from(args.scene.file_name().unwrap()).with_extension(format.extension())
+    });
+    // fail before rendering rather than after on an extension we can't write
+    config.image_format(&image)?;
+    let full = (scene.width() as usize, scene.height() as usize);
+    let crop = config.crop_window(full.0, full.1)?;
+    let save = |framebuf: &Framebuffer| match crop {
+        Some(window) if args.patch => crop::patch_image(&image, framebuf, window, full, &config),
+        _ => framebuf.save_image(&image, &config),
+    };
+    let framebuf = render_scene_file(&args.scene, scene, args, crop, &config, |partial| {
+        // a failed write of an image that is still refining shouldn't stop the render
+        if let Err(e) = save(partial) {
+            log::warn!("{e}");
+        }
+    })?;
+    save(&framebuf)
 }
@@ -0,0 +1,830 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use exr::prelude::{
+    f16, AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec,
+    Text, WritableImage,
+};
+use image::codecs::hdr::HdrEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding};
+use image::codecs::tiff::TiffEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{ExtendedColorType, ImageEncoder, Rgb};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::aov::Aov;
+use super::color::WorkingSpace;
+use super::crop::CropWindow;
+use super::metadata::{Metadata, SOFTWARE};
+use super::tonemap::ToneMap;
+use super::{ColorRGB, Framebuffer};
+use crate::args::{AlphaMode, CliArgs, ExrSample, ImageFormat};
+
+impl ImageFormat {
+    /// The format for a file extension, if it is one we can write
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("png") => Some(ImageFormat::Png),
+            Some("jpg" | "jpeg") => Some(ImageFormat::Jpeg),
+            Some("ppm") => Some(ImageFormat::Ppm),
+            Some("pfm") => Some(ImageFormat::Pfm),
+            Some("tif" | "tiff") => Some(ImageFormat::Tiff),
+            Some("webp") => Some(ImageFormat::Webp),
+            Some("exr") => Some(ImageFormat::Exr),
+            Some("hdr") => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+
+    /// Whether the format stores linear float values rather than display values
+    pub fn is_float(self) -> bool {
+        matches!(
+            self,
+            ImageFormat::Pfm | ImageFormat::Tiff | ImageFormat::Exr | ImageFormat::Hdr
+        )
+    }
+
+    /// Whether the format is written with an alpha channel when one is configured
+    pub fn has_alpha(self) -> bool {
+        matches!(
+            self,
+            ImageFormat::Png | ImageFormat::Png16 | ImageFormat::Exr
+        )
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png | ImageFormat::Png16 => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Pfm => "pfm",
+            ImageFormat::Tiff => "tiff",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Exr => "exr",
+            ImageFormat::Hdr => "hdr",
+        }
+    }
+}
+
+fn default_quality() -> u8 {
+    90
+}
+
+/// The scene's `output` block: how the framebuffer is written to disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    /// Overrides the format given by the image's extension
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<ImageFormat>,
+    /// JPEG quality, 1 to 100
+    pub quality: u8,
+    /// Exposure adjustment in stops, applied before tone mapping
+    pub exposure: f32,
+    pub tone_map: ToneMap,
+    /// Linear value mapped to white by extended Reinhard and Hable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub white_point: Option<f32>,
+    pub exr_sample: ExrSample,
+    /// Auxiliary images to render, layers of EXR output and separate files otherwise
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aovs: Vec<Aov>,
+    /// Write coverage alpha to PNG and EXR output
+    pub alpha: AlphaMode,
+    /// Leave the background colour in transparent pixels rather than black
+    pub keep_background: bool,
+    /// Render only `[x0, y0, x1, y1]` given as fractions of the image size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<[f32; 4]>,
+    /// Write a cropped render at full size with the rest of the image empty
+    pub crop_full_size: bool,
+    /// A crop window in pixels from the command line, replacing `crop`
+    #[serde(skip)]
+    pub crop_pixels: Option<CropWindow>,
+    /// Taken from the scene's `working_space`, 8-bit output is sRGB encoded from linear
+    #[serde(skip)]
+    pub working_space: WorkingSpace,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            format: None,
+            quality: default_quality(),
+            exposure: 0.0,
+            tone_map: ToneMap::default(),
+            white_point: None,
+            exr_sample: ExrSample::default(),
+            aovs: Vec::new(),
+            alpha: AlphaMode::default(),
+            keep_background: false,
+            crop: None,
+            crop_full_size: false,
+            crop_pixels: None,
+            working_space: WorkingSpace::default(),
+        }
+    }
+}
+
+impl OutputConfig {
+    /// The format to write `path` in: the configured one, else the one given by its extension,
+    /// and PNG when it has none. Extensions we can't write are an error.
+    pub fn image_format(&self, path: &Path) -> io::Result<ImageFormat> {
+        if let Some(format) = self.format {
+            return Ok(format);
+        }
+        if path.extension().is_none() {
+            return Ok(ImageFormat::default());
+        }
+        ImageFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: unknown image format, expected .png, .jpg, .jpeg, .ppm, .pfm, .tif, \
+                     .tiff, .webp, .exr or .hdr",
+                    path.display()
+                ),
+            )
+        })
+    }
+
+    /// Override the scene's settings with those given on the command line
+    #[must_use]
+    pub fn with_args(mut self, args: &CliArgs) -> Self {
+        if args.format.is_some() {
+            self.format = args.format;
+        }
+        if let Some(quality) = args.quality {
+            self.quality = quality;
+        }
+        if let Some(exposure) = args.exposure {
+            self.exposure = exposure;
+        }
+        if let Some(tone_map) = args.tone_map {
+            self.tone_map = tone_map;
+        }
+        if args.white_point.is_some() {
+            self.white_point = args.white_point;
+        }
+        if let Some(exr_sample) = args.exr_sample {
+            self.exr_sample = exr_sample;
+        }
+        if !args.aovs.is_empty() {
+            self.aovs.clone_from(&args.aovs);
+        }
+        if let Some(alpha) = args.alpha {
+            self.alpha = alpha;
+        }
+        if args.keep_background {
+            self.keep_background = true;
+        }
+        if args.crop.is_some() {
+            self.crop_pixels = args.crop;
+        }
+        if args.crop_full_size {
+            self.crop_full_size = true;
+        }
+        self
+    }
+
+    /// The pixels to render in a `width` by `height` image, `None` for all of them
+    pub fn crop_window(&self, width: usize, height: usize) -> io::Result<Option<CropWindow>> {
+        match (self.crop_pixels, self.crop) {
+            (Some(window), _) => window.check(width, height).map(Some),
+            (None, Some(rect)) => CropWindow::from_normalized(rect, width, height).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Whether colours are premultiplied by alpha: misses are black and transparent.
+    /// With `keep_background` they hold the background colour and are written as they are.
+    #[must_use]
+    pub fn premultiplied(&self) -> bool {
+        self.alpha != AlphaMode::Off && !self.keep_background
+    }
+}
+
+/// A layer of an OpenEXR file: a list of named float channels,
+/// the unnamed layer is the main image
+pub struct ExrLayer {
+    pub name: Option<String>,
+    pub channels: Vec<(String, Vec<f32>)>,
+}
+
+fn create(path: &Path) -> io::Result<BufWriter<File>> {
+    File::create(path).map(BufWriter::new).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("failed to create {}: {e}", path.display()),
+        )
+    })
+}
+
+fn write_error(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(format!("failed to write {}: {e}", path.display()))
+}
+
+/// 8-bit RGB after exposure, tone mapping and sRGB encoding
+fn display_rgb8(fb: &Framebuffer, config: &OutputConfig) -> Vec<u8> {
+    let fb = fb.display_encoded(config);
+    fb.data.par_iter().flat_map(ColorRGB::to_irgb).collect()
+}
+
+/// Like `display_rgb8` with straight alpha after each pixel
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn display_rgba8(fb: &Framebuffer, config: &OutputConfig) -> Vec<u8> {
+    let display = fb.display_encoded(config);
+    display
+        .data
+        .par_iter()
+        .zip(&fb.alpha)
+        .flat_map(|(c, &a)| {
+            let [r, g, b] = c.to_irgb();
+            [r, g, b, (a.clamp(0.0, 1.0) * 255.0).round() as u8]
+        })
+        .collect()
+}
+
+/// Linear RGB as native endian floats
+fn linear_rgb32f(fb: &Framebuffer) -> Vec<u8> {
+    fb.data
+        .iter()
+        .flat_map(|c| [c.r, c.g, c.b])
+        .flat_map(f32::to_ne_bytes)
+        .collect()
+}
+
+/// Write `data` with one of the `image` crate's encoders
+fn encode(
+    path: &Path,
+    encoder: impl ImageEncoder,
+    fb: &Framebuffer,
+    data: &[u8],
+    color: ExtendedColorType,
+) -> io::Result<()> {
+    // Framebuffer::new asserts these fit in u32.
+    #[allow(clippy::cast_possible_truncation)]
+    encoder
+        .write_image(data, fb.width as u32, fb.height as u32, color)
+        .map_err(|e| write_error(path, e))
+}
+
+/// Write 8 bit RGB after exposure, tone mapping and sRGB encoding, RGBA when the
+/// config has alpha
+pub fn write_png(fb: &Framebuffer, path: &Path, config: &OutputConfig) -> io::Result<()> {
+    let data = if config.alpha == AlphaMode::Off {
+        display_rgb8(fb, config)
+    } else {
+        display_rgba8(fb, config)
+    };
+    encode_png(fb, path, config, png::BitDepth::Eight, &data)
+}
+
+/// Write 16 bit RGB or RGBA PNG, encoded like 8 bit output
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn write_png16(fb: &Framebuffer, path: &Path, config: &OutputConfig) -> io::Result<()> {
+    let display = fb.display_encoded(config);
+    let channels = if config.alpha == AlphaMode::Off { 3 } else { 4 };
+    let data: Vec<u8> = display
+        .data
+        .iter()
+        .zip(&fb.alpha)
+        .flat_map(|(c, &a)| {
+            let [r, g, b] = c.to_irgb16();
+            let a = (a.clamp(0.0, 1.0) * 65535.0).round() as u16;
+            [r, g, b, a].into_iter().take(channels)
+        })
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    encode_png(fb, path, config, png::BitDepth::Sixteen, &data)
+}
+
+/// Write PNG samples with the framebuffer's metadata as text chunks
+fn encode_png(
+    fb: &Framebuffer,
+    path: &Path,
+    config: &OutputConfig,
+    depth: png::BitDepth,
+    data: &[u8],
+) -> io::Result<()> {
+    let bufwriter = create(path)?;
+
+    // Framebuffer::new asserts these fit in u32.
+    #[allow(clippy::cast_possible_truncation)]
+    let mut encoder = png::Encoder::new(bufwriter, fb.width as u32, fb.height as u32);
+    encoder.set_color(if config.alpha == AlphaMode::Off {
+        png::ColorType::Rgb
+    } else {
+        png::ColorType::Rgba
+    });
+    encoder.set_depth(depth);
+    if let Some(metadata) = &fb.metadata {
+        let text = std::iter::once(("Software".to_string(), SOFTWARE.to_string()))
+            .chain(metadata.entries());
+        for (keyword, value) in text {
+            if value.is_ascii() {
+                encoder.add_text_chunk(keyword, value)
+            } else {
+                encoder.add_itxt_chunk(keyword, value)
+            }
+            .map_err(|e| write_error(path, e))?;
+        }
+    }
+    let mut writer = encoder.write_header().map_err(|e| write_error(path, e))?;
+
+    writer
+        .write_image_data(data)
+        .map_err(|e| write_error(path, e))
+}
+
+pub fn write_jpeg(fb: &Framebuffer, path: &Path, config: &OutputConfig) -> io::Result<()> {
+    let encoder = JpegEncoder::new_with_quality(create(path)?, config.quality.clamp(1, 100));
+    let data = display_rgb8(fb, config);
+    encode(path, encoder, fb, &data, ExtendedColorType::Rgb8)
+}
+
+/// Write a binary 8 bit pixmap
+pub fn write_ppm(fb: &Framebuffer, path: &Path, config: &OutputConfig) -> io::Result<()> {
+    let encoder =
+        PnmEncoder::new(create(path)?).with_subtype(PnmSubtype::Pixmap(SampleEncoding::Binary));
+    let data = display_rgb8(fb, config);
+    encode(path, encoder, fb, &data, ExtendedColorType::Rgb8)
+}
+
+/// Write lossless 8 bit WebP
+pub fn write_webp(fb: &Framebuffer, path: &Path, config: &OutputConfig) -> io::Result<()> {
+    let encoder = WebPEncoder::new_lossless(create(path)?);
+    let data = display_rgb8(fb, config);
+    encode(path, encoder, fb, &data, ExtendedColorType::Rgb8)
+}
+
+/// Write linear float RGB TIFF
+pub fn write_tiff(fb: &Framebuffer, path: &Path) -> io::Result<()> {
+    let encoder = TiffEncoder::new(create(path)?);
+    encode(
+        path,
+        encoder,
+        fb,
+        &linear_rgb32f(fb),
+        ExtendedColorType::Rgb32F,
+    )
+}
+
+/// Write linear float RGB as a portable float map, rows run bottom to top
+pub fn write_pfm(fb: &Framebuffer, path: &Path) -> io::Result<()> {
+    let mut out = create(path)?;
+    let mut write = || -> io::Result<()> {
+        // a negative scale marks little endian samples
+        write!(out, "PF\n{} {}\n-1.0\n", fb.width, fb.height)?;
+        for row in fb.data.chunks(fb.width).rev() {
+            for c in row {
+                for v in [c.r, c.g, c.b] {
+                    out.write_all(&v.to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    };
+    write().map_err(|e| write_error(path, e))
+}
+
+/// Write unclamped float RGB as Radiance RGBE
+pub fn write_hdr(fb: &Framebuffer, path: &Path) -> io::Result<()> {
+    let bufwriter = create(path)?;
+    let pixels: Vec<Rgb<f32>> = fb
+        .data
+        .iter()
+        .map(|c| Rgb([c.r.max(0.0), c.g.max(0.0), c.b.max(0.0)]))
+        .collect();
+    HdrEncoder::new(bufwriter)
+        .encode(&pixels, fb.width, fb.height)
+        .map_err(|e| write_error(path, e))
+}
+
+/// Write layers of float channels to an OpenEXR file. Named layers follow the OpenEXR
+/// convention of prefixing their channel names with `<layer>.`, all in a single part so that
+/// viewers show the unnamed RGB layer by default.
+pub fn write_exr(
+    path: &Path,
+    width: usize,
+    height: usize,
+    layers: &[ExrLayer],
+    sample: ExrSample,
+    metadata: Option<&Metadata>,
+) -> io::Result<()> {
+    let channels = layers
+        .iter()
+        .flat_map(|layer| {
+            layer.channels.iter().map(|(name, values)| {
+                let name = match &layer.name {
+                    Some(layer) => format!("{layer}.{name}"),
+                    None => name.clone(),
+                };
+                let samples = match sample {
+                    ExrSample::Half => {
+                        FlatSamples::F16(values.iter().copied().map(f16::from_f32).collect())
+                    }
+                    ExrSample::Float => FlatSamples::F32(values.clone()),
+                };
+                AnyChannel::new(name.as_str(), samples)
+            })
+        })
+        .collect::<SmallVec<_>>();
+
+    let mut attributes = LayerAttributes::default();
+    if let Some(metadata) = metadata {
+        attributes.software_name = Text::new_or_none(SOFTWARE);
+        attributes.other.extend(metadata.exr_attributes());
+    }
+    let layer = Layer::new(
+        (width, height),
+        attributes,
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .map_err(|e| write_error(path, e))
+}
+
+impl Framebuffer {
+    /// Colours for 8-bit output: tone mapped, then sRGB encoded in a linear working space.
+    /// Premultiplied colours are divided by alpha first, as 8-bit formats store straight alpha.
+    #[must_use]
+    pub fn display_encoded(&self, config: &OutputConfig) -> Framebuffer {
+        let mut fb = if config.premultiplied() {
+            self.unpremultiplied().tone_mapped(config)
+        } else {
+            self.tone_mapped(config)
+        };
+        if config.working_space == WorkingSpace::LinearSrgb {
+            fb.data.par_iter_mut().for_each(|c| *c = c.to_srgb());
+        }
+        fb
+    }
+
+    /// Colours divided by their alpha, fully transparent pixels are left black
+    #[must_use]
+    pub fn unpremultiplied(&self) -> Framebuffer {
+        Framebuffer {
+            data: self
+                .data
+                .par_iter()
+                .zip(&self.alpha)
+                .map(|(&c, &a)| if a > 0.0 { c / a } else { ColorRGB::black() })
+                .collect(),
+            ..Framebuffer::new(self.width, self.height)
+        }
+    }
+
+    /// The colour buffer as the R, G and B channels of an unnamed EXR layer, and A when
+    /// `alpha` is set. EXR colours are premultiplied, as rendered.
+    #[must_use]
+    pub fn exr_layer(&self, alpha: bool) -> ExrLayer {
+        let channel = |f: fn(&ColorRGB) -> f32| self.data.iter().map(f).collect();
+        let mut channels = vec![
+            ("R".to_string(), channel(|c| c.r)),
+            ("G".to_string(), channel(|c| c.g)),
+            ("B".to_string(), channel(|c| c.b)),
+        ];
+        if alpha {
+            channels.push(("A".to_string(), self.alpha.clone()));
+        }
+        ExrLayer {
+            name: None,
+            channels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::metadata::read_metadata;
+
+    fn bright_framebuffer() -> Framebuffer {
+        let mut fb = Framebuffer::new(3, 2);
+        fb.set_color(0, 0, &ColorRGB::new(4.0, 0.5, 0.25));
+        fb.set_color(2, 1, &ColorRGB::new(0.0, 16.0, 1.0));
+        fb
+    }
+
+    fn read_exr_rgb(path: &Path) -> Vec<(String, Vec<f32>)> {
+        let image = exr::prelude::read_all_flat_layers_from_file(path).unwrap();
+        image.layer_data[0]
+            .channel_data
+            .list
+            .iter()
+            .map(|c| (c.name.to_string(), c.sample_data.values_as_f32().collect()))
+            .collect()
+    }
+
+    #[test]
+    fn exr_keeps_values_above_one() {
+        let fb = bright_framebuffer();
+        for sample in [ExrSample::Half, ExrSample::Float] {
+            let path = std::env::temp_dir().join(format!("arrt_output_{sample:?}.exr"));
+            let config = OutputConfig {
+                exr_sample: sample,
+                ..OutputConfig::default()
+            };
+            fb.save_image(&path, &config).unwrap();
+            let channels = read_exr_rgb(&path);
+            // channels are sorted by name
+            let names: Vec<&str> = channels.iter().map(|(n, _)| n.as_str()).collect();
+            assert_eq!(names, ["B", "G", "R"]);
+            assert!((channels[2].1[0] - 4.0).abs() < 1e-3);
+            assert!((channels[1].1[5] - 16.0).abs() < 1e-3);
+            assert!((channels[0].1[0] - 0.25).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn hdr_keeps_values_above_one() {
+        let fb = bright_framebuffer();
+        let path = std::env::temp_dir().join("arrt_output.hdr");
+        fb.save_image(&path, &OutputConfig::default()).unwrap();
+        let image = image::open(&path).unwrap().to_rgb32f();
+        assert_eq!(image.dimensions(), (3, 2));
+        let p = image.get_pixel(0, 0);
+        assert!((p[0] - 4.0).abs() < 0.1);
+        let p = image.get_pixel(2, 1);
+        assert!((p[1] - 16.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn png_is_tone_mapped() {
+        let mut fb = Framebuffer::new(1, 1);
+        fb.set_color(0, 0, &ColorRGB::fill(4.0));
+        let path = std::env::temp_dir().join("arrt_output_tone_mapped.png");
+        let config = OutputConfig {
+            tone_map: ToneMap::Reinhard,
+            ..OutputConfig::default()
+        };
+        fb.save_image(&path, &config).unwrap();
+        let image = image::open(&path).unwrap().to_rgb8();
+        // 4 / (1 + 4), sRGB encoded
+        assert_eq!(image.get_pixel(0, 0).0, [231, 231, 231]);
+
+        let config = OutputConfig {
+            working_space: WorkingSpace::Srgb,
+            ..config
+        };
+        fb.save_image(&path, &config).unwrap();
+        let image = image::open(&path).unwrap().to_rgb8();
+        assert_eq!(image.get_pixel(0, 0).0, [204, 204, 204]);
+    }
+
+    #[test]
+    fn cli_overrides_scene_output() {
+        let scene = OutputConfig {
+            exposure: 1.0,
+            tone_map: ToneMap::Aces,
+            ..OutputConfig::default()
+        };
+        let args = CliArgs {
+            tone_map: Some(ToneMap::Hable),
+            white_point: Some(6.0),
+            ..CliArgs::default()
+        };
+        let config = scene.with_args(&args);
+        assert!((config.exposure - 1.0).abs() < 1e-5);
+        assert_eq!(config.tone_map, ToneMap::Hable);
+        assert_eq!(config.white_point, Some(6.0));
+    }
+
+    #[test]
+    fn eight_bit_formats_read_back() {
+        let mut fb = Framebuffer::new(3, 2);
+        fb.set_color(1, 0, &ColorRGB::new(1.0, 0.0, 0.5));
+        let config = OutputConfig {
+            working_space: WorkingSpace::Srgb,
+            quality: 100,
+            ..OutputConfig::default()
+        };
+        for ext in ["ppm", "webp", "jpg"] {
+            let path = std::env::temp_dir().join(format!("arrt_output_formats.{ext}"));
+            fb.save_image(&path, &config).unwrap();
+            let image = image::open(&path).unwrap().to_rgb8();
+            assert_eq!(image.dimensions(), (3, 2));
+            let p = image.get_pixel(1, 0);
+            assert!(
+                p[0] > 240 && p[1] < 20 && p[2].abs_diff(128) < 10,
+                "{ext}: {p:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn png16_keeps_precision() {
+        let mut fb = Framebuffer::new(1, 1);
+        fb.set_color(0, 0, &ColorRGB::fill(0.3));
+        let config = OutputConfig {
+            format: Some(ImageFormat::Png16),
+            working_space: WorkingSpace::Srgb,
+            ..OutputConfig::default()
+        };
+        let path = std::env::temp_dir().join("arrt_output_16.png");
+        fb.save_image(&path, &config).unwrap();
+        let image = image::open(&path).unwrap().to_rgb16();
+        assert_eq!(image.get_pixel(0, 0).0, [19661, 19661, 19661]);
+    }
+
+    #[test]
+    fn float_formats_are_linear() {
+        let fb = bright_framebuffer();
+        let path = std::env::temp_dir().join("arrt_output.tiff");
+        fb.save_image(&path, &OutputConfig::default()).unwrap();
+        let image = image::open(&path).unwrap().to_rgb32f();
+        assert!((image.get_pixel(0, 0)[0] - 4.0).abs() < 1e-5);
+
+        let path = std::env::temp_dir().join("arrt_output.pfm");
+        fb.save_image(&path, &OutputConfig::default()).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        // the top row is written last, pixel (0, 0) starts it
+        let start = header.len() + 3 * 3 * 4;
+        let r = f32::from_le_bytes(bytes[start..start + 4].try_into().unwrap());
+        assert!((r - 4.0).abs() < 1e-5);
+    }
+
+    fn with_depth_aov(mut fb: Framebuffer) -> Framebuffer {
+        let mut depth = Framebuffer::new(fb.width, fb.height);
+        depth.set_color(1, 0, &ColorRGB::fill(2.0));
+        depth.set_color(2, 0, &ColorRGB::fill(8.0));
+        fb.aovs.push((Aov::Depth, depth));
+        fb
+    }
+
+    #[test]
+    fn aovs_are_exr_layers() {
+        let fb = with_depth_aov(bright_framebuffer());
+        let path = std::env::temp_dir().join("arrt_output_aovs.exr");
+        fb.save_image(&path, &OutputConfig::default()).unwrap();
+        let channels = read_exr_rgb(&path);
+        let names: Vec<&str> = channels.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["B", "G", "R", "depth.Z"]);
+        assert!((channels[3].1[2] - 8.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn aovs_are_separate_files_for_other_formats() {
+        let fb = with_depth_aov(bright_framebuffer());
+        let path = std::env::temp_dir().join("arrt_output_aovs.png");
+        fb.save_image(&path, &OutputConfig::default()).unwrap();
+        let depth = image::open(path.with_extension("depth.png"))
+            .unwrap()
+            .to_rgb8();
+        // scaled by the largest depth, without tone mapping or encoding
+        assert_eq!(depth.get_pixel(1, 0).0, [64, 64, 64]);
+        assert_eq!(depth.get_pixel(2, 0).0, [255, 255, 255]);
+
+        let path = std::env::temp_dir().join("arrt_output_aovs.pfm");
+        fb.save_image(&path, &OutputConfig::default()).unwrap();
+        assert!(path.with_extension("depth.pfm").exists());
+    }
+
+    #[test]
+    fn png_alpha_is_straight() {
+        let mut fb = Framebuffer::new(1, 1);
+        fb.set_color(0, 0, &ColorRGB::fill(0.25));
+        fb.alpha[0] = 0.5;
+        let path = std::env::temp_dir().join("arrt_output_alpha.png");
+        let mut config = OutputConfig {
+            alpha: AlphaMode::Coverage,
+            working_space: WorkingSpace::Srgb,
+            ..OutputConfig::default()
+        };
+        fb.save_image(&path, &config).unwrap();
+        let image = image::open(&path).unwrap().to_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [128, 128, 128, 128]);
+
+        // with the background kept the colour isn't premultiplied
+        config.keep_background = true;
+        fb.save_image(&path, &config).unwrap();
+        let image = image::open(&path).unwrap().to_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [64, 64, 64, 128]);
+    }
+
+    #[test]
+    fn exr_alpha_is_premultiplied() {
+        let mut fb = Framebuffer::new(1, 1);
+        fb.set_color(0, 0, &ColorRGB::fill(0.25));
+        fb.alpha[0] = 0.5;
+        let path = std::env::temp_dir().join("arrt_output_alpha.exr");
+        let config = OutputConfig {
+            alpha: AlphaMode::Transmission,
+            ..OutputConfig::default()
+        };
+        fb.save_image(&path, &config).unwrap();
+        let channels = read_exr_rgb(&path);
+        let names: Vec<&str> = channels.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["A", "B", "G", "R"]);
+        assert!((channels[0].1[0] - 0.5).abs() < 1e-3);
+        assert!((channels[3].1[0] - 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn metadata_reads_back() {
+        let mut fb = Framebuffer::new(2, 2);
+        fb.metadata = Some(Metadata {
+            scene: Some("scènes/a.yaml".into()),
+            width: 2,
+            height: 2,
+            ray_count: 42,
+            ..Metadata::default()
+        });
+        for (name, format) in [
+            ("png", ImageFormat::Png),
+            ("16.png", ImageFormat::Png16),
+            ("exr", ImageFormat::Exr),
+        ] {
+            let path = std::env::temp_dir().join(format!("arrt_output_metadata.{name}"));
+            let config = OutputConfig {
+                format: Some(format),
+                ..OutputConfig::default()
+            };
+            fb.save_image(&path, &config).unwrap();
+            let entries = read_metadata(&path).unwrap();
+            let get = |key: &str| entries.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+            assert_eq!(get("arrt.ray_count").unwrap(), "42", "{name}");
+            assert_eq!(get("arrt.resolution").unwrap(), "2x2", "{name}");
+            assert!(get("Software").or(get("software")).is_some(), "{name}");
+            if format != ImageFormat::Exr {
+                // non-ASCII values are stored as iTXt
+                assert_eq!(get("arrt.scene").unwrap(), "scènes/a.yaml");
+            }
+        }
+    }
+
+    #[test]
+    fn unwritable_path_is_an_error() {
+        let fb = Framebuffer::new(1, 1);
+        let path = Path::new("/nonexistent/dir/image.png");
+        let e = fb.save_image(path, &OutputConfig::default()).unwrap_err();
+        assert!(e.to_string().contains("/nonexistent/dir/image.png"), "{e}");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn failed_pfm_write_names_the_file() {
+        let fb = Framebuffer::new(4, 4);
+        let e = write_pfm(&fb, Path::new("/dev/full")).unwrap_err();
+        assert!(e.to_string().contains("/dev/full"), "{e}");
+    }
+
+    #[test]
+    fn formats_by_extension() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a.EXR")),
+            Some(ImageFormat::Exr)
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a.hdr")),
+            Some(ImageFormat::Hdr)
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a.png")),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a.jpeg")),
+            Some(ImageFormat::Jpeg)
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a.tif")),
+            Some(ImageFormat::Tiff)
+        );
+        assert_eq!(ImageFormat::from_path(Path::new("a")), None);
+    }
+
+    #[test]
+    fn unknown_extension_is_an_error() {
+        let config = OutputConfig::default();
+        let e = config.image_format(Path::new("out.exrr")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(e.to_string().contains("out.exrr"), "{e}");
+        assert!(e.to_string().contains(".exr"), "{e}");
+
+        assert_eq!(
+            config.image_format(Path::new("out")).unwrap(),
+            ImageFormat::Png
+        );
+        let config = OutputConfig {
+            format: Some(ImageFormat::Hdr),
+            ..OutputConfig::default()
+        };
+        assert_eq!(
+            config.image_format(Path::new("out.xyz")).unwrap(),
+            ImageFormat::Hdr
+        );
+    }
+}
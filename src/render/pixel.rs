@@ -1,21 +1,28 @@
+use super::tracer::Sample;
 use super::{ColorRGB, Framebuffer, TraceContext, XYCoord};
 
 const TOLERANCE: f32 = 0.05;
 
 /// A super pixel capable of doing
 /// 0, 1, or 2 levels of adaptive supersampling
+///
+/// Pixel `j`, `k` covers view plane coordinates `j..j + 1` across and `k - 1..k` down. Its
+/// corners are read from a buffer of samples one larger each way, taken at `x`, `y - 1`.
+/// A `uniform` pixel is subdivided to the full depth even where its samples agree.
 #[derive(Debug)]
 pub struct Pixel {
     j: usize,
     k: usize,
-    stash: [[Option<ColorRGB>; 5]; 5],
+    uniform: bool,
+    stash: [[Option<Sample>; 5]; 5],
 }
 
 impl Pixel {
-    pub fn new(j: usize, k: usize) -> Self {
+    pub fn new(j: usize, k: usize, uniform: bool) -> Self {
         Pixel {
             j,
             k,
+            uniform,
             stash: [[None; 5]; 5],
         }
     }
@@ -24,13 +31,17 @@ impl Pixel {
     pub fn sample(
         &mut self,
         tracer: &mut TraceContext,
-        framebuf: &Framebuffer,
+        corners: &Framebuffer,
         max_depth: u8,
-    ) -> ColorRGB {
-        self.stash[4][0] = Some(framebuf.get_color(self.j, self.k));
-        self.stash[4][4] = Some(framebuf.get_color(self.j + 1, self.k));
-        self.stash[0][4] = Some(framebuf.get_color(self.j + 1, self.k - 1));
-        self.stash[0][0] = Some(framebuf.get_color(self.j, self.k - 1));
+    ) -> Sample {
+        let corner = |x, y| Sample {
+            color: corners.get_color(x, y),
+            alpha: corners.get_alpha(x, y),
+        };
+        self.stash[4][0] = Some(corner(self.j, self.k + 1));
+        self.stash[4][4] = Some(corner(self.j + 1, self.k + 1));
+        self.stash[0][4] = Some(corner(self.j + 1, self.k));
+        self.stash[0][0] = Some(corner(self.j, self.k));
         let bottom_left = XYCoord {
             x: self.j as f32,
             y: self.k as f32,
@@ -51,7 +62,7 @@ impl Pixel {
         depth: usize,
         tracer: &mut TraceContext,
         min_depth: usize,
-    ) -> ColorRGB {
+    ) -> Sample {
         let off = depth;
         let adjust = depth as f32 / 4.0;
 
@@ -81,8 +92,8 @@ impl Pixel {
 
         let samples = [a, b, e, d];
 
-        if !samples_differ(&samples) || depth == min_depth {
-            average_color(&samples)
+        if (!self.uniform && !samples_differ(&samples)) || depth == min_depth {
+            average_sample(&samples)
         } else {
             let d = depth / 2;
             let off = d;
@@ -124,31 +135,33 @@ impl Pixel {
             );
 
             let samples = [a, b, e, d];
-            average_color(&samples)
+            average_sample(&samples)
         }
     }
 }
 
-fn average_color(samples: &[ColorRGB; 4]) -> ColorRGB {
-    let mut sum = ColorRGB {
-        r: 0.0,
-        g: 0.0,
-        b: 0.0,
+fn average_sample(samples: &[Sample; 4]) -> Sample {
+    let mut sum = Sample {
+        color: ColorRGB::black(),
+        alpha: 0.0,
     };
     for &sample in samples {
-        sum += sample;
+        sum = sum + sample;
     }
     sum / 4.0
 }
 
-fn samples_differ(samples: &[ColorRGB; 4]) -> bool {
+fn samples_differ(samples: &[Sample; 4]) -> bool {
     colors_differ(&samples[0], &samples[1])
         || colors_differ(&samples[0], &samples[3])
         || colors_differ(&samples[2], &samples[3])
         || colors_differ(&samples[2], &samples[1])
 }
 
-fn colors_differ(a: &ColorRGB, b: &ColorRGB) -> bool {
-    let diff = *a - *b;
-    diff.r.abs() > TOLERANCE || diff.g.abs() > TOLERANCE || diff.b.abs() > TOLERANCE
+fn colors_differ(a: &Sample, b: &Sample) -> bool {
+    let diff = a.color - b.color;
+    diff.r.abs() > TOLERANCE
+        || diff.g.abs() > TOLERANCE
+        || diff.b.abs() > TOLERANCE
+        || (a.alpha - b.alpha).abs() > TOLERANCE
 }
@@ -0,0 +1,205 @@
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use rayon::prelude::*;
+
+use super::crop::CropWindow;
+use super::tracer::{RayTracer, Sample, TraceContext, TraceResult};
+use super::{Framebuffer, XYCoord};
+use crate::args::CliArgs;
+
+/// Written this often when neither `--write-interval` nor `--write-passes` is given
+const DEFAULT_WRITE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many passes a progressive render takes and when it writes the image in between
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progressive {
+    /// Samples per pixel, the first being the average of the pixel's corners
+    pub passes: u32,
+    /// Write the image when this long has passed since it was last written
+    pub write_interval: Option<Duration>,
+    /// Write the image after every this many passes
+    pub write_passes: Option<u32>,
+}
+
+impl Progressive {
+    /// The progressive settings of the command line, `None` when rendering adaptively
+    #[must_use]
+    pub fn with_args(args: &CliArgs) -> Option<Self> {
+        let passes = args.progressive?;
+        let write_interval = args.write_interval.map(Duration::from_secs_f32);
+        let write_passes = args.write_passes;
+        Some(Progressive {
+            passes,
+            write_interval: write_interval
+                .or_else(|| write_passes.is_none().then_some(DEFAULT_WRITE_INTERVAL)),
+            write_passes,
+        })
+    }
+}
+
+/// Decides after which passes the in-progress image is written
+pub struct WriteSchedule {
+    progressive: Progressive,
+    last_write: Instant,
+}
+
+impl WriteSchedule {
+    #[must_use]
+    pub fn new(progressive: Progressive, start: Instant) -> Self {
+        WriteSchedule {
+            progressive,
+            last_write: start,
+        }
+    }
+
+    /// Whether to write after `pass` passes have finished at `now`. The first pass is always
+    /// written as a preview and the last is left to the caller, which writes the final image.
+    pub fn due(&mut self, pass: u32, now: Instant) -> bool {
+        if pass >= self.progressive.passes {
+            return false;
+        }
+        let due = pass == 1
+            || self
+                .progressive
+                .write_passes
+                .is_some_and(|n| pass.is_multiple_of(n))
+            || self
+                .progressive
+                .write_interval
+                .is_some_and(|interval| now - self.last_write >= interval);
+        if due {
+            self.last_write = now;
+        }
+        due
+    }
+}
+
+/// Start the sums of the pixels in `window` with the average of their corners
+pub fn first_pass(corners: &Framebuffer, sums: &mut Framebuffer, window: CropWindow) {
+    let corner = |x, y| Sample {
+        color: corners.get_color(x, y),
+        alpha: corners.get_alpha(x, y),
+    };
+    sums.data
+        .par_chunks_mut(sums.width)
+        .zip(sums.alpha.par_chunks_mut(sums.width))
+        .enumerate()
+        .for_each(|(k, (row, alpha))| {
+            for (j, (c, a)) in row.iter_mut().zip(alpha).enumerate() {
+                if !window.contains(j, k) {
+                    continue;
+                }
+                let sample =
+                    (corner(j, k) + corner(j + 1, k) + corner(j, k + 1) + corner(j + 1, k + 1))
+                        / 4.0;
+                *c = sample.color;
+                *a = sample.alpha;
+            }
+        });
+}
+
+/// Add a sample at a random point of each pixel in `window` to its sum
+#[allow(clippy::cast_precision_loss)]
+pub fn refine(tracer: &RayTracer, sums: &mut Framebuffer, window: CropWindow) -> TraceResult {
+    sums.data
+        .par_chunks_mut(sums.width)
+        .zip(sums.alpha.par_chunks_mut(sums.width))
+        .enumerate()
+        .map(|(k, (row, alpha))| {
+            let mut ctxt = TraceContext::new(tracer);
+            let mut rng = rand::thread_rng();
+            for (j, (c, a)) in row.iter_mut().zip(alpha).enumerate() {
+                if !window.contains(j, k) {
+                    continue;
+                }
+                let sample = ctxt.sample_coord(XYCoord {
+                    x: j as f32 + rng.gen::<f32>(),
+                    y: k as f32 - rng.gen::<f32>(),
+                });
+                *c += sample.color;
+                *a += sample.alpha;
+            }
+            ctxt.result
+        })
+        .reduce(TraceResult::new, |a, b| a.combine(&b))
+}
+
+/// The image after `passes` samples have been summed into each pixel
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn average(sums: &Framebuffer, passes: u32) -> Framebuffer {
+    let n = passes as f32;
+    let mut fb = Framebuffer::new(sums.width, sums.height);
+    fb.data
+        .par_iter_mut()
+        .zip(&sums.data)
+        .for_each(|(c, sum)| *c = *sum / n);
+    fb.alpha
+        .par_iter_mut()
+        .zip(&sums.alpha)
+        .for_each(|(a, sum)| *a = sum / n);
+    fb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ColorRGB;
+    use super::*;
+
+    fn progressive(write_interval: Option<u64>, write_passes: Option<u32>) -> Progressive {
+        Progressive {
+            passes: 8,
+            write_interval: write_interval.map(Duration::from_secs),
+            write_passes,
+        }
+    }
+
+    #[test]
+    fn writes_first_pass_and_every_n() {
+        let start = Instant::now();
+        let mut schedule = WriteSchedule::new(progressive(None, Some(3)), start);
+        let written: Vec<u32> = (1..=8).filter(|&pass| schedule.due(pass, start)).collect();
+        assert_eq!(written, [1, 3, 6]);
+    }
+
+    #[test]
+    fn writes_when_interval_has_passed() {
+        let start = Instant::now();
+        let mut schedule = WriteSchedule::new(progressive(Some(10), None), start);
+        assert!(schedule.due(1, start + Duration::from_secs(1)));
+        assert!(!schedule.due(2, start + Duration::from_secs(5)));
+        assert!(schedule.due(3, start + Duration::from_secs(11)));
+        assert!(!schedule.due(4, start + Duration::from_secs(12)));
+        assert!(!schedule.due(8, start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn default_interval_only_without_write_passes() {
+        let args = CliArgs {
+            progressive: Some(4),
+            ..CliArgs::default()
+        };
+        let p = Progressive::with_args(&args).unwrap();
+        assert_eq!(p.write_interval, Some(DEFAULT_WRITE_INTERVAL));
+
+        let args = CliArgs {
+            progressive: Some(4),
+            write_passes: Some(2),
+            ..CliArgs::default()
+        };
+        let p = Progressive::with_args(&args).unwrap();
+        assert_eq!((p.write_interval, p.write_passes), (None, Some(2)));
+        assert!(Progressive::with_args(&CliArgs::default()).is_none());
+    }
+
+    #[test]
+    fn average_divides_sums() {
+        let mut sums = Framebuffer::new(2, 1);
+        sums.data[1] = ColorRGB::fill(3.0);
+        sums.alpha[1] = 1.5;
+        let fb = average(&sums, 3);
+        assert!((fb.get_color(1, 0).g - 1.0).abs() < 1e-6);
+        assert!((fb.get_alpha(1, 0) - 0.5).abs() < 1e-6);
+    }
+}
@@ -1,6 +1,7 @@
 use image::RgbImage;
 use serde::{Deserialize, Serialize};
 
+use super::color::{srgb_to_linear, WorkingSpace};
 use super::perlin::PerlinNoise;
 use super::ColorRGB;
 use crate::math::Vec3;
@@ -9,18 +10,27 @@ pub trait Texture: Send + Sync {
     fn color(&self, uv: Option<(f32, f32)>, point: Vec3, diffuse: ColorRGB) -> ColorRGB;
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TextureConfig {
-    Image { file: String },
+    Image {
+        file: String,
+        /// The texels are data such as masks rather than colours, so they aren't decoded
+        /// from sRGB in a linear working space
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        linear: bool,
+    },
     Checker { even: ColorRGB, odd: ColorRGB, scale: f32 },
     Marble { scale: f32, frequency: f32, amplitude: f32 },
 }
 
 impl TextureConfig {
     #[must_use]
-    pub fn build(&self) -> Box<dyn Texture> {
+    pub fn build(&self, working_space: WorkingSpace) -> Box<dyn Texture> {
         match self {
-            Self::Image { file } => Box::new(ImageTexture::new(file)),
+            Self::Image { file, linear } => Box::new(ImageTexture::new(
+                file,
+                decode_srgb(working_space, *linear),
+            )),
             Self::Checker { even, odd, scale } => Box::new(CheckerTexture {
                 even: *even,
                 odd: *odd,
@@ -36,16 +46,38 @@ impl TextureConfig {
     }
 }
 
-struct ImageTexture {
+/// Whether 8-bit colour texels are decoded from sRGB when lighting in `working_space`
+#[must_use]
+pub fn decode_srgb(working_space: WorkingSpace, linear: bool) -> bool {
+    working_space == WorkingSpace::LinearSrgb && !linear
+}
+
+pub(crate) struct ImageTexture {
     image: RgbImage,
+    /// Texel value for each 8-bit channel value
+    table: [f32; 256],
 }
 
 impl ImageTexture {
-    fn new(path: &str) -> Self {
+    fn new(path: &str, decode_srgb: bool) -> Self {
         let image = image::open(path)
             .unwrap_or_else(|e| panic!("failed to open texture {path}: {e}"))
             .to_rgb8();
-        ImageTexture { image }
+        ImageTexture::from_image(image, decode_srgb)
+    }
+
+    /// Wrap an already decoded image, e.g. one embedded in a model file
+    pub(crate) fn from_image(image: RgbImage, decode_srgb: bool) -> Self {
+        let table = std::array::from_fn(|i| {
+            #[allow(clippy::cast_precision_loss)]
+            let v = i as f32 / 255.0;
+            if decode_srgb {
+                srgb_to_linear(v)
+            } else {
+                v
+            }
+        });
+        ImageTexture { image, table }
     }
 }
 
@@ -66,9 +98,9 @@ impl Texture for ImageTexture {
         let y = ((1.0 - v) * (h - 1) as f32) as u32; // flip: UV origin is bottom-left
         let pixel = self.image.get_pixel(x, y);
         ColorRGB::new(
-            f32::from(pixel[0]) / 255.0,
-            f32::from(pixel[1]) / 255.0,
-            f32::from(pixel[2]) / 255.0,
+            self.table[usize::from(pixel[0])],
+            self.table[usize::from(pixel[1])],
+            self.table[usize::from(pixel[2])],
         )
     }
 }
@@ -204,4 +236,24 @@ mod tests {
         let c2 = tex.color(None, Vec3::new(1.7, 0.2, 0.9), diffuse);
         assert!((c1.r - c2.r).abs() > 1e-3);
     }
+
+    #[test]
+    fn image_texels_are_decoded_unless_linear() {
+        let image = RgbImage::from_pixel(2, 2, image::Rgb([128, 255, 0]));
+        let decoded = ImageTexture::from_image(image.clone(), true);
+        let c = decoded.color(Some((0.5, 0.5)), Vec3::zeros(), white());
+        assert!((c.r - 0.2158).abs() < 1e-3);
+        assert!((c.g - 1.0).abs() < 1e-5);
+
+        let raw = ImageTexture::from_image(image, false);
+        let c = raw.color(Some((0.5, 0.5)), Vec3::zeros(), white());
+        assert!((c.r - 128.0 / 255.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn only_colour_textures_in_linear_space_are_decoded() {
+        assert!(decode_srgb(WorkingSpace::LinearSrgb, false));
+        assert!(!decode_srgb(WorkingSpace::LinearSrgb, true));
+        assert!(!decode_srgb(WorkingSpace::Srgb, false));
+    }
 }
@@ -0,0 +1,198 @@
+use clap::ValueEnum;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::output::OutputConfig;
+use super::{ColorRGB, Framebuffer};
+
+/// Display transforms applied before 8-bit output
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+pub enum ToneMap {
+    /// Clip each channel to [0,1]
+    #[default]
+    Clamp,
+    /// Reinhard's L / (1 + L) on luminance
+    Reinhard,
+    /// Reinhard with a white point that maps to 1
+    ExtendedReinhard,
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve
+    Aces,
+    /// John Hable's Uncharted 2 filmic curve
+    Hable,
+}
+
+/// Hable's default linear white point
+const HABLE_WHITE: f32 = 11.2;
+
+fn hable_partial(x: f32) -> f32 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.50;
+    const C: f32 = 0.10;
+    const D: f32 = 0.20;
+    const E: f32 = 0.02;
+    const F: f32 = 0.30;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+fn aces(x: f32) -> f32 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+fn per_channel(c: ColorRGB, f: impl Fn(f32) -> f32) -> ColorRGB {
+    ColorRGB::new(f(c.r), f(c.g), f(c.b))
+}
+
+/// Scale a colour so its luminance becomes `mapped`, keeping its hue
+fn with_luminance(c: ColorRGB, luminance: f32, mapped: f32) -> ColorRGB {
+    if luminance > 0.0 {
+        c * (mapped / luminance)
+    } else {
+        ColorRGB::black()
+    }
+}
+
+/// An exposure and tone curve ready to apply to colours
+#[derive(Debug, Clone, Copy)]
+pub struct ToneMapper {
+    op: ToneMap,
+    scale: f32,
+    white: f32,
+}
+
+impl ToneMapper {
+    /// Without a configured white point, extended Reinhard uses the brightest exposed pixel
+    /// of `fb` and Hable uses 11.2
+    #[must_use]
+    pub fn new(config: &OutputConfig, fb: &Framebuffer) -> Self {
+        let scale = config.exposure.exp2();
+        let white = config.white_point.unwrap_or_else(|| match config.tone_map {
+            ToneMap::Hable => HABLE_WHITE,
+            _ => fb
+                .data
+                .par_iter()
+                .map(|c| c.luminance() * scale)
+                .reduce(|| 0.0, f32::max),
+        });
+        ToneMapper {
+            op: config.tone_map,
+            scale,
+            white,
+        }
+    }
+
+    #[must_use]
+    pub fn map(&self, color: ColorRGB) -> ColorRGB {
+        let c = (color * self.scale).clamp(0.0, f32::MAX);
+        let mapped = match self.op {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => {
+                let l = c.luminance();
+                with_luminance(c, l, l / (1.0 + l))
+            }
+            ToneMap::ExtendedReinhard => {
+                let l = c.luminance();
+                let w2 = (self.white * self.white).max(f32::MIN_POSITIVE);
+                with_luminance(c, l, l * (1.0 + l / w2) / (1.0 + l))
+            }
+            ToneMap::Aces => per_channel(c, aces),
+            ToneMap::Hable => {
+                let white_scale = 1.0 / hable_partial(self.white);
+                // Hable's exposure bias of 2
+                per_channel(c, |x| hable_partial(2.0 * x) * white_scale)
+            }
+        };
+        mapped.clamp(0.0, 1.0)
+    }
+}
+
+impl Framebuffer {
+    /// Apply exposure and the tone curve of `config`, giving colours in [0,1]
+    #[must_use]
+    pub fn tone_mapped(&self, config: &OutputConfig) -> Framebuffer {
+        let mapper = ToneMapper::new(config, self);
+        Framebuffer {
+            width: self.width,
+            height: self.height,
+            data: self.data.par_iter().map(|&c| mapper.map(c)).collect(),
+            alpha: self.alpha.clone(),
+            aovs: Vec::new(),
+            metadata: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(tone_map: ToneMap) -> OutputConfig {
+        OutputConfig {
+            tone_map,
+            ..OutputConfig::default()
+        }
+    }
+
+    fn mapper(config: &OutputConfig) -> ToneMapper {
+        ToneMapper::new(config, &Framebuffer::new(1, 1))
+    }
+
+    #[test]
+    fn clamp_is_identity_in_range() {
+        let c = mapper(&config(ToneMap::Clamp)).map(ColorRGB::new(0.25, 0.5, 2.0));
+        assert!((c.r - 0.25).abs() < 1e-5);
+        assert!((c.g - 0.5).abs() < 1e-5);
+        assert!((c.b - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        let mut cfg = config(ToneMap::Clamp);
+        cfg.exposure = -1.0;
+        let c = mapper(&cfg).map(ColorRGB::fill(0.5));
+        assert!((c.r - 0.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn reinhard_compresses_grey() {
+        let c = mapper(&config(ToneMap::Reinhard)).map(ColorRGB::fill(1.0));
+        assert!((c.r - 0.5).abs() < 1e-5);
+        assert!((c.b - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn extended_reinhard_maps_white_point_to_one() {
+        let mut cfg = config(ToneMap::ExtendedReinhard);
+        cfg.white_point = Some(4.0);
+        let c = mapper(&cfg).map(ColorRGB::fill(4.0));
+        assert!((c.g - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn extended_reinhard_defaults_to_brightest_pixel() {
+        let mut fb = Framebuffer::new(2, 1);
+        fb.set_color(1, 0, &ColorRGB::fill(8.0));
+        let mapped = fb.tone_mapped(&config(ToneMap::ExtendedReinhard));
+        assert!((mapped.get_color(1, 0).r - 1.0).abs() < 1e-5);
+        assert!(mapped.get_color(0, 0).r.abs() < 1e-5);
+    }
+
+    #[test]
+    fn filmic_curves_are_monotonic_and_bounded() {
+        for op in [ToneMap::Aces, ToneMap::Hable] {
+            let m = mapper(&config(op));
+            let mut last = -1.0;
+            for i in 0..64 {
+                #[allow(clippy::cast_precision_loss)]
+                let c = m.map(ColorRGB::fill(i as f32 * 0.25));
+                assert!(c.r >= last && c.r <= 1.0, "{op:?} at {i}");
+                last = c.r;
+            }
+        }
+    }
+
+    #[test]
+    fn hable_maps_white_to_one() {
+        let c = mapper(&config(ToneMap::Hable)).map(ColorRGB::fill(HABLE_WHITE / 2.0));
+        assert!((c.r - 1.0).abs() < 1e-4);
+    }
+}
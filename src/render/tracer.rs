@@ -1,9 +1,14 @@
+use std::io;
+use std::ops::{Add, Div};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use super::{ColorRGB, XYCoord};
+use super::aov::Aov;
+use super::{ColorRGB, OutputConfig, XYCoord};
 
-use crate::math::{dot, normalize, reflect, refract, Range, Ray};
+use crate::args::AlphaMode;
+use crate::math::{dot, normalize, reflect, refract, Range, Ray, Vec3};
+use crate::objects::material::VertexColorMode;
 use crate::objects::{Material, Object, Surfel};
 use crate::scene::{Camera, Scene};
 
@@ -12,6 +17,43 @@ pub struct RayTracer {
     scene: Scene,
     camera: Camera,
     objects: Vec<Arc<dyn Object>>,
+    alpha: AlphaMode,
+    keep_background: bool,
+}
+
+/// The colour seen along a ray and how much of it is covered by the scene
+#[derive(Copy, Clone, Debug)]
+pub struct Sample {
+    pub color: ColorRGB,
+    pub alpha: f32,
+}
+
+impl Sample {
+    pub fn opaque(color: ColorRGB) -> Self {
+        Sample { color, alpha: 1.0 }
+    }
+}
+
+impl Add for Sample {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Sample {
+            color: self.color + other.color,
+            alpha: self.alpha + other.alpha,
+        }
+    }
+}
+
+impl Div<f32> for Sample {
+    type Output = Self;
+
+    fn div(self, rhs: f32) -> Self {
+        Sample {
+            color: self.color / rhs,
+            alpha: self.alpha / rhs,
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -53,6 +95,14 @@ impl TraceResult {
         }
     }
 
+    pub fn ray_count(&self) -> u32 {
+        self.ray_count
+    }
+
+    pub fn hit_count(&self) -> u32 {
+        self.hit_count
+    }
+
     #[allow(clippy::cast_precision_loss)]
     pub fn print_stats(&self) {
         let mut hit_percent = 0.0;
@@ -81,21 +131,21 @@ impl<'tracer> TraceContext<'tracer> {
         }
     }
 
-    #[allow(clippy::cast_precision_loss)]
-    pub fn sample_point(&mut self, j: usize, k: usize) -> ColorRGB {
-        let ray = self.tracer.camera.ray_at(j as f32, k as f32);
-        self.trace_ray(&ray)
-    }
-
-    pub fn sample_coord(&mut self, coord: XYCoord) -> ColorRGB {
-        let ray = self.tracer.camera.ray_at(coord.x, coord.y);
-        self.trace_ray(&ray)
+    pub fn sample_coord(&mut self, coord: XYCoord) -> Sample {
+        match self.tracer.camera.ray_at(coord.x, coord.y) {
+            Some(ray) => self.trace_ray(&ray),
+            // outside the image of a panoramic camera
+            None => Sample {
+                color: ColorRGB::black(),
+                alpha: 0.0,
+            },
+        }
     }
 
-    fn trace_ray(&mut self, ray: &Ray) -> ColorRGB {
+    fn trace_ray(&mut self, ray: &Ray) -> Sample {
         self.result.ray_count += 1;
         let start = Instant::now();
-        let (color, hit) = self.tracer.sample_ray(ray);
+        let (sample, hit) = self.tracer.sample_ray(ray, true);
         let stop = Instant::now();
         let delta = stop - start;
         self.result.trace_sum += delta;
@@ -105,21 +155,114 @@ impl<'tracer> TraceContext<'tracer> {
         if hit {
             self.result.hit_count += 1;
         }
-        color
+        sample
     }
 }
 
 impl RayTracer {
-    pub fn new(scene: Scene) -> Self {
+    pub fn new(mut scene: Scene, config: &OutputConfig) -> io::Result<Self> {
         let camera = scene.make_camera();
-        let objects = scene.make_objects();
-        RayTracer {
+        let objects = scene.make_objects()?;
+        Ok(RayTracer {
             scene,
             camera,
             objects,
+            alpha: config.alpha,
+            keep_background: config.keep_background,
+        })
+    }
+
+    /// Whether pixels need every supersample, as the camera's depth of field can blur
+    /// them where their corners agree
+    pub fn sample_uniformly(&self) -> bool {
+        self.camera.has_lens()
+    }
+
+    /// The first surface hit by the primary ray through a pixel, from the center of the lens
+    pub fn primary_hit(&self, j: usize, k: usize) -> Option<Surfel> {
+        #[allow(clippy::cast_precision_loss)]
+        let ray = self.camera.center_ray_at(j as f32, k as f32)?;
+        self.trace_ray(&ray)
+    }
+
+    /// The value of an AOV for a primary hit, single values are in the red channel and
+    /// pairs in red and green
+    pub fn aov_value(&self, aov: Aov, surfel: &Surfel) -> ColorRGB {
+        let material = self.scene.material_for_surfel(surfel);
+        match aov {
+            Aov::Depth => ColorRGB::fill(self.camera.depth_of(surfel.hit_point)),
+            Aov::Normal => {
+                let n = self.shading_normal(surfel, material);
+                ColorRGB::new(n.x(), n.y(), n.z())
+            }
+            Aov::Position => {
+                let p = surfel.hit_point;
+                ColorRGB::new(p.x(), p.y(), p.z())
+            }
+            Aov::Albedo => self.diffuse_color(surfel, material),
+            #[allow(clippy::cast_precision_loss)]
+            Aov::MaterialId => ColorRGB::fill((surfel.material_id.0 + 1) as f32),
+            #[allow(clippy::cast_precision_loss)]
+            Aov::ObjectId => ColorRGB::fill(surfel.object_id as f32),
+            Aov::Uv => surfel
+                .uv
+                .map_or(ColorRGB::black(), |(u, v)| ColorRGB::new(u, v, 0.0)),
+            Aov::Shadow => ColorRGB::fill(self.visibility(surfel, material)),
         }
     }
 
+    /// Fraction of the light samples that reach a surface, 1 where it is fully lit
+    fn visibility(&self, surfel: &Surfel, material: &Material) -> f32 {
+        let n = self.shading_normal(surfel, material);
+        let mut total = 0.0_f32;
+        let mut count = 0_u32;
+        for light in self.scene.lights() {
+            for dir in light.sample_directions_from(surfel.hit_point) {
+                count += 1;
+                let l = normalize(dir);
+                if dot(n, l) > 0.0_f32 {
+                    let ray = Ray {
+                        origin: surfel.hit_point + (0.01_f32 * n),
+                        direction: l,
+                        depth: 0,
+                    };
+                    total += self.shadow_intensity(&ray, 1.0_f32);
+                }
+            }
+        }
+        #[allow(clippy::cast_precision_loss)]
+        if count == 0 {
+            1.0_f32
+        } else {
+            total / count as f32
+        }
+    }
+
+    /// The surface normal, facing the viewer for two-sided materials
+    fn shading_normal(&self, surfel: &Surfel, material: &Material) -> Vec3 {
+        let n = normalize(surfel.normal);
+        let v = self.camera.eye - surfel.hit_point;
+        if material.two_sided && dot(n, v) < 0.0_f32 {
+            -n
+        } else {
+            n
+        }
+    }
+
+    /// Diffuse colour after vertex colours and textures
+    fn diffuse_color(&self, surfel: &Surfel, material: &Material) -> ColorRGB {
+        let base_diffuse = match (surfel.color, material.vertex_colors) {
+            (Some(c), VertexColorMode::Multiply) => c * material.diffuse,
+            (Some(c), VertexColorMode::Replace) => c,
+            (None, _) => material.diffuse,
+        };
+        self.scene
+            .texture_for_surfel(surfel)
+            .map_or(base_diffuse, |tex| {
+                tex.color(surfel.uv, surfel.hit_point, base_diffuse)
+            })
+    }
+
     fn trace_ray(&self, ray: &Ray) -> Option<Surfel> {
         let mut range = Range {
             min: 0.025,
@@ -136,21 +279,40 @@ impl RayTracer {
         surfel
     }
 
-    pub fn sample_ray(&self, ray: &Ray) -> (ColorRGB, bool) {
+    /// Trace a ray into the scene. `see_through` marks camera rays, and the rays they
+    /// transmit with `AlphaMode::Transmission`, whose misses are transparent.
+    pub fn sample_ray(&self, ray: &Ray, see_through: bool) -> (Sample, bool) {
         let max_depth = 5_u32;
         let surfel = self.trace_ray(ray);
 
         if ray.depth > max_depth {
-            return (ColorRGB::black(), false);
+            return (Sample::opaque(ColorRGB::black()), false);
         }
 
         match surfel {
             Some(surf) => {
                 let material = self.scene.material_for_surfel(&surf);
-                let color = self.shade(&surf, material, ray.depth);
-                (color, true)
+                let sample = self.shade(&surf, material, ray.depth, see_through);
+                (sample, true)
+            }
+            None => (self.background(see_through), false),
+        }
+    }
+
+    /// What a ray that misses every object sees
+    fn background(&self, see_through: bool) -> Sample {
+        if !see_through || self.alpha == AlphaMode::Off {
+            Sample::opaque(self.scene.bgcolor())
+        } else if self.keep_background {
+            Sample {
+                color: self.scene.bgcolor(),
+                alpha: 0.0,
+            }
+        } else {
+            Sample {
+                color: ColorRGB::black(),
+                alpha: 0.0,
             }
-            None => (self.scene.bgcolor(), false),
         }
     }
 
@@ -179,19 +341,25 @@ impl RayTracer {
 
     /// Apply shading to the given surface and material
     /// Uses Hall/phong model
-    fn shade(&self, surfel: &Surfel, material: &Material, curr_depth: u32) -> ColorRGB {
+    fn shade(
+        &self,
+        surfel: &Surfel,
+        material: &Material,
+        curr_depth: u32,
+        see_through: bool,
+    ) -> Sample {
         let mut color = ColorRGB::black();
+        let mut alpha = 1.0_f32;
+        let transmit_alpha = see_through && self.alpha == AlphaMode::Transmission;
+        // the part of a transmitted ray's transparency that shows through the surface
+        let transparency =
+            |t: Sample| material.kt * material.transmissive.luminance() * (1.0 - t.alpha);
 
-        let mut n = normalize(surfel.normal);
+        let mut n = self.shading_normal(surfel, material);
         let v = normalize(self.camera.eye - surfel.hit_point); // from P to viewer
         let mut visible_lights = Vec::new();
 
-        let diffuse_color = self
-            .scene
-            .texture_for_surfel(surfel)
-            .map_or(material.diffuse, |tex| {
-                tex.color(surfel.uv, surfel.hit_point, material.diffuse)
-            });
+        let diffuse_color = self.diffuse_color(surfel, material);
 
         for light in self.scene.lights() {
             let dirs = light.sample_directions_from(surfel.hit_point);
@@ -258,7 +426,7 @@ impl RayTracer {
                 direction: r,
                 depth: curr_depth + 1,
             };
-            let reflected_intensity = self.sample_ray(&reflected).0;
+            let reflected_intensity = self.sample_ray(&reflected, false).0.color;
             // specular reflection from other surfaces
             // + kr * Ir * Cs
             reflected_color += material.kr * reflected_intensity * material.specular;
@@ -282,8 +450,9 @@ impl RayTracer {
                     direction: t,
                     depth: curr_depth + 1,
                 };
-                let it = self.sample_ray(&transmitted).0;
-                color += material.kt * it * material.transmissive;
+                let it = self.sample_ray(&transmitted, transmit_alpha).0;
+                color += material.kt * it.color * material.transmissive;
+                alpha -= transparency(it);
 
                 for light in &visible_lights {
                     let l = normalize(light.direction_from(surfel.hit_point));
@@ -308,14 +477,19 @@ impl RayTracer {
                 //let ray = Ray{origin: surfel.hit_point + (surfel.n_offset * n), direction: -v, depth: curr_depth + 1};
 
                 // 2 or 3
-                let it = self.sample_ray(&ray).0;
-                color += material.kt * it * material.transmissive;
+                let it = self.sample_ray(&ray, transmit_alpha).0;
+                color += material.kt * it.color * material.transmissive;
+                alpha -= transparency(it);
             }
         }
 
         // ambient lighting
         let ambient = self.scene.ambient() * material.ka * material.ambient;
         color += ambient;
-        color.clamp(0.0_f32, 1.0_f32)
+        // radiance is left unclamped above so HDR output keeps highlights
+        Sample {
+            color: color.clamp(0.0_f32, f32::MAX),
+            alpha: alpha.clamp(0.0_f32, 1.0_f32),
+        }
     }
 }
@@ -1,18 +1,242 @@
+use std::f32::consts::{PI, TAU};
+use std::io;
+
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
-use crate::math::{cross, normalize, Degree, Ray, Vec3};
+use crate::math::{cross, dot, length, normalize, Degree, Ray, Vec3};
+
+/// Width of the full-frame sensor an `f_stop` is relative to, in meters
+const SENSOR_WIDTH: f32 = 0.036;
+
+/// How rays leave the camera
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    /// Rays spread from the eye through the image plane, covering `fov`
+    #[default]
+    Perspective,
+    /// Parallel rays along the view direction from a plane through the eye, `view_width` across
+    Orthographic,
+    /// Longitude across and latitude down the whole sphere around the eye, for 2:1 images
+    Equirectangular,
+    /// A circle in the middle of the image covering `fov` around the view direction
+    Fisheye,
+    /// Six 90 degree views along the camera axes, laid out as a cross or a strip
+    CubeMap,
+}
+
+impl Projection {
+    /// Whether pixels map to directions around the eye rather than through an image plane
+    fn is_panoramic(self) -> bool {
+        matches!(
+            self,
+            Projection::Equirectangular | Projection::Fisheye | Projection::CubeMap
+        )
+    }
+}
+
+/// How the angle from the view direction grows with the distance from the fisheye center
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FisheyeMapping {
+    /// In proportion to the distance
+    #[default]
+    Equidistant,
+    /// Keeping areas of the sphere in proportion to the image
+    Equisolid,
+}
+
+/// Where the faces of a cube map go in the image
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CubeLayout {
+    /// Left, front, right and back in a row of four, with up above and down below front, for
+    /// 4:3 images
+    #[default]
+    Cross,
+    /// Right, left, up, down, front and back in a row, for 6:1 images
+    Strip,
+}
 
 #[derive(Copy, Clone, Serialize, Deserialize)]
 pub struct CameraConfig {
+    #[serde(default, rename = "type")]
+    pub projection: Projection,
     pub eye: Vec3,     // camera location O
     pub up: Vec3,      // camera view up vector Vup
     pub look_at: Vec3, // camera view out direction Zv
-    pub dist: f32,     // distance to image plane
-    pub fov: Degree,   // field of view
+    #[serde(default = "default_dist")]
+    pub dist: f32, // distance to image plane
+    #[serde(default = "default_fov")]
+    pub fov: Degree, // field of view
+    /// Width of the view in scene units for an orthographic camera
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view_width: Option<f32>,
+    /// Mapping of a fisheye camera [default: Equidistant]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mapping: Option<FisheyeMapping>,
+    /// Face layout of a cube map camera [default: Cross]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<CubeLayout>,
+    /// Lens diameter in scene units, a pinhole when neither this nor `f_stop` is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aperture: Option<f32>,
+    /// Lens diameter as an f-number of a full-frame camera with the same `fov`, taking
+    /// scene units as meters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub f_stop: Option<f32>,
+    /// Distance in focus along the view direction [default: distance to `look_at`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_distance: Option<f32>,
+    /// Point whose distance along the view direction is in focus
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_on: Option<Vec3>,
+    /// Number of aperture blades, a round aperture if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blades: Option<u32>,
+    /// Rotation of the aperture blades
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blade_rotation: Option<Degree>,
+}
+
+fn default_dist() -> f32 {
+    1.0
+}
+
+fn default_fov() -> Degree {
+    Degree(60.0)
+}
+
+impl CameraConfig {
+    /// A pinhole camera
+    pub fn new(eye: Vec3, up: Vec3, look_at: Vec3, dist: f32, fov: Degree) -> Self {
+        CameraConfig {
+            projection: Projection::Perspective,
+            eye,
+            up,
+            look_at,
+            dist,
+            fov,
+            view_width: None,
+            mapping: None,
+            layout: None,
+            aperture: None,
+            f_stop: None,
+            focus_distance: None,
+            focus_on: None,
+            blades: None,
+            blade_rotation: None,
+        }
+    }
+
+    /// Check the settings that can't be rendered, naming the field at fault
+    pub fn check(&self) -> io::Result<()> {
+        let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        if self.aperture.is_some() && self.f_stop.is_some() {
+            return invalid("camera has both aperture and f_stop".to_string());
+        }
+        if self.focus_distance.is_some() && self.focus_on.is_some() {
+            return invalid("camera has both focus_distance and focus_on".to_string());
+        }
+        if self.projection == Projection::Orthographic {
+            match self.view_width {
+                None => return invalid("orthographic camera needs a view_width".to_string()),
+                Some(width) if width <= 0.0 => {
+                    return invalid(format!("camera view_width {width} must be positive"));
+                }
+                Some(_) => {}
+            }
+        }
+        if self.projection == Projection::Fisheye && (self.fov.0 <= 0.0 || self.fov.0 > 360.0) {
+            return invalid(format!(
+                "fisheye camera fov {} must be between 0 and 360 degrees",
+                self.fov.0
+            ));
+        }
+        if let Some(aperture) = self.aperture.filter(|a| *a < 0.0) {
+            return invalid(format!("camera aperture {aperture} must not be negative"));
+        }
+        if let Some(f_stop) = self.f_stop.filter(|f| *f <= 0.0) {
+            return invalid(format!("camera f_stop {f_stop} must be positive"));
+        }
+        if let Some(blades) = self.blades.filter(|b| *b < 3) {
+            return invalid(format!("camera blades {blades} must be at least 3"));
+        }
+        let zv = normalize(self.look_at - self.eye);
+        let focus_distance = self.focus_distance(zv);
+        if self.aperture() > 0.0 && !self.projection.is_panoramic() && focus_distance <= 0.0 {
+            return invalid(format!(
+                "camera focus distance {focus_distance} must be in front of the camera"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Lens diameter in scene units, from `aperture` when `check` finds both it and `f_stop`
+    fn aperture(&self) -> f32 {
+        match (self.aperture, self.f_stop) {
+            (Some(aperture), _) => aperture,
+            (None, Some(f_stop)) => {
+                let focal_length = SENSOR_WIDTH / 2.0 / Degree(self.fov.0 / 2.0).tan();
+                focal_length / f_stop
+            }
+            (None, None) => 0.0,
+        }
+    }
+
+    /// Distance in focus along the view direction `zv`, from `focus_distance` when `check`
+    /// finds both it and `focus_on`
+    fn focus_distance(&self, zv: Vec3) -> f32 {
+        match (self.focus_distance, self.focus_on) {
+            (Some(distance), _) => distance,
+            (None, Some(point)) => dot(point - self.eye, zv),
+            (None, None) => length(self.look_at - self.eye),
+        }
+    }
+}
+
+/// The aperture shape a thin lens samples, as blade count and rotation in radians
+#[derive(Debug, Clone, Copy)]
+enum Aperture {
+    Round,
+    Blades(u32, f32),
+}
+
+impl Aperture {
+    /// A point of the unit-radius aperture for uniform random numbers `u`
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn point(self, u: [f32; 3]) -> (f32, f32) {
+        match self {
+            Aperture::Round => {
+                let r = u[0].sqrt();
+                let phi = TAU * u[1];
+                (r * phi.cos(), r * phi.sin())
+            }
+            Aperture::Blades(blades, rotation) => {
+                // pick one of the equal triangles from the center to each edge, then a point
+                // in it
+                let n = blades as f32;
+                let i = ((u[0] * n) as u32).min(blades - 1) as f32;
+                let a0 = rotation + TAU * i / n;
+                let a1 = a0 + TAU / n;
+                let s = u[1].sqrt();
+                let (b0, b1) = (s * (1.0 - u[2]), s * u[2]);
+                (b0 * a0.cos() + b1 * a1.cos(), b0 * a0.sin() + b1 * a1.sin())
+            }
+        }
+    }
+}
+
+/// Pixel steps across a resolution, at least one so single pixel images have a ray
+fn steps(res: f32) -> f32 {
+    (res - 1.0_f32).max(1.0_f32)
 }
 
 pub struct Camera {
+    projection: Projection,
+    fov: f32,
+    mapping: FisheyeMapping,
+    layout: CubeLayout,
     pub eye: Vec3,
+    zv: Vec3,
     top_left: Vec3,
     xv: Vec3,
     yv: Vec3,
@@ -20,9 +244,13 @@ pub struct Camera {
     sk: f32,
     hres: f32,
     vres: f32,
+    lens_radius: f32,
+    focus_distance: f32,
+    aperture: Aperture,
 }
 
 impl Camera {
+    /// A camera for a config that passes `CameraConfig::check`
     pub fn new(config: &CameraConfig, hres: f32, vres: f32) -> Camera {
         let zv = normalize(config.look_at - config.eye);
         let vup = normalize(config.up);
@@ -30,12 +258,27 @@ impl Camera {
         let xv = normalize(cross(vup, zv));
         let yv = normalize(cross(zv, xv));
 
-        let theta = Degree(config.fov.0 / 2.0_f32);
-        let h = config.dist * theta.tan();
-        let sj = 2.0_f32 * h;
-        let sk = sj * (vres / hres);
+        // the plane rays pass through and its width
+        let (center, sj) = match config.projection {
+            Projection::Perspective => {
+                let theta = Degree(config.fov.0 / 2.0_f32);
+                let h = config.dist * theta.tan();
+                log::debug!("fov:   {}", config.fov.0);
+                log::debug!("theta: {}", theta.0);
+                log::debug!("h:     {h}");
+                log::debug!("dist:  {}", config.dist);
+                (config.eye + config.dist * zv, 2.0_f32 * h)
+            }
+            Projection::Orthographic => (config.eye, config.view_width.unwrap_or(1.0)),
+            // panoramic projections map pixels straight to directions
+            Projection::Equirectangular | Projection::Fisheye | Projection::CubeMap => {
+                (config.eye, 0.0)
+            }
+        };
+        // ray_at spans the image in res - 1 steps, keep them the same size both ways
+        let sk = sj * (steps(vres) / steps(hres));
 
-        let top_left = config.eye + config.dist * zv + (sj / 2.0_f32) * xv + (sk / 2.0_f32) * yv;
+        let top_left = center + (sj / 2.0_f32) * xv + (sk / 2.0_f32) * yv;
 
         log::debug!("eye: {:?}", config.eye);
         log::debug!("zv:  {zv:?}");
@@ -45,13 +288,48 @@ impl Camera {
         log::debug!("sj:  {sj}");
         log::debug!("sk:  {sk}");
         log::debug!("top left {top_left:?}");
-        log::debug!("fov:   {}", config.fov.0);
-        log::debug!("theta: {}", theta.0);
-        log::debug!("h:     {h}");
-        log::debug!("dist:  {}", config.dist);
+
+        let layout = config.layout.unwrap_or_default();
+        let aspect = match config.projection {
+            Projection::Equirectangular => Some(2.0),
+            Projection::CubeMap if layout == CubeLayout::Cross => Some(4.0 / 3.0),
+            Projection::CubeMap => Some(6.0),
+            _ => None,
+        };
+        if let Some(aspect) = aspect {
+            if ((hres / vres) / aspect - 1.0).abs() > 0.01 {
+                log::warn!(
+                    "{:?} camera images are {aspect:.3}:1, not {hres}x{vres}",
+                    config.projection
+                );
+            }
+        }
+        let mut lens_radius = config.aperture() / 2.0;
+        if config.projection.is_panoramic() && lens_radius > 0.0 {
+            log::warn!(
+                "{:?} cameras have no depth of field, ignoring the aperture",
+                config.projection
+            );
+            lens_radius = 0.0;
+        }
+        let focus_distance = config.focus_distance(zv);
+        let aperture = match config.blades {
+            None => Aperture::Round,
+            Some(blades) => {
+                let rotation = config.blade_rotation.map_or(0.0, |r| r.0.to_radians());
+                Aperture::Blades(blades, rotation)
+            }
+        };
+        log::debug!("lens radius: {lens_radius}");
+        log::debug!("focus distance: {focus_distance}");
 
         Camera {
+            projection: config.projection,
+            fov: config.fov.0.to_radians(),
+            mapping: config.mapping.unwrap_or_default(),
+            layout,
             eye: config.eye,
+            zv,
             top_left,
             xv,
             yv,
@@ -59,21 +337,168 @@ impl Camera {
             sk,
             hres,
             vres,
+            lens_radius,
+            focus_distance,
+            aperture,
         }
     }
 
-    pub fn ray_at(&self, jf: f32, kf: f32) -> Ray {
-        let v = (self.top_left
-            - self.sj * (jf / (self.hres - 1.0_f32)) * self.xv
-            - self.sk * (kf / (self.vres - 1.0_f32)) * self.yv)
-            - self.eye;
+    /// Whether rays start across a lens, so pixels blur in ways their corners can't show
+    pub fn has_lens(&self) -> bool {
+        self.lens_radius > 0.0
+    }
+
+    /// Distance of a point in front of the camera along the view direction, or from the eye
+    /// for panoramic projections
+    pub fn depth_of(&self, point: Vec3) -> f32 {
+        if self.projection.is_panoramic() {
+            length(point - self.eye)
+        } else {
+            dot(point - self.eye, self.zv)
+        }
+    }
+
+    /// A ray through the view plane at `jf`, `kf` from a random point of the lens, which
+    /// passes through the same point at the focus distance as the ray from the lens center
+    pub fn ray_at(&self, jf: f32, kf: f32) -> Option<Ray> {
+        let ray = self.center_ray_at(jf, kf)?;
+        if self.lens_radius <= 0.0 {
+            return Some(ray);
+        }
+        let mut rng = rand::thread_rng();
+        let (u, v) = self.aperture.point(rng.gen());
+        let focus =
+            ray.origin + (self.focus_distance / dot(ray.direction, self.zv)) * ray.direction;
+        let origin = ray.origin + self.lens_radius * (u * self.xv + v * self.yv);
+        Some(Ray {
+            origin,
+            direction: normalize(focus - origin),
+            depth: 0,
+        })
+    }
 
-        Ray {
+    /// The ray through the view plane at `jf`, `kf` from the center of the lens, or `None`
+    /// outside the circle of a fisheye and the faces of a cube map
+    pub fn center_ray_at(&self, jf: f32, kf: f32) -> Option<Ray> {
+        // panoramas cover pixel j from j to j + 1 across and k from k - 1 to k down
+        let (u, v) = (jf / self.hres, (kf + 1.0) / self.vres);
+        let direction = match self.projection {
+            Projection::Perspective | Projection::Orthographic => {
+                return Some(self.planar_ray_at(jf, kf));
+            }
+            Projection::Equirectangular => equirectangular(u, v),
+            Projection::Fisheye => self.fisheye(u, v)?,
+            Projection::CubeMap => cube_map(self.layout, u, v)?,
+        };
+        Some(Ray {
             origin: self.eye,
-            direction: normalize(v),
+            // camera space is right, up and forward, and j runs against xv
+            direction: normalize(
+                direction.z() * self.zv + direction.y() * self.yv - direction.x() * self.xv,
+            ),
             depth: 0,
+        })
+    }
+
+    fn planar_ray_at(&self, jf: f32, kf: f32) -> Ray {
+        let p = self.top_left
+            - self.sj * (jf / steps(self.hres)) * self.xv
+            - self.sk * (kf / steps(self.vres)) * self.yv;
+
+        match self.projection {
+            Projection::Orthographic => Ray {
+                origin: p,
+                direction: self.zv,
+                depth: 0,
+            },
+            _ => Ray {
+                origin: self.eye,
+                direction: normalize(p - self.eye),
+                depth: 0,
+            },
         }
     }
+
+    /// Camera space direction at `u`, `v` of the fisheye circle inscribed in the image
+    fn fisheye(&self, u: f32, v: f32) -> Option<Vec3> {
+        let x = (u - 0.5) * self.hres;
+        let y = (0.5 - v) * self.vres;
+        let r = x.hypot(y) / (self.hres.min(self.vres) / 2.0);
+        if r > 1.0 {
+            return None;
+        }
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.fov / 2.0,
+            FisheyeMapping::Equisolid => 2.0 * (r * (self.fov / 4.0).sin()).asin(),
+        };
+        let alpha = y.atan2(x);
+        Some(Vec3::new(
+            theta.sin() * alpha.cos(),
+            theta.sin() * alpha.sin(),
+            theta.cos(),
+        ))
+    }
+}
+
+/// Camera space direction at `u`, `v` of a latitude-longitude image centered on the view
+/// direction
+fn equirectangular(u: f32, v: f32) -> Vec3 {
+    let phi = (u - 0.5) * TAU;
+    let theta = (0.5 - v) * PI;
+    Vec3::new(
+        theta.cos() * phi.sin(),
+        theta.sin(),
+        theta.cos() * phi.cos(),
+    )
+}
+
+/// Forward, right and up of the cube faces in camera space, in strip order: right, left, up,
+/// down, front and back. Up and down meet front along their bottom and top edges.
+const CUBE_FACES: [[[f32; 3]; 3]; 6] = [
+    [[1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]],
+    [[-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]],
+    [[0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]],
+    [[0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+    [[0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+    [[0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+];
+
+/// Camera space direction at `u`, `v` of a cube map, `None` between the arms of a cross
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn cube_map(layout: CubeLayout, u: f32, v: f32) -> Option<Vec3> {
+    let (cols, rows) = match layout {
+        CubeLayout::Cross => (4, 3),
+        CubeLayout::Strip => (6, 1),
+    };
+    let x = (u * cols as f32).max(0.0);
+    let y = (v * rows as f32).max(0.0);
+    let col = (x as usize).min(cols - 1);
+    let row = (y as usize).min(rows - 1);
+    let face = match layout {
+        CubeLayout::Strip => col,
+        CubeLayout::Cross => match (col, row) {
+            (1, 0) => 2,
+            (0, 1) => 1,
+            (1, 1) => 4,
+            (2, 1) => 0,
+            (3, 1) => 5,
+            (1, 2) => 3,
+            _ => return None,
+        },
+    };
+    // position on the face, -1 to 1 rightwards and upwards
+    let a = 2.0 * (x - col as f32) - 1.0;
+    let b = 1.0 - 2.0 * (y - row as f32);
+    let [f, r, up] = CUBE_FACES[face];
+    Some(Vec3::new(
+        f[0] + a * r[0] + b * up[0],
+        f[1] + a * r[1] + b * up[1],
+        f[2] + a * r[2] + b * up[2],
+    ))
 }
 
 #[cfg(test)]
@@ -82,27 +507,27 @@ mod tests {
     use crate::math::Vec3;
 
     fn simple_camera() -> Camera {
-        let config = CameraConfig {
-            eye: Vec3::new(0.0, 0.0, 3.0),
-            up: Vec3::new(0.0, 1.0, 0.0),
-            look_at: Vec3::new(0.0, 0.0, 0.0),
-            dist: 1.0,
-            fov: Degree(60.0),
-        };
+        let config = CameraConfig::new(
+            Vec3::new(0.0, 0.0, 3.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            1.0,
+            Degree(60.0),
+        );
         Camera::new(&config, 512.0, 512.0)
     }
 
     #[test]
     fn center_ray_points_toward_scene() {
         let cam = simple_camera();
-        let ray = cam.ray_at(255.5, 255.5);
+        let ray = cam.ray_at(255.5, 255.5).unwrap();
         assert!(ray.direction.z() < -0.99);
     }
 
     #[test]
     fn ray_direction_is_normalized() {
         let cam = simple_camera();
-        let ray = cam.ray_at(0.0, 0.0);
+        let ray = cam.ray_at(0.0, 0.0).unwrap();
         let len_sq = ray.direction.x() * ray.direction.x()
             + ray.direction.y() * ray.direction.y()
             + ray.direction.z() * ray.direction.z();
@@ -112,7 +537,7 @@ mod tests {
     #[test]
     fn ray_origin_is_eye() {
         let cam = simple_camera();
-        let ray = cam.ray_at(100.0, 200.0);
+        let ray = cam.ray_at(100.0, 200.0).unwrap();
         assert!((ray.origin.x() - 0.0).abs() < 1e-5);
         assert!((ray.origin.y() - 0.0).abs() < 1e-5);
         assert!((ray.origin.z() - 3.0).abs() < 1e-5);
@@ -121,8 +546,293 @@ mod tests {
     #[test]
     fn left_and_right_edge_rays_are_symmetric() {
         let cam = simple_camera();
-        let left = cam.ray_at(0.0, 255.5);
-        let right = cam.ray_at(511.0, 255.5);
+        let left = cam.ray_at(0.0, 255.5).unwrap();
+        let right = cam.ray_at(511.0, 255.5).unwrap();
         assert!((left.direction.x() + right.direction.x()).abs() < 1e-4);
     }
+
+    #[test]
+    fn pixels_are_square_for_any_aspect() {
+        let config = CameraConfig::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            1.0,
+            Degree(60.0),
+        );
+        let cam = Camera::new(&config, 640.0, 120.0);
+        let center = cam.ray_at(319.5, 59.5).unwrap().direction;
+        let right = cam.ray_at(320.5, 59.5).unwrap().direction;
+        let down = cam.ray_at(319.5, 60.5).unwrap().direction;
+        let dx = (right.x() / right.z() - center.x() / center.z()).abs();
+        let dy = (down.y() / down.z() - center.y() / center.z()).abs();
+        assert!((dx - dy).abs() < 1e-6, "{dx} {dy}");
+
+        let single = Camera::new(&config, 1.0, 1.0).ray_at(0.0, 0.0).unwrap();
+        assert!(single.direction.x().is_finite());
+    }
+
+    #[test]
+    fn depth_is_along_view_direction() {
+        let cam = simple_camera();
+        assert!((cam.depth_of(Vec3::new(0.0, 0.0, 0.0)) - 3.0).abs() < 1e-5);
+        assert!((cam.depth_of(Vec3::new(5.0, -2.0, 1.0)) - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn lens_rays_meet_at_focus_distance() {
+        let config = CameraConfig {
+            aperture: Some(0.5),
+            focus_distance: Some(2.0),
+            ..CameraConfig::new(
+                Vec3::new(0.0, 0.0, 3.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, 0.0, 0.0),
+                1.0,
+                Degree(60.0),
+            )
+        };
+        let cam = Camera::new(&config, 64.0, 64.0);
+        let center = cam.center_ray_at(10.0, 20.0).unwrap();
+        let target = center.origin + (2.0 / -center.direction.z()) * center.direction;
+        let mut spread = 0.0_f32;
+        for _ in 0..32 {
+            let ray = cam.ray_at(10.0, 20.0).unwrap();
+            spread = spread.max(length(ray.origin - cam.eye));
+            let t = (ray.origin.z() - target.z()) / -ray.direction.z();
+            let hit = ray.origin + t * ray.direction;
+            assert!(length(hit - target) < 1e-4);
+        }
+        assert!(spread > 0.05 && spread <= 0.25 + 1e-5, "{spread}");
+    }
+
+    #[test]
+    fn check_names_the_invalid_field() {
+        let base = CameraConfig::new(
+            Vec3::new(0.0, 0.0, 3.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            1.0,
+            Degree(60.0),
+        );
+        assert!(base.check().is_ok());
+        let cases = [
+            (
+                CameraConfig {
+                    aperture: Some(0.1),
+                    f_stop: Some(2.0),
+                    ..base
+                },
+                "f_stop",
+            ),
+            (
+                CameraConfig {
+                    focus_distance: Some(2.0),
+                    focus_on: Some(Vec3::zeros()),
+                    ..base
+                },
+                "focus_on",
+            ),
+            (
+                CameraConfig {
+                    blades: Some(2),
+                    ..base
+                },
+                "blades",
+            ),
+            (
+                CameraConfig {
+                    aperture: Some(-0.1),
+                    ..base
+                },
+                "aperture",
+            ),
+            (
+                CameraConfig {
+                    projection: Projection::Orthographic,
+                    ..base
+                },
+                "view_width",
+            ),
+            (
+                CameraConfig {
+                    projection: Projection::Fisheye,
+                    fov: Degree(400.0),
+                    ..base
+                },
+                "fov",
+            ),
+        ];
+        for (config, field) in cases {
+            let err = config.check().err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains(field), "{err}");
+        }
+    }
+
+    #[test]
+    fn f_stop_and_focus_on_set_the_lens() {
+        let mut config = CameraConfig {
+            f_stop: Some(2.0),
+            focus_on: Some(Vec3::new(1.0, 0.0, 1.0)),
+            ..CameraConfig::new(
+                Vec3::new(0.0, 0.0, 3.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, 0.0, 0.0),
+                1.0,
+                Degree(90.0),
+            )
+        };
+        // a 90 degree full-frame lens is 18 mm
+        assert!((config.aperture() - 0.009).abs() < 1e-6);
+        let zv = Vec3::new(0.0, 0.0, -1.0);
+        assert!((config.focus_distance(zv) - 2.0).abs() < 1e-6);
+        config.f_stop = None;
+        config.focus_on = None;
+        assert!(config.aperture().abs() < 1e-9);
+        assert!((config.focus_distance(zv) - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn aperture_points_stay_inside_the_blades() {
+        let hexagon = Aperture::Blades(6, 0.3);
+        let apothem = (std::f32::consts::PI / 6.0).cos();
+        let steps = [0.0, 0.1, 0.37, 0.5, 0.81, 0.999];
+        for &a in &steps {
+            for &b in &steps {
+                for &c in &steps {
+                    let (x, y) = Aperture::Round.point([a, b, c]);
+                    assert!(x * x + y * y <= 1.0 + 1e-5);
+                    let (x, y) = hexagon.point([a, b, c]);
+                    for i in 0..6 {
+                        let normal = 0.3 + TAU * (i as f32 + 0.5) / 6.0;
+                        assert!(x * normal.cos() + y * normal.sin() <= apothem + 1e-5);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let config = CameraConfig {
+            projection: Projection::Orthographic,
+            view_width: Some(4.0),
+            ..CameraConfig::new(
+                Vec3::new(0.0, 0.0, 3.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, 0.0, 0.0),
+                1.0,
+                Degree(60.0),
+            )
+        };
+        let cam = Camera::new(&config, 65.0, 33.0);
+        let left = cam.ray_at(0.0, 16.0).unwrap();
+        let right = cam.ray_at(64.0, 16.0).unwrap();
+        let top = cam.ray_at(32.0, 0.0).unwrap();
+        for ray in [&left, &right, &top] {
+            assert!(ray.direction.z() < -0.9999);
+            assert!((ray.origin.z() - 3.0).abs() < 1e-5);
+        }
+        assert!((length(left.origin - right.origin) - 4.0).abs() < 1e-4);
+        assert!((top.origin.y().abs() - 1.0).abs() < 1e-4);
+        assert!(length(cam.ray_at(32.0, 16.0).unwrap().origin - cam.eye) < 1e-5);
+    }
+
+    /// A panoramic camera at the origin looking down -z, so right is +x and up is +y
+    fn panoramic(projection: Projection, config: CameraConfig, hres: f32, vres: f32) -> Camera {
+        let config = CameraConfig {
+            projection,
+            ..config
+        };
+        Camera::new(&config, hres, vres)
+    }
+
+    fn origin_config(fov: f32) -> CameraConfig {
+        CameraConfig::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            1.0,
+            Degree(fov),
+        )
+    }
+
+    fn direction_at(cam: &Camera, u: f32, v: f32) -> Option<Vec3> {
+        // the inverse of the pixel mapping in center_ray_at
+        cam.center_ray_at(u * cam.hres, v * cam.vres - 1.0)
+            .map(|ray| ray.direction)
+    }
+
+    fn assert_direction(d: Option<Vec3>, expected: [f32; 3]) {
+        let d = d.expect("expected a ray");
+        let e = Vec3::new(expected[0], expected[1], expected[2]);
+        assert!(length(d - normalize(e)) < 1e-4, "{d:?} != {e:?}");
+    }
+
+    #[test]
+    fn equirectangular_covers_the_sphere() {
+        let cam = panoramic(Projection::Equirectangular, origin_config(60.0), 64.0, 32.0);
+        assert_direction(direction_at(&cam, 0.5, 0.5), [0.0, 0.0, -1.0]);
+        assert_direction(direction_at(&cam, 0.75, 0.5), [1.0, 0.0, 0.0]);
+        assert_direction(direction_at(&cam, 0.0, 0.5), [0.0, 0.0, 1.0]);
+        assert_direction(direction_at(&cam, 0.3, 0.0), [0.0, 1.0, 0.0]);
+        assert!((cam.depth_of(Vec3::new(0.0, 0.0, 2.0)) - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn fisheye_maps_radius_to_angle() {
+        let cam = panoramic(Projection::Fisheye, origin_config(180.0), 64.0, 64.0);
+        assert_direction(direction_at(&cam, 0.5, 0.5), [0.0, 0.0, -1.0]);
+        assert_direction(direction_at(&cam, 1.0, 0.5), [1.0, 0.0, 0.0]);
+        assert_direction(direction_at(&cam, 0.5, 0.0), [0.0, 1.0, 0.0]);
+        assert_direction(direction_at(&cam, 0.75, 0.5), [1.0, 0.0, -1.0]);
+        assert!(direction_at(&cam, 0.02, 0.02).is_none());
+
+        let config = CameraConfig {
+            mapping: Some(FisheyeMapping::Equisolid),
+            ..origin_config(180.0)
+        };
+        let cam = panoramic(Projection::Fisheye, config, 64.0, 64.0);
+        assert_direction(direction_at(&cam, 1.0, 0.5), [1.0, 0.0, 0.0]);
+        // halfway out is nearer the axis than the equidistant 45 degrees
+        let theta = 2.0 * (0.5 * 45.0_f32.to_radians().sin()).asin();
+        let d = direction_at(&cam, 0.75, 0.5).unwrap();
+        assert!((d.x() - theta.sin()).abs() < 1e-4, "{d:?}");
+    }
+
+    #[test]
+    fn cube_map_faces_meet_at_their_edges() {
+        let cam = panoramic(Projection::CubeMap, origin_config(60.0), 64.0, 48.0);
+        // face centers of the cross
+        assert_direction(direction_at(&cam, 1.5 / 4.0, 0.5), [0.0, 0.0, -1.0]);
+        assert_direction(direction_at(&cam, 2.5 / 4.0, 0.5), [1.0, 0.0, 0.0]);
+        assert_direction(direction_at(&cam, 3.5 / 4.0, 0.5), [0.0, 0.0, 1.0]);
+        assert_direction(direction_at(&cam, 0.5 / 4.0, 0.5), [-1.0, 0.0, 0.0]);
+        assert_direction(direction_at(&cam, 1.5 / 4.0, 0.5 / 3.0), [0.0, 1.0, 0.0]);
+        assert_direction(direction_at(&cam, 1.5 / 4.0, 2.5 / 3.0), [0.0, -1.0, 0.0]);
+        assert!(direction_at(&cam, 0.1, 0.1).is_none());
+        // front's top edge is up's bottom edge, front's right edge is right's left edge
+        assert_direction(direction_at(&cam, 1.5 / 4.0, 1.0 / 3.0), [0.0, 1.0, -1.0]);
+        assert_direction(direction_at(&cam, 0.5, 0.5), [1.0, 0.0, -1.0]);
+
+        let config = CameraConfig {
+            layout: Some(CubeLayout::Strip),
+            ..origin_config(60.0)
+        };
+        let cam = panoramic(Projection::CubeMap, config, 96.0, 16.0);
+        let centers = [
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, -1.0],
+            [0.0, 0.0, 1.0],
+        ];
+        for (i, center) in centers.into_iter().enumerate() {
+            #[allow(clippy::cast_precision_loss)]
+            let u = (i as f32 + 0.5) / 6.0;
+            assert_direction(direction_at(&cam, u, 0.5), center);
+        }
+    }
 }
@@ -0,0 +1,87 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::args::SceneFormat;
+use crate::objects::Material;
+
+impl SceneFormat {
+    /// The format for a file extension, if it is one we know
+    pub fn from_path(path: &Path) -> Option<SceneFormat> {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("yaml" | "yml") => Some(SceneFormat::Yaml),
+            Some("json") => Some(SceneFormat::Json),
+            Some("toml") => Some(SceneFormat::Toml),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            SceneFormat::Yaml => "yaml",
+            SceneFormat::Json => "json",
+            SceneFormat::Toml => "toml",
+        }
+    }
+}
+
+fn invalid_data(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {e}", path.display()),
+    )
+}
+
+/// Read a scene or material file, choosing the format by extension. Files with other
+/// extensions are read as YAML.
+pub fn read_document<T: DeserializeOwned>(path: &Path) -> io::Result<T> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to open {}: {e}", path.display())))?;
+
+    match SceneFormat::from_path(path).unwrap_or_default() {
+        SceneFormat::Yaml => serde_yml::from_str(&text).map_err(|e| invalid_data(path, e)),
+        SceneFormat::Json => serde_json::from_str(&text).map_err(|e| invalid_data(path, e)),
+        SceneFormat::Toml => toml::from_str(&text).map_err(|e| invalid_data(path, e)),
+    }
+}
+
+/// TOML can't have a list at the top level, so TOML libraries hold a `materials` table array
+#[derive(Deserialize)]
+struct MaterialTable {
+    materials: Vec<Material>,
+}
+
+/// Read a YAML, JSON or TOML material library, chosen by extension
+pub fn read_materials(path: &Path) -> io::Result<Vec<Material>> {
+    log::info!("loading materials from: {}", path.display());
+    if SceneFormat::from_path(path) == Some(SceneFormat::Toml) {
+        read_document::<MaterialTable>(path).map(|table| table.materials)
+    } else {
+        read_document(path)
+    }
+}
+
+/// Serialize a document in the given format
+pub fn write_document<T: Serialize, W: Write>(
+    value: &T,
+    format: SceneFormat,
+    mut out: W,
+) -> io::Result<()> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    match format {
+        SceneFormat::Yaml => serde_yml::to_writer(out, value).map_err(|e| invalid(e.to_string())),
+        SceneFormat::Json => {
+            serde_json::to_writer_pretty(out, value).map_err(|e| invalid(e.to_string()))
+        }
+        SceneFormat::Toml => {
+            let text = toml::to_string(value).map_err(|e| invalid(e.to_string()))?;
+            out.write_all(text.as_bytes())
+        }
+    }
+}
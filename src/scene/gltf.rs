@@ -0,0 +1,593 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use ::gltf::image::{Data as ImageData, Format};
+use ::gltf::khr_lights_punctual::Kind;
+use ::gltf::material::AlphaMode;
+use ::gltf::mesh::Mode;
+use ::gltf::{buffer, camera::Projection, Document, Node};
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+
+use super::camera::{CameraConfig, Projection as CameraProjection};
+use super::lights::LightsConfig;
+use crate::lights::{PointLight, SpotLight};
+use crate::math::{normalize, Degree, Mat4, Vec3};
+use crate::objects::material::MaterialID;
+use crate::objects::mesh::{compute_bbox, compute_normals, Triangle};
+use crate::objects::transform::Transform;
+use crate::objects::{Instance, Material, MaterialMap, Mesh, Object};
+use crate::render::texture::{decode_srgb, ImageTexture};
+use crate::render::ColorRGB;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GltfConfig {
+    pub file: String, // .gltf or .glb, relative to mesh_dir
+    #[serde(default)]
+    pub material: String, // used for primitives without a material, if set
+    #[serde(default)]
+    pub transform: Transform,
+    #[serde(default)]
+    pub camera: bool, // use the file's first camera if the scene has none
+    #[serde(default)]
+    pub lights: bool, // add the file's KHR_lights_punctual lights
+}
+
+fn gltf_error(path: &Path, e: &::gltf::Error) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {e}", path.display()),
+    )
+}
+
+/// Walk the nodes of the default scene (or the first one if none is marked default),
+/// calling `visit` with each node and its world transform.
+fn visit_nodes(document: &Document, root: &Mat4, visit: &mut impl FnMut(&Node, &Mat4)) {
+    fn walk(node: &Node, parent: &Mat4, visit: &mut impl FnMut(&Node, &Mat4)) {
+        let world = parent * &Mat4::from_cols(node.transform().matrix());
+        visit(node, &world);
+        for child in node.children() {
+            walk(&child, &world, visit);
+        }
+    }
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next());
+    for node in scene.iter().flat_map(|s| s.nodes()) {
+        walk(&node, root, visit);
+    }
+}
+
+/// Read a triangle primitive, `None` if it has another mode or no positions. Indices past the
+/// end of the positions are an error.
+fn read_primitive(
+    primitive: &::gltf::Primitive,
+    buffers: &[buffer::Data],
+    path: &Path,
+) -> io::Result<Option<Mesh>> {
+    if primitive.mode() != Mode::Triangles {
+        log::warn!("skipping gltf primitive with mode {:?}", primitive.mode());
+        return Ok(None);
+    }
+
+    let reader = primitive.reader(|b| buffers.get(b.index()).map(|data| &data.0[..]));
+    let Some(positions) = reader.read_positions() else {
+        return Ok(None);
+    };
+    let vertices: Vec<Vec3> = positions.map(|[x, y, z]| Vec3::new(x, y, z)).collect();
+
+    let indices: Vec<usize> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+        None => (0..vertices.len()).collect(),
+    };
+    if let Some(index) = indices.iter().find(|&&i| i >= vertices.len()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: primitive {} has index {index} but only {} positions",
+                path.display(),
+                primitive.index(),
+                vertices.len()
+            ),
+        ));
+    }
+    let triangles: Vec<Triangle> = indices
+        .chunks_exact(3)
+        .map(|t| Triangle {
+            i: t[0],
+            j: t[1],
+            k: t[2],
+        })
+        .collect();
+
+    let normals = match reader.read_normals() {
+        Some(normals) => normals.map(|[x, y, z]| Vec3::new(x, y, z)).collect(),
+        None => compute_normals(&vertices, &triangles, false),
+    };
+    // glTF puts the UV origin at the top left, textures here expect it bottom left
+    let uvs = reader
+        .read_tex_coords(0)
+        .map(|uvs| uvs.into_f32().map(|[u, v]| (u, 1.0 - v)).collect());
+    let colors = reader.read_colors(0).map(|colors| {
+        colors
+            .into_rgb_f32()
+            .map(|[r, g, b]| ColorRGB::new(r, g, b))
+            .collect()
+    });
+
+    let bbox = compute_bbox(&vertices);
+    Ok(Some(Mesh {
+        vertices,
+        triangles,
+        normals,
+        uvs,
+        colors,
+        bbox,
+    }))
+}
+
+fn to_rgb_image(data: &ImageData) -> Option<RgbImage> {
+    let channels = match data.format {
+        Format::R8 => 1,
+        Format::R8G8 => 2,
+        Format::R8G8B8 => 3,
+        Format::R8G8B8A8 => 4,
+        format => {
+            log::warn!("unsupported gltf texture format {format:?}");
+            return None;
+        }
+    };
+    let pixels = data
+        .pixels
+        .chunks_exact(channels)
+        .flat_map(|p| {
+            if channels < 3 {
+                [p[0]; 3]
+            } else {
+                [p[0], p[1], p[2]]
+            }
+        })
+        .collect();
+    RgbImage::from_raw(data.width, data.height, pixels)
+}
+
+/// Approximate a metallic-roughness material with the Phong style parameters used here.
+/// Metals lose their diffuse colour and tint their highlights and reflections.
+fn convert_material(material: &::gltf::Material, name: String) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, alpha] = pbr.base_color_factor();
+    let base = ColorRGB::new(r, g, b);
+    let metallic = pbr.metallic_factor();
+    let roughness = pbr.roughness_factor();
+    let diffuse = base * (1.0 - metallic);
+    let a = (roughness * roughness).max(1e-3);
+
+    let mut mat = Material {
+        name,
+        ambient: diffuse,
+        diffuse,
+        specular: ColorRGB::white() * (1.0 - metallic) + base * metallic,
+        ka: 0.1,
+        ks: 1.0 - roughness,
+        kr: metallic * (1.0 - roughness),
+        shininess: (2.0 / (a * a) - 2.0).clamp(1.0, 1000.0),
+        ..Default::default()
+    };
+    if material.alpha_mode() == AlphaMode::Blend {
+        mat.kt = 1.0 - alpha;
+        mat.transmissive = ColorRGB::white();
+        mat.ior = 1.0;
+    }
+    mat
+}
+
+/// The name a material goes by within its file: its own name, with its index added when
+/// other materials share the name, else its index
+fn material_label(material: &::gltf::Material, document: &Document) -> String {
+    match (material.name(), material.index()) {
+        (Some(name), Some(index)) => {
+            let shared = document
+                .materials()
+                .filter(|m| m.name() == Some(name))
+                .count()
+                > 1;
+            if shared {
+                format!("{name}.{index}")
+            } else {
+                name.to_string()
+            }
+        }
+        (Some(name), None) => name.to_string(),
+        (None, Some(index)) => index.to_string(),
+        (None, None) => "default".to_string(),
+    }
+}
+
+/// Register a glTF material, including its base colour texture, and return its id
+fn register_material(
+    material: &::gltf::Material,
+    document: &Document,
+    file: &str,
+    images: &[ImageData],
+    materials: &mut MaterialMap,
+) -> MaterialID {
+    let name = format!("{file}#{}", material_label(material, document));
+    // the file was already loaded for another object
+    if let Some(id) = materials.find_material_id(&name) {
+        return id;
+    }
+    let mat = convert_material(material, name);
+
+    let texture = material
+        .pbr_metallic_roughness()
+        .base_color_texture()
+        .and_then(|info| images.get(info.texture().source().index()))
+        .and_then(to_rgb_image);
+    match texture {
+        Some(image) => {
+            // base colour textures are sRGB encoded
+            let decode = decode_srgb(materials.working_space(), false);
+            let texture = ImageTexture::from_image(image, decode);
+            materials.register_material_with_texture(mat, Box::new(texture))
+        }
+        None => materials.register_material(mat),
+    }
+}
+
+fn make_instances(
+    document: &Document,
+    buffers: &[buffer::Data],
+    images: &[ImageData],
+    config: &GltfConfig,
+    materials: &mut MaterialMap,
+    path: &Path,
+) -> io::Result<Vec<Arc<dyn Object>>> {
+    let mut meshes: HashMap<(usize, usize), Arc<Mesh>> = HashMap::new();
+    for mesh in document.meshes() {
+        for primitive in mesh.primitives() {
+            if let Some(part) = read_primitive(&primitive, buffers, path)? {
+                meshes.insert((mesh.index(), primitive.index()), Arc::new(part));
+            }
+        }
+    }
+    let mut material_ids: HashMap<Option<usize>, MaterialID> = HashMap::new();
+    let mut instances: Vec<Arc<dyn Object>> = Vec::new();
+
+    visit_nodes(document, &config.transform.mat4(), &mut |node, world| {
+        let Some(mesh) = node.mesh() else {
+            return;
+        };
+        let Some(inverse) = world.inverse() else {
+            log::warn!(
+                "skipping gltf node {} with singular transform",
+                node.index()
+            );
+            return;
+        };
+
+        for primitive in mesh.primitives() {
+            let Some(part) = meshes.get(&(mesh.index(), primitive.index())) else {
+                continue;
+            };
+
+            let material = primitive.material();
+            let material_id = *material_ids.entry(material.index()).or_insert_with(|| {
+                if material.index().is_none() && !config.material.is_empty() {
+                    materials.get_material_id(&config.material)
+                } else {
+                    // without a scene material, glTF's own default material is used
+                    register_material(&material, document, &config.file, images, materials)
+                }
+            });
+
+            instances.push(Arc::new(Instance::from_matrices(
+                part.clone(),
+                material_id,
+                *world,
+                inverse,
+            )));
+        }
+    });
+
+    log::debug!("gltf instances: {}", instances.len());
+    Ok(instances)
+}
+
+/// Load every mesh in a glTF scene as instances placed by the node hierarchy.
+/// Materials and base colour textures are registered under `<file>#<material name>`.
+pub fn load_gltf(
+    path: &Path,
+    config: &GltfConfig,
+    materials: &mut MaterialMap,
+) -> io::Result<Vec<Arc<dyn Object>>> {
+    log::info!("loading gltf scene from: {}", path.display());
+    let (document, buffers, images) = ::gltf::import(path).map_err(|e| gltf_error(path, &e))?;
+    make_instances(&document, &buffers, &images, config, materials, path)
+}
+
+/// Read just the document (no buffers or images), for cameras and lights
+pub fn open_document(path: &Path) -> io::Result<Document> {
+    ::gltf::Gltf::open(path)
+        .map(|gltf| gltf.document)
+        .map_err(|e| gltf_error(path, &e))
+}
+
+/// The first camera in the scene. glTF cameras look down -Z with a vertical field of view,
+/// which is converted to the horizontal one for the given aspect ratio, or for orthographic
+/// cameras a half width `xmag`.
+pub fn find_camera(document: &Document, config: &GltfConfig, aspect: f32) -> Option<CameraConfig> {
+    let mut camera = None;
+    visit_nodes(document, &config.transform.mat4(), &mut |node, world| {
+        if camera.is_some() {
+            return;
+        }
+        let Some(cam) = node.camera() else {
+            return;
+        };
+        match cam.projection() {
+            Projection::Perspective(p) => {
+                let eye = world.transform_point(Vec3::zeros());
+                let hfov = 2.0 * ((p.yfov() / 2.0).tan() * aspect).atan();
+                camera = Some(CameraConfig::new(
+                    eye,
+                    normalize(world.transform_vector(Vec3::new(0.0, 1.0, 0.0))),
+                    eye + normalize(world.transform_vector(Vec3::new(0.0, 0.0, -1.0))),
+                    1.0,
+                    Degree(hfov.to_degrees()),
+                ));
+            }
+            Projection::Orthographic(o) => {
+                let eye = world.transform_point(Vec3::zeros());
+                camera = Some(CameraConfig {
+                    projection: CameraProjection::Orthographic,
+                    view_width: Some(2.0 * o.xmag()),
+                    ..CameraConfig::new(
+                        eye,
+                        normalize(world.transform_vector(Vec3::new(0.0, 1.0, 0.0))),
+                        eye + normalize(world.transform_vector(Vec3::new(0.0, 0.0, -1.0))),
+                        1.0,
+                        Degree(60.0),
+                    )
+                });
+            }
+        }
+    });
+    camera
+}
+
+/// Falloff of a spot light from its cone angles. A cone with no room between the inner and
+/// outer angle, including a zero outer angle, has a hard edge.
+fn spot_sharpness(inner: f32, outer: f32) -> f32 {
+    if outer <= inner {
+        return 0.0;
+    }
+    2.0 * (1.0 - inner / outer)
+}
+
+/// Convert KHR_lights_punctual point and spot lights. Intensities are photometric so they are
+/// only used to dim the colour; directional lights have no equivalent and are skipped.
+pub fn find_lights(document: &Document, config: &GltfConfig) -> Vec<LightsConfig> {
+    let mut lights = Vec::new();
+    visit_nodes(document, &config.transform.mat4(), &mut |node, world| {
+        let Some(light) = node.light() else {
+            return;
+        };
+        let [r, g, b] = light.color();
+        let color = ColorRGB::new(r, g, b) * light.intensity().min(1.0);
+        let position = world.transform_point(Vec3::zeros());
+
+        match light.kind() {
+            Kind::Point => lights.push(LightsConfig::Point(PointLight {
+                position,
+                ambient: ColorRGB::black(),
+                diffuse: color,
+                specular: color,
+            })),
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => lights.push(LightsConfig::Spot(SpotLight {
+                color,
+                position,
+                direction: normalize(world.transform_vector(Vec3::new(0.0, 0.0, -1.0))),
+                angle: Degree(outer_cone_angle.to_degrees()),
+                sharpness: spot_sharpness(inner_cone_angle, outer_cone_angle),
+            })),
+            Kind::Directional => {
+                log::warn!("skipping directional gltf light {}", light.index());
+            }
+        }
+    });
+    lights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::color::WorkingSpace;
+
+    /// One triangle in a node translated by (0, 0, -5), with a camera at the origin and a
+    /// point light above it. The buffer holds the positions (0,0,0), (1,0,0), (0,1,0).
+    const TRIANGLE: &str = r#"{
+        "asset": {"version": "2.0"},
+        "extensionsUsed": ["KHR_lights_punctual"],
+        "extensions": {"KHR_lights_punctual": {"lights": [
+            {"type": "point", "color": [1, 0.5, 0.5], "intensity": 0.5}
+        ]}},
+        "scene": 0,
+        "scenes": [{"nodes": [0, 1, 2]}],
+        "nodes": [
+            {"mesh": 0, "translation": [0, 0, -5]},
+            {"camera": 0},
+            {"translation": [0, 3, 0], "extensions": {"KHR_lights_punctual": {"light": 0}}}
+        ],
+        "cameras": [{"type": "perspective", "perspective": {"yfov": 1.0, "znear": 0.1}}],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "material": 0}]}],
+        "materials": [{"name": "gold", "pbrMetallicRoughness": {
+            "baseColorFactor": [1, 0.8, 0.2, 1], "metallicFactor": 1, "roughnessFactor": 0.5
+        }}],
+        "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                       "min": [0, 0, 0], "max": [1, 1, 0]}],
+        "bufferViews": [{"buffer": 0, "byteLength": 36}],
+        "buffers": [{"byteLength": 36, "uri":
+            "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"}]
+    }"#;
+
+    fn config() -> GltfConfig {
+        GltfConfig {
+            file: "test.gltf".to_string(),
+            material: String::new(),
+            transform: Transform::default(),
+            camera: true,
+            lights: true,
+        }
+    }
+
+    #[test]
+    fn reads_triangle_primitive() {
+        let (document, buffers, _) = ::gltf::import_slice(TRIANGLE).unwrap();
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        let mesh = read_primitive(&primitive, &buffers, Path::new("test.gltf"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(mesh.vertices.len(), 3);
+        assert_eq!(mesh.triangles.len(), 1);
+        assert!((mesh.normals[0].z() - 1.0).abs() < 1e-5);
+        assert!(mesh.uvs.is_none());
+    }
+
+    #[test]
+    fn index_past_positions_is_an_error() {
+        // index the same triangle with (0, 1, 5), stored after the positions in the buffer
+        let json = TRIANGLE
+            .replace(r#""POSITION": 0}"#, r#""POSITION": 0}, "indices": 1"#)
+            .replace(
+                r#""max": [1, 1, 0]}]"#,
+                r#""max": [1, 1, 0]},
+                    {"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}]"#,
+            )
+            .replace(
+                r#"[{"buffer": 0, "byteLength": 36}]"#,
+                r#"[{"buffer": 0, "byteLength": 36},
+                    {"buffer": 0, "byteOffset": 36, "byteLength": 6}]"#,
+            )
+            .replace(r#""byteLength": 36, "uri""#, r#""byteLength": 44, "uri""#)
+            .replace(
+                "AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA",
+                "AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAABAAUAAAA=",
+            );
+        let (document, buffers, _) = ::gltf::import_slice(json).unwrap();
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        let err = read_primitive(&primitive, &buffers, Path::new("test.gltf"))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("index 5"), "{err}");
+    }
+
+    #[test]
+    fn node_transform_places_mesh() {
+        let (document, _, _) = ::gltf::import_slice(TRIANGLE).unwrap();
+        let mut origin = None;
+        visit_nodes(&document, &Mat4::identity(), &mut |node, world| {
+            if node.mesh().is_some() {
+                origin = Some(world.transform_point(Vec3::zeros()));
+            }
+        });
+        assert!((origin.unwrap().z() + 5.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn metal_has_no_diffuse() {
+        let (document, _, _) = ::gltf::import_slice(TRIANGLE).unwrap();
+        let material = document.materials().next().unwrap();
+        let mat = convert_material(&material, "test.gltf#gold".to_string());
+        assert!(mat.diffuse.r.abs() < 1e-5);
+        assert!((mat.specular.g - 0.8).abs() < 1e-5);
+        assert!((mat.kr - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn primitive_without_material_uses_default() {
+        let json = TRIANGLE.replace(r#", "material": 0}"#, "}");
+        let (document, buffers, images) = ::gltf::import_slice(json).unwrap();
+        let mut materials = MaterialMap::from_materials(Vec::new(), WorkingSpace::default());
+        let instances = make_instances(
+            &document,
+            &buffers,
+            &images,
+            &config(),
+            &mut materials,
+            Path::new("test.gltf"),
+        )
+        .unwrap();
+        assert_eq!(instances.len(), 1);
+        assert!(materials.find_material_id("test.gltf#default").is_some());
+    }
+
+    #[test]
+    fn materials_sharing_a_name_are_kept_apart() {
+        let json = TRIANGLE
+            .replace(
+                r#""meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "material": 0}]}]"#,
+                r#""meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "material": 0},
+                                              {"attributes": {"POSITION": 0}, "material": 1}]}]"#,
+            )
+            .replace(
+                r#""materials": [{"name": "gold", "#,
+                r#""materials": [{"name": "gold"}, {"name": "gold", "#,
+            );
+        let (document, buffers, images) = ::gltf::import_slice(json).unwrap();
+        let mut materials = MaterialMap::from_materials(Vec::new(), WorkingSpace::default());
+        make_instances(
+            &document,
+            &buffers,
+            &images,
+            &config(),
+            &mut materials,
+            Path::new("test.gltf"),
+        )
+        .unwrap();
+        let first = materials.find_material_id("test.gltf#gold.0").unwrap();
+        let second = materials.find_material_id("test.gltf#gold.1").unwrap();
+        assert!((materials.get_material(first).kr).abs() < 1e-5);
+        assert!((materials.get_material(second).kr - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn spot_without_cone_has_hard_edge() {
+        assert!(spot_sharpness(0.0, 0.0).abs() < 1e-6);
+        assert!(spot_sharpness(0.5, 0.25).abs() < 1e-6);
+        assert!((spot_sharpness(0.25, 0.5) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn camera_and_lights() {
+        let (document, _, _) = ::gltf::import_slice(TRIANGLE).unwrap();
+        let camera = find_camera(&document, &config(), 1.0).unwrap();
+        assert!((camera.fov.0 - 1.0_f32.to_degrees()).abs() < 1e-3);
+        assert!((camera.look_at.z() + 1.0).abs() < 1e-5);
+
+        let lights = find_lights(&document, &config());
+        assert_eq!(lights.len(), 1);
+        let LightsConfig::Point(light) = &lights[0] else {
+            panic!("expected a point light");
+        };
+        assert!((light.position.y() - 3.0).abs() < 1e-5);
+        assert!((light.diffuse.g - 0.25).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,261 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::format::read_document;
+use super::lights::LightsConfig;
+use super::objects::ObjectConfig;
+use crate::math::{length, normalize, Mat4, Vec3};
+use crate::objects::transform::Transform;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IncludeConfig {
+    pub file: String, // relative to the including file
+    #[serde(default)]
+    pub transform: Option<Transform>, // applied to everything in the file
+}
+
+/// The parts of an included file that are used, so any scene file can be included
+#[derive(Deserialize)]
+struct IncludeFile {
+    #[serde(default)]
+    include: Vec<IncludeConfig>,
+    #[serde(default)]
+    objects: Vec<ObjectConfig>,
+    #[serde(default)]
+    lights: Vec<LightsConfig>,
+}
+
+/// The files being read, outermost first, to catch files that include themselves
+pub struct IncludeStack(Vec<PathBuf>);
+
+impl IncludeStack {
+    /// Start from the top-level file, which can't be included either
+    pub fn new(path: &Path) -> Self {
+        IncludeStack(path.canonicalize().into_iter().collect())
+    }
+
+    /// Enter an included file, failing if it is already being read
+    pub fn push(&mut self, path: &Path) -> io::Result<()> {
+        let canonical = path.canonicalize().map_err(|e| {
+            io::Error::new(e.kind(), format!("failed to open {}: {e}", path.display()))
+        })?;
+        if self.0.contains(&canonical) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} includes itself", path.display()),
+            ));
+        }
+        self.0.push(canonical);
+        Ok(())
+    }
+
+    /// Leave the innermost included file
+    pub fn pop(&mut self) {
+        self.0.pop();
+    }
+}
+
+/// The largest length a matrix scales an axis by, which a sphere's radius is scaled by, and
+/// whether every axis is scaled by it
+pub fn sphere_scale(m: &Mat4) -> (f32, bool) {
+    let scales = [
+        length(m.transform_vector(Vec3::new(1.0, 0.0, 0.0))),
+        length(m.transform_vector(Vec3::new(0.0, 1.0, 0.0))),
+        length(m.transform_vector(Vec3::new(0.0, 0.0, 1.0))),
+    ];
+    let scale = scales.iter().copied().fold(0.0_f32, f32::max);
+    let uniform = scales.iter().all(|s| (s - scale).abs() <= 1e-4 * scale);
+    (scale, uniform)
+}
+
+/// Move an object by a matrix, baking it into spheres and planes. Fails if the matrix is
+/// singular.
+pub fn place_object(obj: &mut ObjectConfig, m: &Mat4) -> io::Result<()> {
+    let Some(inverse) = m.inverse() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "transform is singular",
+        ));
+    };
+    match obj {
+        ObjectConfig::Sphere(s) => {
+            let (scale, uniform) = sphere_scale(m);
+            if !uniform {
+                log::warn!("non-uniform include scale on a sphere, using the largest axis");
+            }
+            s.center = m.transform_point(s.center);
+            s.radius *= scale;
+        }
+        ObjectConfig::Plane(p) => {
+            p.point = m.transform_point(p.point);
+            p.normal = normalize(inverse.transpose().transform_vector(p.normal));
+        }
+        ObjectConfig::Model(mc) => mc.transform.wrap(m),
+        ObjectConfig::TriangleMesh(tmc) => tmc.transform.wrap(m),
+        ObjectConfig::SuperQuadric(sqc) => sqc.transform.wrap(m),
+        ObjectConfig::BPatch(bpc) => bpc.transform.wrap(m),
+        ObjectConfig::Gltf(gc) => gc.transform.wrap(m),
+    }
+    Ok(())
+}
+
+/// Move a light by a matrix
+pub fn place_light(light: &mut LightsConfig, m: &Mat4) {
+    match light {
+        LightsConfig::Point(pl) => pl.position = m.transform_point(pl.position),
+        LightsConfig::Spot(sl) => {
+            sl.position = m.transform_point(sl.position);
+            sl.direction = normalize(m.transform_vector(sl.direction));
+        }
+        LightsConfig::Area(al) => al.transform.wrap(m),
+    }
+}
+
+/// Load the objects and lights of the included files, and of the files they include in turn.
+/// `stack` holds the files being included to catch cycles.
+fn load_includes(
+    includes: &[IncludeConfig],
+    dir: &Path,
+    outer: Option<&Mat4>,
+    stack: &mut IncludeStack,
+    objects: &mut Vec<ObjectConfig>,
+    lights: &mut Vec<LightsConfig>,
+) -> io::Result<()> {
+    for include in includes {
+        let path = dir.join(&include.file);
+        stack.push(&path)?;
+
+        log::info!("including scene file: {}", path.display());
+        let mut file: IncludeFile = read_document(&path)?;
+
+        let m = match (&include.transform, outer) {
+            (Some(t), Some(outer)) => Some(outer * &t.mat4()),
+            (Some(t), None) => Some(t.mat4()),
+            (None, outer) => outer.copied(),
+        };
+        if let Some(m) = &m {
+            for obj in &mut file.objects {
+                place_object(obj, m).map_err(|e| {
+                    io::Error::new(e.kind(), format!("{}: include {e}", path.display()))
+                })?;
+            }
+            file.lights
+                .iter_mut()
+                .for_each(|light| place_light(light, m));
+        }
+        objects.append(&mut file.objects);
+        lights.append(&mut file.lights);
+
+        let include_dir = path.parent().unwrap_or(Path::new(""));
+        load_includes(
+            &file.include,
+            include_dir,
+            m.as_ref(),
+            stack,
+            objects,
+            lights,
+        )?;
+        stack.pop();
+    }
+    Ok(())
+}
+
+/// Append the objects and lights of the scene's includes to its own, with each include's
+/// transform applied. Include paths are relative to the file that includes them.
+pub fn resolve_includes(
+    includes: &[IncludeConfig],
+    scene_path: &Path,
+    objects: &mut Vec<ObjectConfig>,
+    lights: &mut Vec<LightsConfig>,
+) -> io::Result<()> {
+    let mut stack = IncludeStack::new(scene_path);
+    let dir = scene_path.parent().unwrap_or(Path::new(""));
+    load_includes(includes, dir, None, &mut stack, objects, lights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::sphere::SphereConfig;
+    use std::fs;
+
+    #[test]
+    fn sphere_is_moved_and_scaled() {
+        let mut obj = ObjectConfig::Sphere(SphereConfig {
+            center: Vec3::new(1.0, 0.0, 0.0),
+            radius: 0.5,
+            material: String::new(),
+        });
+        let t = Transform {
+            translate: Vec3::new(0.0, 2.0, 0.0),
+            scale: Vec3::new(2.0, 2.0, 2.0),
+            ..Transform::default()
+        };
+        place_object(&mut obj, &t.mat4()).unwrap();
+
+        let ObjectConfig::Sphere(s) = obj else {
+            panic!("expected a sphere");
+        };
+        assert!((s.center.x() - 2.0).abs() < 1e-5);
+        assert!((s.center.y() - 2.0).abs() < 1e-5);
+        assert!((s.radius - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn zero_scale_include_is_an_error() {
+        let dir = std::env::temp_dir().join("arrt_include_zero_scale");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("floor.yaml"),
+            "objects:\n  - !Plane\n      point: [0, 0, 0]\n      normal: [0, 1, 0]\n      \
+             material: grey\n",
+        )
+        .unwrap();
+
+        let includes = vec![IncludeConfig {
+            file: "floor.yaml".to_string(),
+            transform: Some(Transform {
+                scale: Vec3::new(1.0, 0.0, 1.0),
+                ..Transform::default()
+            }),
+        }];
+        let (mut objects, mut lights) = (Vec::new(), Vec::new());
+        let err = resolve_includes(
+            &includes,
+            &dir.join("scene.yaml"),
+            &mut objects,
+            &mut lights,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let message = err.to_string();
+        assert!(message.contains("floor.yaml"), "{message}");
+        assert!(message.contains("singular"), "{message}");
+    }
+
+    #[test]
+    fn include_cycle_is_an_error() {
+        let dir = std::env::temp_dir().join("arrt_include_cycle");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.yaml"), "include:\n  - file: b.yaml\n").unwrap();
+        fs::write(dir.join("b.yaml"), "include:\n  - file: a.yaml\n").unwrap();
+
+        let includes = vec![IncludeConfig {
+            file: "a.yaml".to_string(),
+            transform: None,
+        }];
+        let (mut objects, mut lights) = (Vec::new(), Vec::new());
+        let err = resolve_includes(
+            &includes,
+            &dir.join("scene.yaml"),
+            &mut objects,
+            &mut lights,
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("includes itself"), "{err}");
+    }
+}
@@ -1,23 +1,37 @@
 pub mod camera;
 
+pub mod format;
+mod gltf;
+mod include;
 mod lights;
 mod objects;
+mod pbrt;
 
-use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
 use std::sync::Arc;
 
-use serde::{Deserialize, Serialize};
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::args::SceneFormat;
 use crate::lights::{AreaLight, Light, PointLight, SpotLight};
+use crate::objects::material::MaterialID;
+use crate::objects::mesh::MeshConfig;
 use crate::objects::{
-    bpatch, superquadric, Bvh, Instance, Material, MaterialMap, Mesh, Object, Plane, Sphere, Surfel,
+    bpatch, obj, ply, smf, stl, superquadric, Bvh, Instance, Labeled, Material, MaterialMap, Mesh,
+    Object, Plane, Sphere, Surfel,
 };
+use crate::render::color::WorkingSpace;
 use crate::render::texture::Texture;
-use crate::render::ColorRGB;
+use crate::render::{ColorRGB, OutputConfig};
 
 use camera::CameraConfig;
+use include::IncludeConfig;
 use lights::LightsConfig;
 use objects::ObjectConfig;
 
@@ -34,11 +48,54 @@ struct SceneConfig {
     patch_dir: String,
     #[serde(default = "ColorRGB::white")]
     ambient: ColorRGB,
-    camera: CameraConfig,
+    #[serde(default)]
+    working_space: WorkingSpace,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    camera: Option<CameraConfig>,
     #[serde(default)]
     objects: Vec<ObjectConfig>,
     #[serde(default)]
     lights: Vec<LightsConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    include: Vec<IncludeConfig>,
+    #[serde(default)]
+    materials: Vec<MaterialEntry>,
+    #[serde(default)]
+    output: OutputConfig,
+}
+
+/// An entry of the scene's `materials` list
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum MaterialEntry {
+    Library(String), // material file, relative to the scene file
+    Inline(Material),
+}
+
+/// Entries are told apart by hand rather than with `#[serde(untagged)]`, which buffers the
+/// input and can't represent YAML tags such as a material's `texture: !Image`.
+impl<'de> Deserialize<'de> for MaterialEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntryVisitor;
+
+        impl<'de> Visitor<'de> for EntryVisitor {
+            type Value = MaterialEntry;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a material file name or a material")
+            }
+
+            fn visit_str<E: de::Error>(self, file: &str) -> Result<MaterialEntry, E> {
+                Ok(MaterialEntry::Library(file.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<MaterialEntry, A::Error> {
+                Material::deserialize(MapAccessDeserializer::new(map)).map(MaterialEntry::Inline)
+            }
+        }
+
+        deserializer.deserialize_any(EntryVisitor)
+    }
 }
 
 pub struct Scene {
@@ -48,12 +105,24 @@ pub struct Scene {
 }
 
 impl Scene {
-    pub fn new(fpath: &PathBuf) -> Scene {
-        let mut mat_path = fpath.clone();
-        mat_path.set_file_name("materials.yaml");
-        let yaml = fs::read_to_string(fpath).unwrap();
-        let config: SceneConfig = serde_yml::from_str(&yaml).unwrap();
-        let materials_map = MaterialMap::new(&mat_path);
+    pub fn new(fpath: &Path) -> io::Result<Scene> {
+        let mut config = read_config(fpath)?;
+        let inline = std::mem::take(&mut config.materials);
+        let materials_map =
+            MaterialMap::from_materials(collect_materials(fpath, inline)?, config.working_space);
+        import_gltf_cameras_and_lights(&mut config)?;
+        let Some(camera) = &config.camera else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: scene has no camera and none was imported",
+                    fpath.display()
+                ),
+            ));
+        };
+        camera
+            .check()
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", fpath.display())))?;
         let mut lights: Vec<Arc<dyn Light>> = Vec::new();
 
         for light in &config.lights {
@@ -70,21 +139,23 @@ impl Scene {
             }
         }
 
-        Scene {
+        Ok(Scene {
             config,
             materials_map,
             lights,
-        }
+        })
     }
 
-    pub fn make_objects(&self) -> Vec<Arc<dyn Object>> {
+    pub fn make_objects(&mut self) -> io::Result<Vec<Arc<dyn Object>>> {
         let mut all_objs: Vec<Arc<dyn Object>> = Vec::new();
         let mut bounded_objs: Vec<Arc<dyn Object>> = Vec::new();
         let mesh_dir = &self.config.mesh_dir;
         let patch_dir = &self.config.patch_dir;
         let mut meshes = HashMap::new();
 
-        for obj in &self.config.objects {
+        for (index, obj) in self.config.objects.iter().enumerate() {
+            let first_bounded = bounded_objs.len();
+            let first_unbounded = all_objs.len();
             match obj {
                 ObjectConfig::Sphere(s) => {
                     bounded_objs.push(Arc::new(Sphere::new(
@@ -93,14 +164,32 @@ impl Scene {
                     )));
                 }
                 ObjectConfig::Model(m) => {
-                    let material_id = self.materials_map.get_material_id(&m.material);
-                    let mesh: &Arc<Mesh> = meshes
-                        .entry(m.mesh.clone())
-                        .or_insert_with(|| Arc::new(Mesh::fromSMF(&m.mesh, mesh_dir)));
+                    // the crease angle changes an STL model's normals, so models are shared
+                    // only between objects that agree on it
+                    let crease = m.crease_angle.map(|angle| angle.0.to_bits());
+                    let parts = match meshes.entry((m.mesh.clone(), crease)) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => {
+                            entry.insert(load_model(m, mesh_dir, &mut self.materials_map)?)
+                        }
+                    };
+                    for (mesh, mesh_material_id) in parts.iter() {
+                        let material_id = mesh_material_id
+                            .unwrap_or_else(|| self.materials_map.get_material_id(&m.material));
+                        bounded_objs.push(Arc::new(Instance::new(
+                            mesh.clone(),
+                            material_id,
+                            &m.transform,
+                        )));
+                    }
+                }
+                ObjectConfig::TriangleMesh(tmc) => {
+                    let material_id = self.materials_map.get_material_id(&tmc.material);
+                    let mesh = Mesh::from_config(tmc)?;
                     bounded_objs.push(Arc::new(Instance::new(
-                        mesh.clone(),
+                        Arc::new(mesh),
                         material_id,
-                        &m.transform,
+                        &tmc.transform,
                     )));
                 }
                 ObjectConfig::Plane(p) => {
@@ -119,18 +208,84 @@ impl Scene {
                     let bp = Arc::new(bpatch::tessellate_bpatch(patch_dir, bpc));
                     bounded_objs.push(Arc::new(Instance::new(bp, material_id, &bpc.transform)));
                 }
+                ObjectConfig::Gltf(gc) => {
+                    if !gc.material.is_empty()
+                        && self.materials_map.find_material_id(&gc.material).is_none()
+                    {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{}: unknown material {}", gc.file, gc.material),
+                        ));
+                    }
+                    let path = Path::new(mesh_dir).join(&gc.file);
+                    let instances = gltf::load_gltf(&path, gc, &mut self.materials_map)?;
+                    bounded_objs.extend(instances);
+                }
             }
+            let id = u32::try_from(index + 1).unwrap_or(u32::MAX);
+            label_objects(&mut bounded_objs[first_bounded..], id);
+            label_objects(&mut all_objs[first_unbounded..], id);
         }
 
         all_objs.push(Arc::new(Bvh::new(bounded_objs, 0)));
         log::info!("all objects {}", all_objs.len());
-        all_objs
+        Ok(all_objs)
+    }
+
+    /// Build the triangle mesh for an entry of the scene's object list, merging the parts of
+    /// multi-part models. The object's transform is applied if `apply_transform` is set.
+    pub fn object_mesh(&mut self, index: usize, apply_transform: bool) -> io::Result<Mesh> {
+        let not_a_mesh = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("object {index} is not a triangle mesh"),
+            )
+        };
+        let obj = self.config.objects.get(index).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "object {index} out of range, the scene has {} objects",
+                    self.config.objects.len()
+                ),
+            )
+        })?;
+
+        let (mut mesh, transform) = match obj {
+            ObjectConfig::Model(m) => {
+                let mesh = load_model(m, &self.config.mesh_dir, &mut self.materials_map)?
+                    .into_iter()
+                    .filter_map(|(part, _)| Arc::into_inner(part))
+                    .reduce(|mut mesh, part| {
+                        mesh.append(&part);
+                        mesh
+                    })
+                    .ok_or_else(not_a_mesh)?;
+                (mesh, m.transform)
+            }
+            ObjectConfig::TriangleMesh(tmc) => (Mesh::from_config(tmc)?, tmc.transform),
+            ObjectConfig::SuperQuadric(sqc) => {
+                (superquadric::tessellate_superquadric(sqc), sqc.transform)
+            }
+            ObjectConfig::BPatch(bpc) => (
+                bpatch::tessellate_bpatch(&self.config.patch_dir, bpc),
+                bpc.transform,
+            ),
+            ObjectConfig::Sphere(_) | ObjectConfig::Plane(_) | ObjectConfig::Gltf(_) => {
+                return Err(not_a_mesh());
+            }
+        };
+
+        if apply_transform {
+            mesh.transform(&transform);
+        }
+        Ok(mesh)
     }
 
     #[allow(clippy::cast_precision_loss)]
     pub fn make_camera(&self) -> Camera {
         Camera::new(
-            &self.config.camera,
+            self.config.camera.as_ref().unwrap(),
             self.width() as f32,
             self.height() as f32,
         )
@@ -144,6 +299,25 @@ impl Scene {
         self.config.height
     }
 
+    /// Override the resolution, keeping the aspect ratio when only one side is given, then
+    /// scale both sides. The camera's field of view stays horizontal.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn set_resolution(&mut self, width: Option<u32>, height: Option<u32>, scale: f32) {
+        let (w, h) = (self.config.width as f32, self.config.height as f32);
+        let (w, h) = match (width, height) {
+            (Some(width), Some(height)) => (width as f32, height as f32),
+            (Some(width), None) => (width as f32, width as f32 * h / w),
+            (None, Some(height)) => (height as f32 * w / h, height as f32),
+            (None, None) => (w, h),
+        };
+        self.config.width = ((w * scale).round() as u32).max(1);
+        self.config.height = ((h * scale).round() as u32).max(1);
+    }
+
     pub fn lights(&self) -> &Vec<Arc<dyn Light>> {
         &self.lights
     }
@@ -152,6 +326,13 @@ impl Scene {
         self.config.ambient
     }
 
+    pub fn output(&self) -> OutputConfig {
+        OutputConfig {
+            working_space: self.config.working_space,
+            ..self.config.output.clone()
+        }
+    }
+
     pub fn bgcolor(&self) -> ColorRGB {
         self.config.bgcolor
     }
@@ -164,3 +345,392 @@ impl Scene {
         self.materials_map.get_texture(surfel.material_id)
     }
 }
+
+/// Tag objects with their scene object id so it can be written out as an AOV
+fn label_objects(objects: &mut [Arc<dyn Object>], id: u32) {
+    for object in objects {
+        *object = Arc::new(Labeled::new(object.clone(), id));
+    }
+}
+
+fn read_config(fpath: &Path) -> io::Result<SceneConfig> {
+    let is_pbrt = fpath
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pbrt"));
+    if is_pbrt {
+        return pbrt::read_pbrt(fpath);
+    }
+
+    let mut config: SceneConfig = format::read_document(fpath)?;
+
+    let includes = std::mem::take(&mut config.include);
+    include::resolve_includes(&includes, fpath, &mut config.objects, &mut config.lights)?;
+    Ok(config)
+}
+
+/// Gather the scene's materials from its `materials` list in order, later definitions
+/// replacing earlier ones with the same name. A scene that lists no library starts from the
+/// `materials` file next to it, if there is one, preferring the scene's own format.
+fn collect_materials(fpath: &Path, entries: Vec<MaterialEntry>) -> io::Result<Vec<Material>> {
+    let mut materials: Vec<Material> = Vec::new();
+    let mut add = |mat: Material| match materials.iter_mut().find(|m| m.name == mat.name) {
+        Some(existing) => *existing = mat,
+        None => materials.push(mat),
+    };
+
+    let has_library = entries
+        .iter()
+        .any(|entry| matches!(entry, MaterialEntry::Library(_)));
+    if !has_library {
+        let scene_format = SceneFormat::from_path(fpath).unwrap_or_default();
+        let default_lib = [scene_format, SceneFormat::Yaml]
+            .iter()
+            .map(|f| fpath.with_file_name(format!("materials.{}", f.extension())))
+            .find(|path| path.exists());
+        if let Some(default_lib) = default_lib {
+            format::read_materials(&default_lib)?
+                .into_iter()
+                .for_each(&mut add);
+        }
+    }
+
+    let dir = fpath.parent().unwrap_or(Path::new(""));
+    for entry in entries {
+        match entry {
+            MaterialEntry::Library(file) => {
+                format::read_materials(&dir.join(file))?
+                    .into_iter()
+                    .for_each(&mut add);
+            }
+            MaterialEntry::Inline(mat) => add(mat),
+        }
+    }
+    Ok(materials)
+}
+
+/// Write a scene as a self-contained document: every field is written out, defaults
+/// included, includes are merged in and the materials its objects refer to are inlined,
+/// along with those OBJ models name with `usemtl`.
+/// The mesh and patch directories are made absolute, as the document may be written elsewhere.
+pub fn write_normalized_scene<W: Write>(
+    fpath: &Path,
+    scene_format: SceneFormat,
+    out: W,
+) -> io::Result<()> {
+    let mut config = read_config(fpath)?;
+    config.mesh_dir = absolute_dir(&config.mesh_dir)?;
+    config.patch_dir = absolute_dir(&config.patch_dir)?;
+    let mut used: HashSet<String> = HashSet::new();
+    for obj in &config.objects {
+        used.insert(obj.material().to_string());
+        if let ObjectConfig::Model(m) = obj {
+            let path = Path::new(&config.mesh_dir).join(&m.mesh);
+            let is_obj = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("obj"));
+            if is_obj {
+                used.extend(obj::usemtl_names(&path)?);
+            }
+        }
+    }
+    let mut materials = collect_materials(fpath, std::mem::take(&mut config.materials))?;
+    materials.retain(|m| used.contains(&m.name));
+    config.materials = materials.into_iter().map(MaterialEntry::Inline).collect();
+
+    format::write_document(&config, scene_format, out)
+}
+
+/// A directory relative to the working directory (empty for the working directory itself) as an
+/// absolute path
+fn absolute_dir(dir: &str) -> io::Result<String> {
+    let path = if dir.is_empty() {
+        std::env::current_dir()?
+    } else {
+        std::path::absolute(dir)?
+    };
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Load the meshes for a model config, choosing the loader by file extension.
+/// Meshes that carry their own material (e.g. OBJ `usemtl`) are returned with its id.
+fn load_model(
+    config: &MeshConfig,
+    mesh_dir: &String,
+    materials: &mut MaterialMap,
+) -> io::Result<Vec<(Arc<Mesh>, Option<MaterialID>)>> {
+    let path = Path::new(mesh_dir).join(&config.mesh);
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+
+    let mesh = match extension.as_deref() {
+        Some("obj") => {
+            return Ok(obj::load_obj(&path, materials)?
+                .into_iter()
+                .map(|(mesh, material_id)| (Arc::new(mesh), material_id))
+                .collect());
+        }
+        Some("ply") => ply::load_ply(&path)?,
+        Some("stl") => stl::load_stl(&path, config.crease_angle)?,
+        _ => smf::load_smf(&path)?,
+    };
+    Ok(vec![(Arc::new(mesh), None)])
+}
+
+/// Fill in the camera (if the scene has none) and lights from glTF objects that ask for them
+#[allow(clippy::cast_precision_loss)]
+fn import_gltf_cameras_and_lights(config: &mut SceneConfig) -> io::Result<()> {
+    let aspect = config.width as f32 / config.height as f32;
+    for obj in &config.objects {
+        let ObjectConfig::Gltf(gc) = obj else {
+            continue;
+        };
+        if !gc.camera && !gc.lights {
+            continue;
+        }
+
+        let path = Path::new(&config.mesh_dir).join(&gc.file);
+        let document = gltf::open_document(&path)?;
+        if gc.camera && config.camera.is_none() {
+            config.camera = gltf::find_camera(&document, gc, aspect);
+        }
+        if gc.lights {
+            config.lights.extend(gltf::find_lights(&document, gc));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{normalize, Range, Ray, Vec3};
+
+    #[test]
+    fn resolution_overrides_keep_aspect() {
+        let mut scene = Scene::new(Path::new("scenes/smoke_test.yaml")).unwrap();
+        scene.set_resolution(Some(32), None, 1.0);
+        assert_eq!((scene.width(), scene.height()), (32, 32));
+        scene.set_resolution(Some(100), Some(50), 1.0);
+        assert_eq!((scene.width(), scene.height()), (100, 50));
+        scene.set_resolution(None, Some(10), 1.0);
+        assert_eq!((scene.width(), scene.height()), (20, 10));
+        scene.set_resolution(None, None, 0.25);
+        assert_eq!((scene.width(), scene.height()), (5, 3));
+        scene.set_resolution(None, None, 0.01);
+        assert_eq!((scene.width(), scene.height()), (1, 1));
+    }
+
+    #[test]
+    fn normalized_scene_inlines_used_materials() {
+        let path = Path::new("scenes/smoke_test.yaml");
+        let mut out = Vec::new();
+        write_normalized_scene(path, SceneFormat::Yaml, &mut out).unwrap();
+
+        let config: SceneConfig = serde_yml::from_slice(&out).unwrap();
+        assert_eq!(config.objects.len(), 1);
+        assert_eq!(config.materials.len(), 1);
+        let MaterialEntry::Inline(ruby) = &config.materials[0] else {
+            panic!("expected an inline material");
+        };
+        assert_eq!(ruby.name, "ruby");
+        assert!(config.camera.is_some());
+        assert!(Path::new(&config.mesh_dir).is_absolute());
+        assert!(Path::new(&config.patch_dir).is_absolute());
+    }
+
+    #[test]
+    fn inline_material_replaces_library_entry() {
+        let path = Path::new("scenes/smoke_test.yaml");
+        let inline = Material {
+            name: "ruby".to_string(),
+            kd: 0.5,
+            ..Default::default()
+        };
+        let materials = collect_materials(path, vec![MaterialEntry::Inline(inline)]).unwrap();
+        let ruby: Vec<&Material> = materials.iter().filter(|m| m.name == "ruby").collect();
+        assert_eq!(ruby.len(), 1);
+        assert!((ruby[0].kd - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn material_entries_keep_yaml_tags() {
+        let yaml = "- lib.yaml\n- name: earth\n  texture: !Image\n    file: earth.png\n";
+        let entries: Vec<MaterialEntry> = serde_yml::from_str(yaml).unwrap();
+        assert!(matches!(&entries[0], MaterialEntry::Library(file) if file == "lib.yaml"));
+        let MaterialEntry::Inline(earth) = &entries[1] else {
+            panic!("expected an inline material");
+        };
+        assert!(earth.texture.is_some());
+    }
+
+    #[test]
+    fn scene_formats_read_back() {
+        let path = Path::new("scenes/smoke_test.yaml");
+        for scene_format in [SceneFormat::Json, SceneFormat::Toml] {
+            let mut out = Vec::new();
+            write_normalized_scene(path, scene_format, &mut out).unwrap();
+
+            let copy =
+                std::env::temp_dir().join(format!("arrt_scene.{}", scene_format.extension()));
+            std::fs::write(&copy, out).unwrap();
+            let config = read_config(&copy).unwrap();
+            assert!(matches!(config.objects[0], ObjectConfig::Sphere(_)));
+            assert!(matches!(config.lights[0], LightsConfig::Point(_)));
+            let materials = collect_materials(&copy, config.materials).unwrap();
+            assert_eq!(materials[0].name, "ruby");
+        }
+    }
+
+    #[test]
+    fn normalized_scene_keeps_usemtl_materials() {
+        let dir = std::env::temp_dir().join("arrt_normalize_usemtl");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("tri.obj"),
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n",
+        )
+        .unwrap();
+        let scene = format!(
+            "bgcolor: {{r: 0, g: 0, b: 0}}\nwidth: 8\nheight: 8\nmesh_dir: {}\n\
+             camera:\n  eye: [0, 0, 5]\n  up: [0, 1, 0]\n  look_at: [0, 0, 0]\n\
+             materials:\n  - name: red\n  - name: blue\n  - name: unused\n\
+             objects:\n  - !Model\n      mesh: tri.obj\n      material: blue\n",
+            dir.display(),
+        );
+        let path = dir.join("scene.yaml");
+        std::fs::write(&path, scene).unwrap();
+
+        let mut out = Vec::new();
+        write_normalized_scene(&path, SceneFormat::Yaml, &mut out).unwrap();
+        let copy = dir.join("normalized.yaml");
+        std::fs::write(&copy, out).unwrap();
+
+        let mut scene = Scene::new(&copy).unwrap();
+        assert!(scene.materials_map.find_material_id("unused").is_none());
+        scene.make_objects().unwrap();
+    }
+
+    #[test]
+    fn truncated_model_is_an_error() {
+        let dir = std::env::temp_dir().join("arrt_truncated_model");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("cut.obj"), "v 0 0 0\nv 1 0 0\nv 0 1\n").unwrap();
+        let scene = format!(
+            "bgcolor: {{r: 0, g: 0, b: 0}}\nwidth: 8\nheight: 8\nmesh_dir: {}\n\
+             camera:\n  eye: [0, 0, 5]\n  up: [0, 1, 0]\n  look_at: [0, 0, 0]\n\
+             materials:\n  - name: grey\n\
+             objects:\n  - !Model\n      mesh: cut.obj\n      material: grey\n",
+            dir.display(),
+        );
+        let path = dir.join("scene.yaml");
+        std::fs::write(&path, scene).unwrap();
+
+        let mut scene = Scene::new(&path).unwrap();
+        let err = scene.make_objects().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("cut.obj:3"), "{err}");
+    }
+
+    #[test]
+    fn unknown_gltf_material_is_an_error() {
+        let path = std::env::temp_dir().join("arrt_gltf_material.yaml");
+        std::fs::write(
+            &path,
+            "bgcolor: {r: 0, g: 0, b: 0}\nwidth: 8\nheight: 8\n\
+             camera:\n  eye: [0, 0, 5]\n  up: [0, 1, 0]\n  look_at: [0, 0, 0]\n\
+             objects:\n  - !Gltf\n      file: box.glb\n      material: missing\n",
+        )
+        .unwrap();
+
+        let mut scene = Scene::new(&path).unwrap();
+        let err = scene.make_objects().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            err.to_string().contains("unknown material missing"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn invalid_camera_is_an_error() {
+        let path = std::env::temp_dir().join("arrt_invalid_camera.yaml");
+        std::fs::write(
+            &path,
+            "bgcolor: {r: 0, g: 0, b: 0}\nwidth: 8\nheight: 8\n\
+             camera:\n  eye: [0, 0, 5]\n  up: [0, 1, 0]\n  look_at: [0, 0, 0]\n  blades: 2\n",
+        )
+        .unwrap();
+
+        let err = Scene::new(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let message = err.to_string();
+        assert!(message.contains("arrt_invalid_camera.yaml"), "{message}");
+        assert!(message.contains("blades"), "{message}");
+    }
+
+    #[test]
+    fn models_with_different_crease_angles_are_loaded_separately() {
+        let dir = std::env::temp_dir().join("arrt_crease_cache");
+        std::fs::create_dir_all(&dir).unwrap();
+        let quads = [
+            ["0 0 0", "0 1 0", "1 1 0", "1 0 0"],
+            ["0 0 1", "1 0 1", "1 1 1", "0 1 1"],
+            ["0 0 0", "1 0 0", "1 0 1", "0 0 1"],
+            ["0 1 0", "0 1 1", "1 1 1", "1 1 0"],
+            ["0 0 0", "0 0 1", "0 1 1", "0 1 0"],
+            ["1 0 0", "1 1 0", "1 1 1", "1 0 1"],
+        ];
+        let mut stl = String::from("solid cube\n");
+        for q in quads {
+            for [a, b, c] in [[q[0], q[1], q[2]], [q[0], q[2], q[3]]] {
+                stl += &format!(
+                    "facet normal 0 0 0\nouter loop\nvertex {a}\nvertex {b}\nvertex {c}\n\
+                     endloop\nendfacet\n"
+                );
+            }
+        }
+        std::fs::write(dir.join("cube.stl"), stl + "endsolid cube\n").unwrap();
+
+        let model = |x: f32, angle: f32| {
+            format!(
+                "  - !Model\n      mesh: cube.stl\n      material: grey\n      \
+                 crease_angle: {angle}\n      transform:\n        translate: [{x}, 0, 0]\n"
+            )
+        };
+        let scene = format!(
+            "bgcolor: {{r: 0, g: 0, b: 0}}\nwidth: 8\nheight: 8\nmesh_dir: {}\n\
+             camera:\n  eye: [0, 0, 5]\n  up: [0, 1, 0]\n  look_at: [0, 0, 0]\n\
+             materials:\n  - name: grey\nobjects:\n{}{}",
+            dir.display(),
+            model(-2.0, 30.0),
+            model(2.0, 120.0),
+        );
+        let path = dir.join("scene.yaml");
+        std::fs::write(&path, scene).unwrap();
+
+        let mut scene = Scene::new(&path).unwrap();
+        let objects = scene.make_objects().unwrap();
+        // look down at the top face of each cube near a corner, slightly slanted as instances
+        // work out the distance to the hit along x
+        let normal_at = |x: f32| {
+            let ray = Ray {
+                origin: Vec3::new(x - 0.5, 6.0, 0.05),
+                direction: normalize(Vec3::new(0.1, -1.0, 0.0)),
+                depth: 0,
+            };
+            let range = Range {
+                min: 0.0,
+                max: f32::MAX,
+            };
+            let surfel = objects
+                .iter()
+                .find_map(|obj| obj.intersect(&ray, range))
+                .unwrap();
+            surfel.normal
+        };
+        // creased at 30 degrees the top face stays flat, at 120 degrees it is smoothed
+        assert!((normal_at(-1.95).y().abs() - 1.0).abs() < 1e-5);
+        assert!(normal_at(2.05).y().abs() < 0.99);
+    }
+}
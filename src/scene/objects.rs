@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+use super::gltf::GltfConfig;
 use crate::objects::bpatch::BPatchConfig;
-use crate::objects::mesh::MeshConfig;
+use crate::objects::mesh::{MeshConfig, TriangleMeshConfig};
 use crate::objects::plane::PlaneConfig;
 use crate::objects::sphere::SphereConfig;
 use crate::objects::superquadric::SuperQuadricConfig;
@@ -10,7 +11,24 @@ use crate::objects::superquadric::SuperQuadricConfig;
 pub enum ObjectConfig {
     Sphere(SphereConfig),
     Model(MeshConfig),
+    TriangleMesh(TriangleMeshConfig),
     Plane(PlaneConfig),
     SuperQuadric(SuperQuadricConfig),
     BPatch(BPatchConfig),
+    Gltf(GltfConfig),
+}
+
+impl ObjectConfig {
+    /// Name of the material the object refers to, empty if it has none
+    pub fn material(&self) -> &str {
+        match self {
+            Self::Sphere(s) => &s.material,
+            Self::Model(m) => &m.material,
+            Self::TriangleMesh(tm) => &tm.material,
+            Self::Plane(p) => &p.material,
+            Self::SuperQuadric(sqc) => &sqc.material,
+            Self::BPatch(bpc) => &bpc.material,
+            Self::Gltf(gc) => &gc.material,
+        }
+    }
 }
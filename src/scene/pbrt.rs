@@ -0,0 +1,1217 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use super::camera::{CameraConfig, Projection};
+use super::include::{place_light, place_object, sphere_scale, IncludeStack};
+use super::lights::LightsConfig;
+use super::objects::ObjectConfig;
+use super::{MaterialEntry, SceneConfig};
+use crate::lights::{AreaLightConfig, PointLight, SpotLight};
+use crate::math::{cross, dot, normalize, Degree, Mat4, Vec3};
+use crate::objects::mesh::{parse_error, MeshConfig, TriangleMeshConfig};
+use crate::objects::sphere::SphereConfig;
+use crate::objects::transform::Transform;
+use crate::objects::Material;
+use crate::render::color::WorkingSpace;
+use crate::render::texture::TextureConfig;
+use crate::render::{ColorRGB, OutputConfig};
+
+const DEFAULT_MATERIAL: &str = "pbrt_default";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String), // keywords, numbers and bools
+    Str(String),
+    Open,
+    Close,
+}
+
+/// A pbrt statement: the keyword and every token up to the next keyword
+struct Statement {
+    keyword: String,
+    args: Vec<Token>,
+    path: Rc<Path>,
+    line: usize,
+}
+
+impl Statement {
+    fn error(&self, msg: &str) -> io::Error {
+        parse_error(&self.path, self.line, msg)
+    }
+
+    /// All arguments as numbers, brackets allowed, e.g. `LookAt` or `Transform [ ... ]`
+    fn numbers(&self, count: usize) -> io::Result<Vec<f32>> {
+        let mut values = Vec::with_capacity(count);
+        for token in &self.args {
+            match token {
+                Token::Open | Token::Close => {}
+                Token::Word(word) => values.push(
+                    word.parse::<f32>()
+                        .map_err(|e| self.error(&format!("invalid number '{word}': {e}")))?,
+                ),
+                Token::Str(s) => {
+                    return Err(self.error(&format!("expected a number, found \"{s}\"")))
+                }
+            }
+        }
+        if values.len() != count {
+            return Err(self.error(&format!(
+                "{} needs {count} numbers, found {}",
+                self.keyword,
+                values.len()
+            )));
+        }
+        Ok(values)
+    }
+
+    /// Split the arguments into `leading` quoted strings and a parameter list
+    fn split(&self, leading: usize) -> io::Result<(Vec<String>, Params)> {
+        let mut tokens = self.args.iter();
+        let mut names = Vec::with_capacity(leading);
+        for _ in 0..leading {
+            match tokens.next() {
+                Some(Token::Str(s)) => names.push(s.clone()),
+                _ => {
+                    return Err(self.error(&format!(
+                        "{} needs {leading} quoted arguments",
+                        self.keyword
+                    )))
+                }
+            }
+        }
+
+        let mut params = Vec::new();
+        while let Some(token) = tokens.next() {
+            let Token::Str(decl) = token else {
+                return Err(self.error(&format!("expected a parameter, found {token:?}")));
+            };
+            let mut parts = decl.split_whitespace();
+            let (Some(ty), Some(name), None) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(self.error(&format!("invalid parameter \"{decl}\"")));
+            };
+
+            let mut raw = Vec::new();
+            match tokens.next() {
+                Some(Token::Open) => loop {
+                    match tokens.next() {
+                        Some(Token::Close) => break,
+                        Some(Token::Open) | None => {
+                            return Err(self.error(&format!("unterminated values for \"{decl}\"")))
+                        }
+                        Some(value) => raw.push(value),
+                    }
+                },
+                Some(value @ (Token::Word(_) | Token::Str(_))) => raw.push(value),
+                _ => return Err(self.error(&format!("missing value for \"{decl}\""))),
+            }
+
+            let values = raw
+                .into_iter()
+                .map(|value| match value {
+                    Token::Word(w) if w == "true" => Ok(Value::Bool(true)),
+                    Token::Word(w) if w == "false" => Ok(Value::Bool(false)),
+                    Token::Word(w) => w
+                        .parse::<f32>()
+                        .map(Value::Number)
+                        .map_err(|e| self.error(&format!("invalid number '{w}': {e}"))),
+                    Token::Str(s) if ty == "bool" => Ok(Value::Bool(s == "true")),
+                    Token::Str(s) => Ok(Value::Text(s.clone())),
+                    Token::Open | Token::Close => unreachable!(),
+                })
+                .collect::<io::Result<Vec<Value>>>()?;
+            params.push(Param {
+                ty: ty.to_string(),
+                name: name.to_string(),
+                values,
+            });
+        }
+        Ok((names, Params(params)))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(f32),
+    Text(String),
+    Bool(bool),
+}
+
+struct Param {
+    ty: String,
+    name: String,
+    values: Vec<Value>,
+}
+
+struct Params(Vec<Param>);
+
+impl Params {
+    fn find(&self, name: &str) -> Option<&Param> {
+        self.0.iter().find(|p| p.name == name)
+    }
+
+    fn numbers(&self, name: &str) -> Option<Vec<f32>> {
+        self.find(name).map(|p| {
+            p.values
+                .iter()
+                .filter_map(|v| match v {
+                    Value::Number(n) => Some(*n),
+                    _ => None,
+                })
+                .collect()
+        })
+    }
+
+    fn float(&self, name: &str, default: f32) -> f32 {
+        self.numbers(name)
+            .and_then(|n| n.first().copied())
+            .unwrap_or(default)
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        self.find(name)
+            .and_then(|p| p.values.first())
+            .and_then(|v| match v {
+                Value::Text(s) => Some(s.as_str()),
+                _ => None,
+            })
+    }
+
+    fn point(&self, name: &str) -> Option<Vec3> {
+        self.numbers(name)
+            .filter(|n| n.len() == 3)
+            .map(|n| Vec3::new(n[0], n[1], n[2]))
+    }
+
+    /// A colour given as `rgb`, or as a `spectrum` or `blackbody` that can only be approximated
+    fn color(&self, name: &str) -> Option<ColorRGB> {
+        let param = self.find(name).filter(|p| p.ty != "texture")?;
+        let numbers = self.numbers(name).unwrap_or_default();
+        match (param.ty.as_str(), numbers.as_slice()) {
+            ("rgb", [r, g, b]) => Some(ColorRGB::new(*r, *g, *b)),
+            ("spectrum", [v]) => Some(ColorRGB::fill(*v)),
+            _ => {
+                log::warn!("approximating {} \"{name}\" with white", param.ty);
+                Some(ColorRGB::white())
+            }
+        }
+    }
+
+    /// Name of a texture bound to the parameter with `"texture name" "..."`
+    fn texture(&self, name: &str) -> Option<&str> {
+        self.find(name)
+            .filter(|p| p.ty == "texture")
+            .and_then(|_| self.string(name))
+    }
+}
+
+/// Split pbrt source into tokens, each with its line number
+fn tokenize(text: &str, path: &Path) -> io::Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '#' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '[' => tokens.push((Token::Open, line)),
+            ']' => tokens.push((Token::Close, line)),
+            '"' => {
+                let start = line;
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\n') | None => {
+                            return Err(parse_error(path, start, "unterminated string"))
+                        }
+                        Some(c) => s.push(c),
+                    }
+                }
+                tokens.push((Token::Str(s), start));
+            }
+            c => {
+                let mut word = String::from(c);
+                while let Some(c) =
+                    chars.next_if(|&c| !c.is_whitespace() && !matches!(c, '[' | ']' | '"' | '#'))
+                {
+                    word.push(c);
+                }
+                tokens.push((Token::Word(word), line));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Group the tokens of a file into statements, following `Include` and `Import`.
+/// `stack` holds the files being read to catch cycles.
+fn read_statements(
+    text: &str,
+    path: &Path,
+    stack: &mut IncludeStack,
+    out: &mut Vec<Statement>,
+) -> io::Result<()> {
+    let shared: Rc<Path> = Rc::from(path);
+    for (token, line) in tokenize(text, path)? {
+        match token {
+            Token::Word(word) if word.starts_with(|c: char| c.is_ascii_uppercase()) => {
+                out.push(Statement {
+                    keyword: word,
+                    args: Vec::new(),
+                    path: shared.clone(),
+                    line,
+                });
+            }
+            token => match out.last_mut() {
+                Some(stmt) if Rc::ptr_eq(&stmt.path, &shared) => stmt.args.push(token),
+                _ => return Err(parse_error(path, line, &format!("unexpected {token:?}"))),
+            },
+        }
+
+        // an include is complete as soon as it has its file name
+        let include = out.last().filter(|stmt| {
+            matches!(stmt.keyword.as_str(), "Include" | "Import")
+                && Rc::ptr_eq(&stmt.path, &shared)
+                && !stmt.args.is_empty()
+        });
+        if let Some(stmt) = include {
+            let Token::Str(file) = &stmt.args[0] else {
+                return Err(stmt.error("Include needs a quoted file name"));
+            };
+            let include_path = path.parent().unwrap_or(Path::new("")).join(file);
+            out.pop();
+            stack.push(&include_path)?;
+            let text = read_file(&include_path)?;
+            read_statements(&text, &include_path, stack, out)?;
+            stack.pop();
+        }
+    }
+    Ok(())
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to open {}: {e}", path.display())))
+}
+
+fn determinant(m: &Mat4) -> f32 {
+    let x = m.transform_vector(Vec3::new(1.0, 0.0, 0.0));
+    let y = m.transform_vector(Vec3::new(0.0, 1.0, 0.0));
+    let z = m.transform_vector(Vec3::new(0.0, 0.0, 1.0));
+    dot(x, cross(y, z))
+}
+
+/// Rotation by `angle` degrees about an arbitrary axis
+fn rotate(angle: f32, axis: Vec3) -> Mat4 {
+    let a = normalize(axis);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let mut m = Mat4::identity();
+    m[0][0] = a.x() * a.x() + (1.0 - a.x() * a.x()) * cos;
+    m[0][1] = a.x() * a.y() * (1.0 - cos) - a.z() * sin;
+    m[0][2] = a.x() * a.z() * (1.0 - cos) + a.y() * sin;
+    m[1][0] = a.x() * a.y() * (1.0 - cos) + a.z() * sin;
+    m[1][1] = a.y() * a.y() + (1.0 - a.y() * a.y()) * cos;
+    m[1][2] = a.y() * a.z() * (1.0 - cos) - a.x() * sin;
+    m[2][0] = a.x() * a.z() * (1.0 - cos) - a.y() * sin;
+    m[2][1] = a.y() * a.z() * (1.0 - cos) + a.x() * sin;
+    m[2][2] = a.z() * a.z() + (1.0 - a.z() * a.z()) * cos;
+    m
+}
+
+/// pbrt's `LookAt`, which maps world space to camera space
+fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Option<Mat4> {
+    let dir = normalize(target - eye);
+    let right = normalize(cross(normalize(up), dir));
+    let new_up = cross(dir, right);
+    let col = |v: Vec3, w: f32| [v.x(), v.y(), v.z(), w];
+    Mat4::from_cols([
+        col(right, 0.0),
+        col(new_up, 0.0),
+        col(dir, 0.0),
+        col(eye, 1.0),
+    ])
+    .inverse()
+}
+
+/// Light colours are physical intensities in pbrt; only their hue and `scale` are kept, with
+/// the brightest channel limited to 1
+fn light_color(params: &Params, name: &str) -> ColorRGB {
+    let c = params.color(name).unwrap_or_else(ColorRGB::white) * params.float("scale", 1.0);
+    let max = c.r.max(c.g).max(c.b);
+    if max > 1.0 {
+        c * (1.0 / max)
+    } else {
+        c
+    }
+}
+
+/// Phong exponent for a microfacet roughness, remapped the way pbrt does by default
+fn shininess(params: &Params) -> f32 {
+    let roughness = params.float("roughness", params.float("uroughness", 0.0));
+    let alpha = if params
+        .find("remaproughness")
+        .is_some_and(|p| matches!(p.values.first(), Some(Value::Bool(false))))
+    {
+        roughness
+    } else {
+        roughness.sqrt()
+    };
+    let a = alpha.max(1e-3);
+    (2.0 / (a * a) - 2.0).clamp(1.0, 1000.0)
+}
+
+/// Reflectance of pbrt's named metal spectra, e.g. `"spectrum eta" "metal-Au-eta"`
+fn metal_color(params: &Params) -> ColorRGB {
+    match params.string("eta") {
+        Some(s) if s.contains("-Au-") => ColorRGB::new(1.0, 0.78, 0.34),
+        Some(s) if s.contains("-Ag-") => ColorRGB::new(0.97, 0.96, 0.91),
+        Some(s) if s.contains("-Al-") => ColorRGB::new(0.91, 0.92, 0.92),
+        // copper is pbrt's default conductor
+        _ => ColorRGB::new(0.95, 0.64, 0.54),
+    }
+}
+
+#[derive(Clone)]
+struct GraphicsState {
+    ctm: Mat4,
+    material: String,
+    area_light: Option<ColorRGB>,
+}
+
+struct Importer {
+    dir: PathBuf,
+    state: GraphicsState,
+    stack: Vec<GraphicsState>,
+    coordinate_systems: HashMap<String, Mat4>,
+    camera: Option<(Mat4, Projection, Params)>,
+    width: u32,
+    height: u32,
+    background: ColorRGB,
+    textures: HashMap<String, TextureConfig>,
+    materials: Vec<Material>,
+    objects: Vec<(ObjectConfig, Mat4)>,
+    lights: Vec<(LightsConfig, Mat4)>,
+    in_object: bool,
+    skipped: Vec<String>,
+}
+
+impl Importer {
+    fn new(dir: PathBuf) -> Self {
+        let default = Material {
+            name: DEFAULT_MATERIAL.to_string(),
+            two_sided: true,
+            ..diffuse(ColorRGB::fill(0.5))
+        };
+        Importer {
+            dir,
+            state: GraphicsState {
+                ctm: Mat4::identity(),
+                material: DEFAULT_MATERIAL.to_string(),
+                area_light: None,
+            },
+            stack: Vec::new(),
+            coordinate_systems: HashMap::new(),
+            camera: None,
+            width: 1280,
+            height: 720,
+            background: ColorRGB::black(),
+            textures: HashMap::new(),
+            materials: vec![default],
+            objects: Vec::new(),
+            lights: Vec::new(),
+            in_object: false,
+            skipped: Vec::new(),
+        }
+    }
+
+    fn concat(&mut self, m: &Mat4) {
+        self.state.ctm = &self.state.ctm * m;
+    }
+
+    fn statement(&mut self, stmt: &Statement) -> io::Result<()> {
+        match stmt.keyword.as_str() {
+            "Identity" => self.state.ctm = Mat4::identity(),
+            "Translate" => {
+                let v = stmt.numbers(3)?;
+                self.concat(&Mat4::translate(&Vec3::new(v[0], v[1], v[2])));
+            }
+            "Scale" => {
+                let v = stmt.numbers(3)?;
+                self.concat(&Mat4::scale(&Vec3::new(v[0], v[1], v[2])));
+            }
+            "Rotate" => {
+                let v = stmt.numbers(4)?;
+                self.concat(&rotate(v[0], Vec3::new(v[1], v[2], v[3])));
+            }
+            "LookAt" => {
+                let v = stmt.numbers(9)?;
+                let m = look_at(
+                    Vec3::new(v[0], v[1], v[2]),
+                    Vec3::new(v[3], v[4], v[5]),
+                    Vec3::new(v[6], v[7], v[8]),
+                )
+                .ok_or_else(|| stmt.error("degenerate LookAt"))?;
+                self.concat(&m);
+            }
+            "Transform" | "ConcatTransform" => {
+                let v = stmt.numbers(16)?;
+                // pbrt matrices are written column by column
+                let col = |j: usize| [v[4 * j], v[4 * j + 1], v[4 * j + 2], v[4 * j + 3]];
+                let m = Mat4::from_cols([col(0), col(1), col(2), col(3)]);
+                if stmt.keyword == "Transform" {
+                    self.state.ctm = m;
+                } else {
+                    self.concat(&m);
+                }
+            }
+            "CoordinateSystem" | "CoordSysTransform" => {
+                let (names, _) = stmt.split(1)?;
+                if stmt.keyword == "CoordinateSystem" {
+                    self.coordinate_systems
+                        .insert(names[0].clone(), self.state.ctm);
+                } else {
+                    self.state.ctm = *self.coordinate_systems.get(&names[0]).ok_or_else(|| {
+                        stmt.error(&format!("unknown coordinate system {}", names[0]))
+                    })?;
+                }
+            }
+            "Camera" => {
+                let (names, params) = stmt.split(1)?;
+                let projection = match names[0].as_str() {
+                    "orthographic" => Projection::Orthographic,
+                    "perspective" => Projection::Perspective,
+                    name => {
+                        log::warn!(
+                            "{}:{}: treating {name} camera as perspective",
+                            stmt.path.display(),
+                            stmt.line,
+                        );
+                        Projection::Perspective
+                    }
+                };
+                self.coordinate_systems.insert(
+                    "camera".to_string(),
+                    self.state.ctm.inverse().unwrap_or_else(Mat4::identity),
+                );
+                self.camera = Some((self.state.ctm, projection, params));
+            }
+            "Film" => {
+                let (_, params) = stmt.split(1)?;
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let resolution = |name: &str, default: u32| {
+                    params
+                        .numbers(name)
+                        .and_then(|n| n.first().map(|&v| v as u32))
+                        .unwrap_or(default)
+                };
+                self.width = resolution("xresolution", self.width);
+                self.height = resolution("yresolution", self.height);
+            }
+            "WorldBegin" => {
+                self.state.ctm = Mat4::identity();
+                self.coordinate_systems
+                    .insert("world".to_string(), Mat4::identity());
+            }
+            "AttributeBegin" | "TransformBegin" => self.stack.push(self.state.clone()),
+            "AttributeEnd" | "TransformEnd" => {
+                let state = self
+                    .stack
+                    .pop()
+                    .ok_or_else(|| stmt.error(&format!("unmatched {}", stmt.keyword)))?;
+                if stmt.keyword == "TransformEnd" {
+                    self.state.ctm = state.ctm;
+                } else {
+                    self.state = state;
+                }
+            }
+            "Texture" => {
+                let (names, params) = stmt.split(3)?;
+                self.texture(stmt, &names, &params);
+            }
+            "Material" => {
+                let (names, params) = stmt.split(1)?;
+                let name = format!("pbrt_material_{}", self.materials.len());
+                self.add_material(stmt, &name, &names[0], &params);
+                self.state.material = name;
+            }
+            "MakeNamedMaterial" => {
+                let (names, params) = stmt.split(1)?;
+                let ty = params
+                    .string("type")
+                    .ok_or_else(|| stmt.error("named material has no type"))?
+                    .to_string();
+                self.add_material(stmt, &names[0], &ty, &params);
+            }
+            "NamedMaterial" => {
+                let (names, _) = stmt.split(1)?;
+                if !self.materials.iter().any(|m| m.name == names[0]) {
+                    return Err(stmt.error(&format!("unknown material {}", names[0])));
+                }
+                self.state.material = names[0].clone();
+            }
+            "LightSource" => {
+                let (names, params) = stmt.split(1)?;
+                self.light(stmt, &names[0], &params);
+            }
+            "AreaLightSource" => {
+                let (_, params) = stmt.split(1)?;
+                self.state.area_light = Some(light_color(&params, "L"));
+            }
+            "Shape" => {
+                let (names, params) = stmt.split(1)?;
+                if self.in_object {
+                    return Ok(());
+                }
+                self.shape(stmt, &names[0], &params)?;
+            }
+            "ObjectBegin" => {
+                log::warn!(
+                    "{}:{}: object instancing is not supported, skipping its shapes",
+                    stmt.path.display(),
+                    stmt.line
+                );
+                self.stack.push(self.state.clone());
+                self.in_object = true;
+            }
+            "ObjectInstance" => {
+                log::warn!(
+                    "{}:{}: object instancing is not supported, skipping the instanced shapes",
+                    stmt.path.display(),
+                    stmt.line
+                );
+            }
+            "ObjectEnd" => {
+                self.state = self
+                    .stack
+                    .pop()
+                    .ok_or_else(|| stmt.error("unmatched ObjectEnd"))?;
+                self.in_object = false;
+            }
+            // settings that have no equivalent here
+            "Sampler" | "Integrator" | "PixelFilter" | "ColorSpace" | "Option" | "Accelerator"
+            | "WorldEnd" | "Attribute" | "ReverseOrientation" | "MakeNamedMedium"
+            | "MediumInterface" => {
+                log::debug!("ignoring pbrt {}", stmt.keyword);
+            }
+            keyword => {
+                if !self.skipped.iter().any(|k| k == keyword) {
+                    log::warn!(
+                        "{}:{}: skipping unsupported statement {keyword}",
+                        stmt.path.display(),
+                        stmt.line
+                    );
+                    self.skipped.push(keyword.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn texture(&mut self, stmt: &Statement, names: &[String], params: &Params) {
+        let (name, ty, class) = (&names[0], &names[1], &names[2]);
+        if ty == "float" {
+            log::debug!("ignoring float texture {name}");
+            return;
+        }
+        let texture = match class.as_str() {
+            "imagemap" => params.string("filename").map(|file| TextureConfig::Image {
+                file: self.dir.join(file).to_string_lossy().into_owned(),
+                linear: params.string("encoding") == Some("linear"),
+            }),
+            "checkerboard" => Some(TextureConfig::Checker {
+                even: params.color("tex1").unwrap_or_else(ColorRGB::white),
+                odd: params.color("tex2").unwrap_or_else(ColorRGB::black),
+                scale: params.float("uscale", 1.0),
+            }),
+            _ => None,
+        };
+        match texture {
+            Some(texture) => {
+                self.textures.insert(name.clone(), texture);
+            }
+            None => log::warn!(
+                "{}:{}: skipping {class} texture {name}",
+                stmt.path.display(),
+                stmt.line
+            ),
+        }
+    }
+
+    fn add_material(&mut self, stmt: &Statement, name: &str, ty: &str, params: &Params) {
+        let reflectance = params.color("reflectance");
+        let mut mat = match ty {
+            "diffuse" => diffuse(reflectance.unwrap_or_else(|| ColorRGB::fill(0.5))),
+            "coateddiffuse" => Material {
+                specular: ColorRGB::white(),
+                ks: 0.5,
+                shininess: shininess(params),
+                ..diffuse(reflectance.unwrap_or_else(|| ColorRGB::fill(0.5)))
+            },
+            "conductor" => {
+                let color = reflectance.unwrap_or_else(|| metal_color(params));
+                let roughness = params.float("roughness", params.float("uroughness", 0.0));
+                Material {
+                    ambient: color,
+                    diffuse: ColorRGB::black(),
+                    specular: color,
+                    ka: 0.1,
+                    ks: 1.0,
+                    kr: (1.0 - roughness).clamp(0.0, 1.0),
+                    shininess: shininess(params),
+                    ..Material::default()
+                }
+            }
+            "dielectric" | "thindielectric" => {
+                let eta = if ty == "thindielectric" {
+                    1.0
+                } else {
+                    params.float("eta", 1.5)
+                };
+                Material {
+                    specular: ColorRGB::white(),
+                    transmissive: ColorRGB::white(),
+                    ka: 0.0,
+                    kd: 0.0,
+                    ks: 1.0,
+                    kr: 0.1,
+                    kt: 0.9,
+                    ior: eta,
+                    shininess: 1000.0,
+                    highlight: 100.0,
+                    ..Material::default()
+                }
+            }
+            _ => {
+                log::warn!(
+                    "{}:{}: approximating {ty} material with a diffuse one",
+                    stmt.path.display(),
+                    stmt.line
+                );
+                diffuse(ColorRGB::fill(0.5))
+            }
+        };
+
+        if let Some(texture) = params.texture("reflectance") {
+            match self.textures.get(texture) {
+                Some(texture) => {
+                    mat.texture = Some(texture.clone());
+                    mat.diffuse = ColorRGB::white();
+                    mat.ambient = ColorRGB::white();
+                }
+                None => log::warn!(
+                    "{}:{}: unknown texture {texture}",
+                    stmt.path.display(),
+                    stmt.line
+                ),
+            }
+        }
+
+        // pbrt shades both sides of surfaces, which open meshes rely on
+        mat.two_sided = mat.kt == 0.0;
+        mat.name = name.to_string();
+        self.materials.retain(|m| m.name != name);
+        self.materials.push(mat);
+    }
+
+    fn light(&mut self, stmt: &Statement, ty: &str, params: &Params) {
+        let from = params.point("from").unwrap_or_else(Vec3::zeros);
+        let to = params
+            .point("to")
+            .unwrap_or_else(|| Vec3::new(0.0, 0.0, 1.0));
+        let point_light = |position: Vec3, color: ColorRGB| {
+            LightsConfig::Point(PointLight {
+                position,
+                ambient: ColorRGB::black(),
+                diffuse: color,
+                specular: color,
+            })
+        };
+
+        let light = match ty {
+            "point" => point_light(from, light_color(params, "I")),
+            "spot" => {
+                let cone = params.float("coneangle", 30.0);
+                let delta = params.float("conedeltaangle", 5.0);
+                LightsConfig::Spot(SpotLight {
+                    color: light_color(params, "I"),
+                    position: from,
+                    direction: normalize(to - from),
+                    angle: Degree(cone),
+                    sharpness: 2.0 * (delta / cone).clamp(0.0, 1.0),
+                })
+            }
+            // point lights don't fall off, so a far away one stands in for a distant light
+            "distant" => point_light(
+                from + 1.0e4 * normalize(from - to),
+                light_color(params, "L"),
+            ),
+            "infinite" => {
+                if params.string("filename").is_some() {
+                    log::warn!(
+                        "{}:{}: environment maps are not supported",
+                        stmt.path.display(),
+                        stmt.line
+                    );
+                }
+                self.background = light_color(params, "L");
+                return;
+            }
+            _ => {
+                log::warn!("{}:{}: skipping {ty} light", stmt.path.display(), stmt.line);
+                return;
+            }
+        };
+        self.lights.push((light, self.state.ctm));
+    }
+
+    fn shape(&mut self, stmt: &Statement, ty: &str, params: &Params) -> io::Result<()> {
+        // meshes keep the exact matrix, but spheres can only be moved and evenly scaled
+        if self.state.ctm.inverse().is_none() {
+            return Err(stmt.error(&format!("{ty} transform is singular")));
+        }
+        let material = self.state.material.clone();
+        let obj = match ty {
+            "sphere" => {
+                if !sphere_scale(&self.state.ctm).1 {
+                    return Err(stmt.error("sphere with a non-uniform scale is not supported"));
+                }
+                ObjectConfig::Sphere(SphereConfig {
+                    center: Vec3::zeros(),
+                    radius: params.float("radius", 1.0),
+                    material,
+                })
+            }
+            "trianglemesh" | "bilinearmesh" => {
+                let mesh = triangle_mesh(stmt, ty, params, material)?;
+                ObjectConfig::TriangleMesh(mesh)
+            }
+            "plymesh" => {
+                let file = params
+                    .string("filename")
+                    .ok_or_else(|| stmt.error("plymesh has no filename"))?;
+                ObjectConfig::Model(MeshConfig {
+                    mesh: self.dir.join(file).to_string_lossy().into_owned(),
+                    material,
+                    transform: Transform::default(),
+                    crease_angle: None,
+                })
+            }
+            _ => {
+                log::warn!("{}:{}: skipping {ty} shape", stmt.path.display(), stmt.line);
+                return Ok(());
+            }
+        };
+
+        if let Some(color) = self.state.area_light {
+            let area = area_light(&obj, color);
+            self.lights.push((area, self.state.ctm));
+        }
+        self.objects.push((obj, self.state.ctm));
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<SceneConfig> {
+        let (camera_ctm, projection, params) = self.camera.unwrap_or_else(|| {
+            (
+                Mat4::identity(),
+                Projection::Perspective,
+                Params(Vec::new()),
+            )
+        });
+        let camera_to_world = camera_ctm.inverse().unwrap_or_else(Mat4::identity);
+
+        // pbrt's camera space is left handed, so a scene seen through a proper rotation comes
+        // out mirrored in our right handed camera unless the world is flipped
+        let world = if determinant(&camera_to_world) > 0.0 {
+            Mat4::scale(&Vec3::new(-1.0, 1.0, 1.0))
+        } else {
+            Mat4::identity()
+        };
+        let to_scene = &world * &camera_to_world;
+
+        // pbrt's fov is for the shorter image side, ours is horizontal
+        #[allow(clippy::cast_precision_loss)]
+        let aspect = self.width as f32 / self.height as f32;
+        let fov = params.float("fov", 90.0).to_radians();
+        let hfov = if aspect > 1.0 {
+            2.0 * ((fov / 2.0).tan() * aspect).atan()
+        } else {
+            fov
+        };
+        let eye = to_scene.transform_point(Vec3::zeros());
+        let mut camera = CameraConfig::new(
+            eye,
+            normalize(to_scene.transform_vector(Vec3::new(0.0, 1.0, 0.0))),
+            eye + normalize(to_scene.transform_vector(Vec3::new(0.0, 0.0, 1.0))),
+            1.0,
+            Degree(hfov.to_degrees()),
+        );
+        if projection == Projection::Orthographic {
+            // the screen window spans -1..1 on the shorter image side
+            camera.projection = projection;
+            camera.view_width = Some(2.0 * aspect.max(1.0));
+        }
+        let lens_radius = params.float("lensradius", 0.0);
+        if lens_radius > 0.0 {
+            camera.aperture = Some(2.0 * lens_radius);
+            camera.focus_distance = Some(params.float("focaldistance", 1e6));
+        }
+
+        let objects = self
+            .objects
+            .into_iter()
+            .map(|(mut obj, m)| {
+                place_object(&mut obj, &(&world * &m))?;
+                Ok(obj)
+            })
+            .collect::<io::Result<_>>()?;
+        let lights = self
+            .lights
+            .into_iter()
+            .map(|(mut light, m)| {
+                place_light(&mut light, &(&world * &m));
+                light
+            })
+            .collect();
+
+        Ok(SceneConfig {
+            bgcolor: self.background,
+            width: self.width,
+            height: self.height,
+            mesh_dir: String::new(),
+            patch_dir: String::new(),
+            ambient: ColorRGB::white(),
+            working_space: WorkingSpace::LinearSrgb,
+            camera: Some(camera),
+            objects,
+            lights,
+            include: Vec::new(),
+            materials: self
+                .materials
+                .into_iter()
+                .map(MaterialEntry::Inline)
+                .collect(),
+            output: OutputConfig::default(),
+        })
+    }
+}
+
+fn diffuse(color: ColorRGB) -> Material {
+    Material {
+        ambient: color,
+        diffuse: color,
+        ka: 0.1,
+        kd: 1.0,
+        ks: 0.0,
+        ..Material::default()
+    }
+}
+
+fn triangle_mesh(
+    stmt: &Statement,
+    ty: &str,
+    params: &Params,
+    material: String,
+) -> io::Result<TriangleMeshConfig> {
+    let triples = |name: &str| -> io::Result<Option<Vec<Vec3>>> {
+        let Some(n) = params.numbers(name) else {
+            return Ok(None);
+        };
+        if !n.len().is_multiple_of(3) {
+            return Err(stmt.error(&format!("\"{name}\" needs a multiple of 3 values")));
+        }
+        Ok(Some(
+            n.chunks(3).map(|c| Vec3::new(c[0], c[1], c[2])).collect(),
+        ))
+    };
+
+    let vertices = triples("P")?.ok_or_else(|| stmt.error(&format!("{ty} has no \"P\"")))?;
+    let count = vertices.len();
+    let corners = if ty == "bilinearmesh" { 4 } else { 3 };
+    let indices: Vec<usize> = match params.numbers("indices") {
+        Some(n) => n
+            .iter()
+            .map(|&i| {
+                if i < 0.0 || i.fract() != 0.0 {
+                    return Err(stmt.error(&format!("invalid index {i}")));
+                }
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                Ok(i as usize)
+            })
+            .collect::<io::Result<_>>()?,
+        None if count == corners => (0..corners).collect(),
+        None => return Err(stmt.error(&format!("{ty} has no \"indices\""))),
+    };
+    if !indices.len().is_multiple_of(corners) {
+        return Err(stmt.error(&format!("\"indices\" needs a multiple of {corners} values")));
+    }
+    if let Some(idx) = indices.iter().find(|&&i| i >= count) {
+        return Err(stmt.error(&format!("index {idx} out of range for {count} vertices")));
+    }
+
+    let triangles = if corners == 4 {
+        // bilinear patches are given as p00 p10 p01 p11
+        indices
+            .chunks(4)
+            .flat_map(|q| [[q[0], q[1], q[3]], [q[0], q[3], q[2]]])
+            .collect()
+    } else {
+        indices.chunks(3).map(|t| [t[0], t[1], t[2]]).collect()
+    };
+
+    let normals = triples("N")?;
+    if normals.as_ref().is_some_and(|n| n.len() != count) {
+        return Err(stmt.error(&format!("\"N\" needs {count} normals")));
+    }
+    let uvs = match params.numbers("uv").or_else(|| params.numbers("st")) {
+        Some(uv) if uv.len() == 2 * count => Some(uv.chunks(2).map(|c| (c[0], c[1])).collect()),
+        Some(_) => return Err(stmt.error(&format!("\"uv\" needs {count} pairs"))),
+        None => None,
+    };
+
+    Ok(TriangleMeshConfig {
+        vertices,
+        triangles,
+        normals,
+        uvs,
+        material,
+        transform: Transform::default(),
+    })
+}
+
+/// An area light covering an emissive shape: a rectangle across the two widest sides of its
+/// bounding box in object space
+fn area_light(obj: &ObjectConfig, color: ColorRGB) -> LightsConfig {
+    let (min, max) = match obj {
+        ObjectConfig::Sphere(s) => (Vec3::fill(-s.radius), Vec3::fill(s.radius)),
+        ObjectConfig::TriangleMesh(tm) => tm.vertices.iter().fold(
+            (Vec3::fill(f32::MAX), Vec3::fill(f32::MIN)),
+            |(lo, hi), v| {
+                (
+                    Vec3::new(lo.x().min(v.x()), lo.y().min(v.y()), lo.z().min(v.z())),
+                    Vec3::new(hi.x().max(v.x()), hi.y().max(v.y()), hi.z().max(v.z())),
+                )
+            },
+        ),
+        _ => (Vec3::fill(-0.5), Vec3::fill(0.5)),
+    };
+    let size = max - min;
+    let center = 0.5 * (min + max);
+
+    // the unit light is in the XZ plane, so y takes the thinnest axis
+    let mut axes = [0, 1, 2];
+    axes.sort_by(|&a, &b| size[b].total_cmp(&size[a]));
+    let axis = |i: usize, len: f32| {
+        let mut v = [0.0, 0.0, 0.0, 0.0];
+        v[axes[i]] = len;
+        v
+    };
+    let local = Mat4::from_cols([
+        axis(0, size[axes[0]]),
+        axis(2, 1.0),
+        axis(1, size[axes[1]]),
+        [center.x(), center.y(), center.z(), 1.0],
+    ]);
+
+    LightsConfig::Area(AreaLightConfig {
+        transform: Transform::from_mat4(&local),
+        samples: 25,
+        color,
+    })
+}
+
+fn parse_pbrt(text: &str, path: &Path) -> io::Result<SceneConfig> {
+    let mut statements = Vec::new();
+    read_statements(text, path, &mut IncludeStack::new(path), &mut statements)?;
+
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let mut importer = Importer::new(dir);
+    for stmt in &statements {
+        importer.statement(stmt)?;
+    }
+    if importer.camera.is_none() {
+        log::warn!("{} has no Camera, using pbrt's default", path.display());
+    }
+    if importer.objects.is_empty() {
+        log::warn!("{} has no supported shapes", path.display());
+    }
+    importer
+        .finish()
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+}
+
+/// Convert a pbrt-v4 scene into a scene config. Covers perspective and orthographic cameras,
+/// transforms, sphere/triangle/bilinear/PLY shapes, diffuse, conductor and dielectric materials,
+/// image and checkerboard textures, and point, spot, distant and area lights.
+pub fn read_pbrt(path: &Path) -> io::Result<SceneConfig> {
+    log::info!("importing pbrt scene: {}", path.display());
+    let text = read_file(path)?;
+    parse_pbrt(&text, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> io::Result<SceneConfig> {
+        parse_pbrt(src, Path::new("test.pbrt"))
+    }
+
+    const CAMERA: &str = "LookAt 0 0 5  0 0 0  0 1 0\n\
+                          Camera \"perspective\" \"float fov\" [ 45 ]\n\
+                          Film \"rgb\" \"integer xresolution\" [ 200 ] \"integer yresolution\" 100\n";
+
+    #[test]
+    fn camera_and_sphere() {
+        let src = format!(
+            "{CAMERA}WorldBegin\n\
+             # a red sphere to the right in pbrt's image\n\
+             AttributeBegin\n\
+               Material \"diffuse\" \"rgb reflectance\" [ 0.8 0.1 0.1 ]\n\
+               Translate -1 0 0\n\
+               Shape \"sphere\" \"float radius\" 0.5\n\
+             AttributeEnd\n"
+        );
+        let config = parse(&src).unwrap();
+        assert_eq!((config.width, config.height), (200, 100));
+
+        let camera = config.camera.unwrap();
+        assert!((camera.eye.z() - 5.0).abs() < 1e-4);
+        assert!((camera.look_at.z() - 4.0).abs() < 1e-4);
+        // the vertical 45 degrees become a horizontal fov for the 2:1 image
+        let expected = 2.0 * ((22.5_f32).to_radians().tan() * 2.0).atan();
+        assert!((camera.fov.0 - expected.to_degrees()).abs() < 1e-3);
+
+        let ObjectConfig::Sphere(s) = &config.objects[0] else {
+            panic!("expected a sphere");
+        };
+        // mirrored so the sphere stays on the right in our right handed camera
+        assert!((s.center.x() - 1.0).abs() < 1e-4);
+        assert!((s.radius - 0.5).abs() < 1e-5);
+
+        let MaterialEntry::Inline(mat) = config
+            .materials
+            .iter()
+            .find(|m| matches!(m, MaterialEntry::Inline(m) if m.name == s.material))
+            .unwrap()
+        else {
+            unreachable!();
+        };
+        assert!((mat.diffuse.r - 0.8).abs() < 1e-5);
+    }
+
+    #[test]
+    fn orthographic_camera_spans_screen_window() {
+        let src = "LookAt 0 0 5  0 0 0  0 1 0\n\
+                   Camera \"orthographic\" \"float lensradius\" 0.1\n\
+                   Film \"rgb\" \"integer xresolution\" [ 200 ] \"integer yresolution\" 100\n\
+                   WorldBegin\n";
+        let camera = parse(src).unwrap().camera.unwrap();
+        assert_eq!(camera.projection, Projection::Orthographic);
+        assert_eq!(camera.view_width, Some(4.0));
+        assert_eq!(camera.aperture, Some(0.2));
+    }
+
+    #[test]
+    fn left_handed_camera_is_not_mirrored() {
+        let src = format!("Scale -1 1 1\n{CAMERA}WorldBegin\nTranslate 1 0 0\nShape \"sphere\"\n");
+        let config = parse(&src).unwrap();
+        let ObjectConfig::Sphere(s) = &config.objects[0] else {
+            panic!("expected a sphere");
+        };
+        assert!((s.center.x() - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn emissive_quad_becomes_area_light() {
+        let src = format!(
+            "{CAMERA}WorldBegin\n\
+             MakeNamedMaterial \"glass\" \"string type\" \"dielectric\" \"float eta\" 1.33\n\
+             NamedMaterial \"glass\"\n\
+             AttributeBegin\n\
+               AreaLightSource \"diffuse\" \"rgb L\" [ 8 8 4 ]\n\
+               Shape \"bilinearmesh\" \"point3 P\" [ -1 2 -1  1 2 -1  -1 2 1  1 2 1 ]\n\
+             AttributeEnd\n"
+        );
+        let config = parse(&src).unwrap();
+        let ObjectConfig::TriangleMesh(tm) = &config.objects[0] else {
+            panic!("expected a triangle mesh");
+        };
+        assert_eq!(tm.triangles.len(), 2);
+        assert_eq!(tm.material, "glass");
+
+        let LightsConfig::Area(area) = &config.lights[0] else {
+            panic!("expected an area light");
+        };
+        assert!((area.color.r - 1.0).abs() < 1e-5);
+        assert!((area.color.b - 0.5).abs() < 1e-5);
+        let center = area.transform.mat4().transform_point(Vec3::zeros());
+        assert!((center.y() - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn errors_report_line() {
+        let err = parse("LookAt 0 0 5 0 0 0 0 1 0\nTranslate 1 x 0\n")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("test.pbrt:2"), "{err}");
+
+        let src = format!("{CAMERA}WorldBegin\nShape \"trianglemesh\" \"point3 P\" [ 0 0 0 1 0 0 0 1 0 ] \"integer indices\" [ 0 1 3 ]\n");
+        let err = parse(&src).err().unwrap();
+        assert!(err.to_string().contains("test.pbrt:5"), "{err}");
+    }
+
+    #[test]
+    fn invalid_indices_are_errors() {
+        for indices in ["0 1 -1", "0 1 2.5"] {
+            let src = format!(
+                "{CAMERA}WorldBegin\nShape \"trianglemesh\" \"point3 P\" [ 0 0 0 1 0 0 0 1 0 ] \
+                 \"integer indices\" [ {indices} ]\n"
+            );
+            let err = parse(&src).err().unwrap();
+            let message = err.to_string();
+            assert!(message.contains("test.pbrt:5"), "{message}");
+            assert!(message.contains("invalid index"), "{message}");
+        }
+    }
+
+    #[test]
+    fn sheared_shapes_keep_their_matrix() {
+        let src = format!(
+            "{CAMERA}WorldBegin\nScale 2 1 1\nRotate 45 0 0 1\n\
+             Shape \"trianglemesh\" \"point3 P\" [ 0 0 0 1 0 0 0 1 0 ]\n"
+        );
+        let config = parse(&src).unwrap();
+        let ObjectConfig::TriangleMesh(mesh) = &config.objects[0] else {
+            panic!("expected a triangle mesh");
+        };
+        // the world is mirrored in x for our right handed camera
+        let expected = &Mat4::scale(&Vec3::new(-2.0, 1.0, 1.0)) * &Mat4::rotate_z(Degree(45.0));
+        let p = Vec3::new(1.0, 0.0, 0.0);
+        let result = mesh.transform.mat4().transform_point(p);
+        let want = expected.transform_point(p);
+        for i in 0..3 {
+            assert!((result[i] - want[i]).abs() < 1e-4, "{result:?} != {want:?}");
+        }
+
+        let src = format!("{CAMERA}WorldBegin\nScale 2 1 1\nShape \"sphere\"\n");
+        let err = parse(&src).err().unwrap();
+        assert!(err.to_string().contains("test.pbrt:6"), "{err}");
+    }
+
+    #[test]
+    fn include_cycle_is_an_error() {
+        let dir = std::env::temp_dir().join("arrt_pbrt_include_cycle");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.pbrt"), format!("{CAMERA}Include \"b.pbrt\"\n")).unwrap();
+        fs::write(dir.join("b.pbrt"), "WorldBegin\nInclude \"a.pbrt\"\n").unwrap();
+
+        let err = read_pbrt(&dir.join("a.pbrt")).err().unwrap();
+        assert!(err.to_string().contains("includes itself"), "{err}");
+    }
+}
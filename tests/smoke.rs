@@ -1,15 +1,15 @@
 use std::path::PathBuf;
 
-use arrt::args::CliArgs;
+use arrt::args::{AlphaMode, CliArgs};
 use arrt::render::render_with_args;
 
 fn smoke_framebuffer() -> arrt::render::Framebuffer {
     let args = CliArgs {
         scene: PathBuf::from("scenes/smoke_test.yaml"),
-        image: None,
         sampling_depth: 0,
+        ..CliArgs::default()
     };
-    render_with_args(&args)
+    render_with_args(&args).unwrap()
 }
 
 #[test]
@@ -41,3 +41,95 @@ fn center_hits_sphere() {
         c.b
     );
 }
+
+#[test]
+fn misses_are_transparent_with_alpha() {
+    let args = CliArgs {
+        scene: PathBuf::from("scenes/smoke_test.yaml"),
+        sampling_depth: 0,
+        alpha: Some(AlphaMode::Coverage),
+        ..CliArgs::default()
+    };
+    let fb = render_with_args(&args).unwrap();
+    let c = fb.get_color(1, 1);
+    assert!(fb.get_alpha(1, 1) < 1e-5, "expected transparent corner");
+    assert!(c.b < 1e-5, "expected black behind alpha, got b={}", c.b);
+    assert!((fb.get_alpha(32, 32) - 1.0).abs() < 1e-5);
+}
+
+#[test]
+fn crop_matches_full_frame() {
+    let full = smoke_framebuffer();
+    let args = CliArgs {
+        scene: PathBuf::from("scenes/smoke_test.yaml"),
+        sampling_depth: 0,
+        crop: Some("16,20,40,44".parse().unwrap()),
+        ..CliArgs::default()
+    };
+    let crop = render_with_args(&args).unwrap();
+    assert_eq!((crop.width, crop.height), (24, 24));
+    for (x, y) in [(0, 0), (12, 12), (23, 5)] {
+        let a = crop.get_color(x, y);
+        let b = full.get_color(x + 16, y + 20);
+        assert!((a.r - b.r).abs() < 1e-5 && (a.b - b.b).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn crop_outside_image_is_an_error() {
+    let args = CliArgs {
+        scene: PathBuf::from("scenes/smoke_test.yaml"),
+        sampling_depth: 0,
+        crop: Some("70,70,80,80".parse().unwrap()),
+        ..CliArgs::default()
+    };
+    assert!(render_with_args(&args).is_err());
+}
+
+#[test]
+fn wide_render_covers_every_pixel() {
+    let args = CliArgs {
+        scene: PathBuf::from("scenes/smoke_test.yaml"),
+        sampling_depth: 1,
+        width: Some(96),
+        height: Some(32),
+        ..CliArgs::default()
+    };
+    let fb = render_with_args(&args).unwrap();
+    assert_eq!((fb.width, fb.height), (96, 32));
+    let c = fb.get_color(48, 16);
+    assert!(
+        c.r > c.b,
+        "expected sphere at center, got r={} b={}",
+        c.r,
+        c.b
+    );
+    for (x, y) in [(0, 0), (95, 0), (0, 31), (95, 31)] {
+        let c = fb.get_color(x, y);
+        assert!(
+            c.b > 0.9 && c.r < 0.1,
+            "expected background at {x},{y}, got b={}",
+            c.b
+        );
+    }
+}
+
+#[test]
+fn progressive_render_converges_on_the_scene() {
+    let args = CliArgs {
+        scene: PathBuf::from("scenes/smoke_test.yaml"),
+        progressive: Some(4),
+        ..CliArgs::default()
+    };
+    let fb = render_with_args(&args).unwrap();
+    assert_eq!((fb.width, fb.height), (64, 64));
+    let center = fb.get_color(32, 32);
+    assert!(center.r > center.b, "expected sphere at center");
+    let corner = fb.get_color(1, 1);
+    assert!(
+        corner.b > 0.9 && corner.r < 0.1,
+        "expected background at corner"
+    );
+    let metadata = fb.metadata.unwrap();
+    assert_eq!(metadata.passes, 4);
+}